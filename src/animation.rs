@@ -0,0 +1,578 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    prelude::{
+        Color, Commands, Component, DespawnRecursiveExt, Entity, Or, Query, ReflectComponent,
+        ReflectDefault, Res, Time, With, Without,
+    },
+    reflect::{FromReflect, Reflect},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::PointLight2d;
+
+/// Noise-based flicker of [`PointLight2d::intensity`], e.g. for torches and candles.
+///
+/// The noise only depends on `seed` and the accumulated frame deltas, so two runs fed the same
+/// deltas produce the same flicker.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dFlicker {
+    pub seed: u32,
+    /// Noise samples per second.
+    pub frequency: f32,
+    /// Maximum intensity reduction, in `0.0..=1.0`.
+    pub amount: f32,
+    pub elapsed: f32,
+}
+
+impl Light2dFlicker {
+    pub fn new(seed: u32, frequency: f32, amount: f32) -> Self {
+        Self {
+            seed,
+            frequency,
+            amount,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Default for Light2dFlicker {
+    fn default() -> Self {
+        Self::new(0, 8.0, 0.3)
+    }
+}
+
+/// Sine pulse of [`PointLight2d::intensity`].
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dPulse {
+    /// Pulses per second.
+    pub frequency: f32,
+    /// Maximum intensity reduction, in `0.0..=1.0`.
+    pub amount: f32,
+    /// Phase offset, in cycles.
+    pub phase: f32,
+    pub elapsed: f32,
+}
+
+impl Light2dPulse {
+    pub fn new(frequency: f32, amount: f32) -> Self {
+        Self {
+            frequency,
+            amount,
+            phase: 0.0,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Default for Light2dPulse {
+    fn default() -> Self {
+        Self::new(1.0, 0.5)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Reflect, FromReflect)]
+pub struct Light2dColorKey {
+    pub time: f32,
+    pub color: Color,
+}
+
+/// Color gradient over time. The key colors replace the rgb of the light's tint and their alpha
+/// scales its intensity.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dColorGradient {
+    /// Keys sorted by `time`, in seconds.
+    pub keys: Vec<Light2dColorKey>,
+    pub repeat: bool,
+    pub elapsed: f32,
+}
+
+impl Light2dColorGradient {
+    pub fn new(keys: Vec<Light2dColorKey>, repeat: bool) -> Self {
+        Self {
+            keys,
+            repeat,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn sample(&self, time: f32) -> Color {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::WHITE,
+        };
+        let time = wrap_time(time, last.time, self.repeat);
+        if time <= first.time {
+            return first.color;
+        }
+        for window in self.keys.windows(2) {
+            let (a, b) = (&window[0], &window[1]);
+            if time <= b.time {
+                let t = inverse_lerp(a.time, b.time, time);
+                let a = a.color.as_rgba_f32();
+                let b = b.color.as_rgba_f32();
+                return Color::rgba(
+                    lerp(a[0], b[0], t),
+                    lerp(a[1], b[1], t),
+                    lerp(a[2], b[2], t),
+                    lerp(a[3], b[3], t),
+                );
+            }
+        }
+        last.color
+    }
+}
+
+impl Default for Light2dColorGradient {
+    fn default() -> Self {
+        Self::new(Vec::new(), true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Reflect, FromReflect)]
+pub struct Light2dCurveKey {
+    pub time: f32,
+    pub value: f32,
+}
+
+/// One-shot intensity curve, e.g. a muzzle flash. The last key is held once the curve ends.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dCurve {
+    /// Keys sorted by `time`, in seconds.
+    pub keys: Vec<Light2dCurveKey>,
+    /// Despawn the entity once the curve has played.
+    pub despawn_on_finish: bool,
+    pub elapsed: f32,
+}
+
+impl Light2dCurve {
+    pub fn new(keys: Vec<Light2dCurveKey>) -> Self {
+        Self {
+            keys,
+            despawn_on_finish: false,
+            elapsed: 0.0,
+        }
+    }
+
+    /// A short full-brightness flash that decays to nothing and despawns its light.
+    pub fn muzzle_flash(duration: f32) -> Self {
+        Self {
+            keys: vec![
+                Light2dCurveKey {
+                    time: 0.0,
+                    value: 1.0,
+                },
+                Light2dCurveKey {
+                    time: duration * 0.2,
+                    value: 0.6,
+                },
+                Light2dCurveKey {
+                    time: duration,
+                    value: 0.0,
+                },
+            ],
+            despawn_on_finish: true,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration()
+    }
+
+    pub fn sample(&self, time: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 1.0,
+        };
+        if time <= first.time {
+            return first.value;
+        }
+        for window in self.keys.windows(2) {
+            let (a, b) = (&window[0], &window[1]);
+            if time <= b.time {
+                return lerp(a.value, b.value, inverse_lerp(a.time, b.time, time));
+            }
+        }
+        last.value
+    }
+}
+
+impl Default for Light2dCurve {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// Fades a light in from black, e.g. right after it is spawned.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dFadeIn {
+    pub duration: f32,
+    pub elapsed: f32,
+}
+
+impl Light2dFadeIn {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Default for Light2dFadeIn {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+/// Fades a light out and then despawns it. Insert it instead of despawning the light directly.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dFadeOut {
+    pub duration: f32,
+    pub elapsed: f32,
+}
+
+impl Light2dFadeOut {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Default for Light2dFadeOut {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+/// The light color and intensity the animation components modulate. Inserted automatically
+/// from the [`PointLight2d`] the first time an animated light is seen. Setting
+/// [`PointLight2d::color`] or [`PointLight2d::intensity`] of an animated light replaces them too.
+///
+/// Animations scale the intensity, the color's alpha, how strongly the light covers what is
+/// below it, is left alone.
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dAnimationBase {
+    pub color: Color,
+    pub intensity: f32,
+    /// The color and intensity last written by [`animate_lights`], anything else was set from
+    /// outside.
+    #[reflect(ignore)]
+    applied: Option<(Color, f32)>,
+}
+
+impl Default for Light2dAnimationBase {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 1.0,
+            applied: None,
+        }
+    }
+}
+
+type AnimatedLight2d = Or<(
+    With<Light2dFlicker>,
+    With<Light2dPulse>,
+    With<Light2dColorGradient>,
+    With<Light2dCurve>,
+    With<Light2dFadeIn>,
+    With<Light2dFadeOut>,
+)>;
+
+pub fn init_light_animations(
+    mut commands: Commands,
+    query: Query<(Entity, &PointLight2d), (AnimatedLight2d, Without<Light2dAnimationBase>)>,
+) {
+    for (entity, light) in &query {
+        commands.entity(entity).insert(Light2dAnimationBase {
+            color: light.color,
+            intensity: light.intensity,
+            applied: None,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn animate_lights(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(
+        Entity,
+        &mut PointLight2d,
+        &mut Light2dAnimationBase,
+        Option<&mut Light2dFlicker>,
+        Option<&mut Light2dPulse>,
+        Option<&mut Light2dColorGradient>,
+        Option<&mut Light2dCurve>,
+        Option<&mut Light2dFadeIn>,
+        Option<&mut Light2dFadeOut>,
+    )>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut light, mut base, flicker, pulse, gradient, curve, fade_in, fade_out) in
+        &mut query
+    {
+        if let Some((color, intensity)) = base.applied {
+            if color != light.color {
+                base.color = light.color;
+            }
+            if intensity != light.intensity {
+                base.intensity = light.intensity;
+            }
+        }
+        let mut color = base.color;
+        let mut brightness = 1.0;
+        // A curve and a fade out can both end on the same frame.
+        let mut despawn = false;
+
+        if let Some(mut flicker) = flicker {
+            flicker.elapsed += delta;
+            let noise = value_noise(flicker.seed, flicker.elapsed * flicker.frequency);
+            brightness *= 1.0 - flicker.amount * noise;
+        }
+        if let Some(mut pulse) = pulse {
+            pulse.elapsed += delta;
+            let wave = (TAU * (pulse.elapsed * pulse.frequency + pulse.phase)).cos();
+            brightness *= 1.0 - pulse.amount * 0.5 * (1.0 - wave);
+        }
+        if let Some(mut gradient) = gradient {
+            gradient.elapsed += delta;
+            let key = gradient.sample(gradient.elapsed);
+            color = Color::rgba(key.r(), key.g(), key.b(), base.color.a());
+            brightness *= key.a();
+        }
+        if let Some(mut curve) = curve {
+            curve.elapsed += delta;
+            brightness *= curve.sample(curve.elapsed);
+            despawn |= curve.despawn_on_finish && curve.is_finished();
+        }
+        if let Some(mut fade_in) = fade_in {
+            fade_in.elapsed += delta;
+            brightness *= fade_progress(fade_in.elapsed, fade_in.duration);
+            if fade_in.elapsed >= fade_in.duration {
+                commands.entity(entity).remove::<Light2dFadeIn>();
+            }
+        }
+        if let Some(mut fade_out) = fade_out {
+            fade_out.elapsed += delta;
+            brightness *= 1.0 - fade_progress(fade_out.elapsed, fade_out.duration);
+            despawn |= fade_out.elapsed >= fade_out.duration;
+        }
+        if despawn {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        // Curves may brighten past the base intensity, e.g. for HDR flashes.
+        let intensity = base.intensity * brightness.max(0.0);
+        light.color = color;
+        light.intensity = intensity;
+        base.applied = Some((color, intensity));
+    }
+}
+
+fn fade_progress(elapsed: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        1.0
+    } else {
+        (elapsed / duration).clamp(0.0, 1.0)
+    }
+}
+
+fn wrap_time(time: f32, duration: f32, repeat: bool) -> f32 {
+    if repeat && duration > 0.0 {
+        time.rem_euclid(duration)
+    } else {
+        time
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn inverse_lerp(a: f32, b: f32, value: f32) -> f32 {
+    if b > a {
+        ((value - a) / (b - a)).clamp(0.0, 1.0)
+    } else {
+        1.0
+    }
+}
+
+fn hash(seed: u32, index: i32) -> f32 {
+    let mut x = seed ^ (index as u32).wrapping_mul(0x9e37_79b9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x as f32 / u32::MAX as f32
+}
+
+/// Smooth 1D value noise in `0.0..=1.0`.
+fn value_noise(seed: u32, t: f32) -> f32 {
+    let index = t.floor();
+    let fraction = t - index;
+    let fraction = fraction * fraction * (3.0 - 2.0 * fraction);
+    let index = index as i32;
+    lerp(
+        hash(seed, index),
+        hash(seed, index.wrapping_add(1)),
+        fraction,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        prelude::{Stage, SystemStage, World},
+        utils::Duration,
+    };
+
+    use super::*;
+
+    fn animation_world() -> (World, SystemStage) {
+        let mut world = World::new();
+        let mut time = Time::default();
+        time.update();
+        world.insert_resource(time);
+        let mut stage = SystemStage::single_threaded();
+        stage
+            .add_system(init_light_animations)
+            .add_system(animate_lights);
+        (world, stage)
+    }
+
+    fn advance(world: &mut World, stage: &mut SystemStage, seconds: f32) {
+        let mut time = world.resource_mut::<Time>();
+        let last_update = time.last_update().unwrap();
+        time.update_with_instant(last_update + Duration::from_secs_f32(seconds));
+        stage.run(world);
+    }
+
+    #[test]
+    fn gradients_scale_the_intensity_and_keep_the_alpha() {
+        let (mut world, mut stage) = animation_world();
+        let keys = vec![
+            Light2dColorKey {
+                time: 0.0,
+                color: Color::rgba(1.0, 0.0, 0.0, 1.0),
+            },
+            Light2dColorKey {
+                time: 1.0,
+                color: Color::rgba(0.0, 0.0, 1.0, 0.5),
+            },
+        ];
+        let light = world
+            .spawn((
+                PointLight2d {
+                    color: Color::rgba(1.0, 1.0, 1.0, 0.75),
+                    intensity: 2.0,
+                    ..PointLight2d::default()
+                },
+                Light2dColorGradient::new(keys, false),
+            ))
+            .id();
+        // Inserts the animation base.
+        advance(&mut world, &mut stage, 0.0);
+        advance(&mut world, &mut stage, 2.0);
+
+        let light = world.get::<PointLight2d>(light).unwrap();
+        assert_eq!(light.color, Color::rgba(0.0, 0.0, 1.0, 0.75));
+        assert_eq!(light.intensity, 1.0);
+    }
+
+    #[test]
+    fn curves_can_brighten_past_the_base_intensity() {
+        let (mut world, mut stage) = animation_world();
+        let keys = vec![Light2dCurveKey {
+            time: 0.0,
+            value: 3.0,
+        }];
+        let light = world
+            .spawn((
+                PointLight2d {
+                    intensity: 2.0,
+                    ..PointLight2d::default()
+                },
+                Light2dCurve::new(keys),
+            ))
+            .id();
+        advance(&mut world, &mut stage, 0.0);
+        advance(&mut world, &mut stage, 0.5);
+        assert_eq!(world.get::<PointLight2d>(light).unwrap().intensity, 6.0);
+        assert_eq!(world.get::<PointLight2d>(light).unwrap().color.a(), 1.0);
+
+        // Setting the intensity from outside replaces the base.
+        world.get_mut::<PointLight2d>(light).unwrap().intensity = 0.5;
+        advance(&mut world, &mut stage, 0.5);
+        assert_eq!(world.get::<PointLight2d>(light).unwrap().intensity, 1.5);
+    }
+
+    #[test]
+    fn flicker_only_depends_on_the_seed_and_deltas() {
+        let (mut world, mut stage) = animation_world();
+        let lights = [1, 1, 2].map(|seed| {
+            world
+                .spawn((PointLight2d::default(), Light2dFlicker::new(seed, 8.0, 0.5)))
+                .id()
+        });
+        advance(&mut world, &mut stage, 0.0);
+        let mut sequences = [Vec::new(), Vec::new(), Vec::new()];
+        for _ in 0..20 {
+            advance(&mut world, &mut stage, 0.05);
+            for (light, sequence) in lights.iter().zip(&mut sequences) {
+                sequence.push(world.get::<PointLight2d>(*light).unwrap().intensity);
+            }
+        }
+        assert_eq!(sequences[0], sequences[1]);
+        assert_ne!(sequences[0], sequences[2]);
+    }
+
+    #[test]
+    fn fade_in_removes_itself_when_done() {
+        let (mut world, mut stage) = animation_world();
+        let light = world
+            .spawn((PointLight2d::default(), Light2dFadeIn::new(1.0)))
+            .id();
+        advance(&mut world, &mut stage, 0.0);
+        advance(&mut world, &mut stage, 0.5);
+        assert_eq!(world.get::<PointLight2d>(light).unwrap().intensity, 0.5);
+        advance(&mut world, &mut stage, 0.5);
+        assert_eq!(world.get::<PointLight2d>(light).unwrap().intensity, 1.0);
+        assert!(world.get::<Light2dFadeIn>(light).is_none());
+    }
+
+    #[test]
+    fn fade_out_and_curve_ending_together_despawn_the_light() {
+        let (mut world, mut stage) = animation_world();
+        let light = world
+            .spawn((
+                PointLight2d::default(),
+                Light2dFadeOut::new(0.5),
+                Light2dCurve::muzzle_flash(0.5),
+            ))
+            .id();
+        advance(&mut world, &mut stage, 0.0);
+        advance(&mut world, &mut stage, 0.25);
+        assert!(world.get_entity(light).is_some());
+        advance(&mut world, &mut stage, 0.25);
+        assert!(world.get_entity(light).is_none());
+        // Nothing is left to despawn it again.
+        advance(&mut world, &mut stage, 0.25);
+    }
+}
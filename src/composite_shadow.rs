@@ -0,0 +1,604 @@
+use std::{f32::consts::PI, ops::Range};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{mesh_shadow::trace_loops, Shadow2d, Static};
+
+/// Replaces the closed [`Shadow2d`] polygons of its direct children, e.g. the tiles of a
+/// tilemap, with their union: edges shared by neighbours are removed and collinear edges are
+/// merged, leaving one caster per outline or hole. The outlines are spawned as
+/// [`CompositeShadow2dPart`] children and take the opacity and tint of the first child.
+///
+/// Children are expected to touch but not overlap, as tiles do. They are merged in square
+/// chunks, by the center of their bounds, so editing a child only merges its chunk again,
+/// updating its parts in place. Outlines are split where they cross a chunk border.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct CompositeShadow2d {
+    /// Side of the chunks, in the space of this entity. `0.0` or less merges all the children
+    /// in one chunk.
+    pub chunk_size: f32,
+    /// Child polygons in the space of this entity, counter-clockwise.
+    #[reflect(ignore)]
+    polygons: HashMap<Entity, CompositePolygon>,
+    /// The parts of each chunk.
+    #[reflect(ignore)]
+    chunks: HashMap<IVec2, Vec<Entity>>,
+    /// Opacity, tint and transmission of the parts.
+    #[reflect(ignore)]
+    appearance: Option<(f32, Color, f32)>,
+    /// The `chunk_size` of `chunks`.
+    #[reflect(ignore)]
+    built_chunk_size: Option<f32>,
+}
+
+impl Default for CompositeShadow2d {
+    fn default() -> Self {
+        Self {
+            chunk_size: 256.0,
+            polygons: HashMap::default(),
+            chunks: HashMap::default(),
+            appearance: None,
+            built_chunk_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CompositePolygon {
+    points: Vec<Vec2>,
+    min: Vec2,
+    max: Vec2,
+}
+
+impl CompositePolygon {
+    fn new(points: Vec<Vec2>) -> Self {
+        let (min, max) = points.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), point| (min.min(*point), max.max(*point)),
+        );
+        Self { points, min, max }
+    }
+
+    fn chunk(&self, chunk_size: f32) -> IVec2 {
+        if chunk_size > 0.0 && chunk_size.is_finite() {
+            ((self.min + self.max) / 2.0 / chunk_size)
+                .floor()
+                .as_ivec2()
+        } else {
+            IVec2::ZERO
+        }
+    }
+}
+
+/// A caster spawned by [`CompositeShadow2d`] for one loop of the union.
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct CompositeShadow2dPart;
+
+/// A [`Shadow2d`] merged into the outline of its [`CompositeShadow2d`] parent, which casts no
+/// shadow on its own. Added and removed by [`update_composite_shadows`].
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct MergedShadow2d;
+
+/// Edges and corners of neighbouring polygons closer than this are welded together.
+const WELD_EPSILON: f32 = 1.0 / 1024.0;
+
+/// Edges whose directions differ by less than this, in radians, can lie on the same line.
+const ANGLE_EPSILON: f32 = 1.0 / 8192.0;
+
+fn signed_area(points: &[Vec2]) -> f32 {
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| point.perp_dot(points[(i + 1) % points.len()]))
+        .sum::<f32>()
+        / 2.0
+}
+
+/// Splits ascending `values` into runs whose neighbours are at most `epsilon` apart.
+fn runs(values: &[f32], epsilon: f32) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for i in 1..=values.len() {
+        if i == values.len() || values[i] - values[i - 1] > epsilon {
+            runs.push(start..i);
+            start = i;
+        }
+    }
+    runs
+}
+
+/// Corners of the union, each welded to the first one found within [`WELD_EPSILON`].
+#[derive(Default)]
+struct WeldedPoints {
+    points: Vec<Vec2>,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl WeldedPoints {
+    fn weld(&mut self, point: Vec2) -> usize {
+        let cell = (point / WELD_EPSILON).floor().as_ivec2();
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let found = self
+                    .cells
+                    .get(&(cell + IVec2::new(x, y)))
+                    .and_then(|indices| {
+                        indices
+                            .iter()
+                            .find(|index| self.points[**index].distance(point) <= WELD_EPSILON)
+                    });
+                if let Some(index) = found {
+                    return *index;
+                }
+            }
+        }
+        self.points.push(point);
+        self.cells
+            .entry(cell)
+            .or_default()
+            .push(self.points.len() - 1);
+        self.points.len() - 1
+    }
+}
+
+/// An edge of a polygon, counter-clockwise.
+struct UnionEdge {
+    /// Of the line through the edge, in `0.0..PI`.
+    angle: f32,
+    start: Vec2,
+    end: Vec2,
+}
+
+/// The union of polygons that touch but don't overlap, as closed loops: counter-clockwise
+/// outlines and clockwise holes.
+pub fn polygon_union(polygons: &[Vec<Vec2>]) -> Vec<Vec<Vec2>> {
+    // With every polygon counter-clockwise, an edge shared by two neighbours appears once in
+    // each direction. Summing the edges on each line cancels them out, even where a long edge
+    // meets several short ones.
+    let mut edges = Vec::new();
+    for polygon in polygons {
+        let area = signed_area(polygon);
+        if polygon.len() < 3 || area == 0.0 {
+            continue;
+        }
+        for i in 0..polygon.len() {
+            let (mut start, mut end) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            if area < 0.0 {
+                std::mem::swap(&mut start, &mut end);
+            }
+            let vector = end - start;
+            if vector.length_squared() == 0.0 {
+                continue;
+            }
+            let angle = vector.y.atan2(vector.x).rem_euclid(PI);
+            edges.push(UnionEdge { angle, start, end });
+        }
+    }
+    edges.sort_unstable_by(|a, b| a.angle.total_cmp(&b.angle));
+
+    // Edges of nearly the same direction, measured along the direction of the first one.
+    let angles = edges.iter().map(|edge| edge.angle).collect::<Vec<_>>();
+    let mut directions = runs(&angles, ANGLE_EPSILON)
+        .into_iter()
+        .map(|run| run.collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    // Opposite directions share a line, so angles just below `PI` go with those near `0`.
+    if directions.len() > 1 {
+        let last = &directions[directions.len() - 1];
+        if angles[directions[0][0]] + PI - angles[last[last.len() - 1]] <= ANGLE_EPSILON {
+            let last = directions.pop().unwrap();
+            directions[0].extend(last);
+        }
+    }
+
+    // Whatever doesn't cancel out becomes the outline, one edge per run of the same winding.
+    let mut points = WeldedPoints::default();
+    let mut next = HashMap::<usize, Vec<usize>>::default();
+    for direction_edges in directions {
+        let first = &edges[direction_edges[0]];
+        let direction = (first.end - first.start).normalize();
+        let mut offsets = direction_edges
+            .iter()
+            .map(|index| {
+                let edge = &edges[*index];
+                (direction.perp().dot((edge.start + edge.end) / 2.0), *index)
+            })
+            .collect::<Vec<_>>();
+        offsets.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let values = offsets
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>();
+        for line in runs(&values, WELD_EPSILON) {
+            // Along `direction`, an edge running with it covers its span with `+1` and one
+            // running against it with `-1`.
+            let mut events = Vec::new();
+            for (_, index) in &offsets[line] {
+                let edge = &edges[*index];
+                events.push((direction.dot(edge.start), 1, edge.start));
+                events.push((direction.dot(edge.end), -1, edge.end));
+            }
+            events.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            let positions = events.iter().map(|event| event.0).collect::<Vec<_>>();
+
+            let mut winding = 0;
+            let mut run: Option<(usize, i32)> = None;
+            for group in runs(&positions, WELD_EPSILON) {
+                winding += events[group.clone()]
+                    .iter()
+                    .map(|event| event.1)
+                    .sum::<i32>();
+                let sign = winding.signum();
+                if run.map_or(0, |(_, run_sign)| run_sign) == sign {
+                    continue;
+                }
+                let point = points.weld(events[group.start].2);
+                if let Some((start, run_sign)) = run {
+                    if run_sign > 0 {
+                        next.entry(start).or_default().push(point);
+                    } else {
+                        next.entry(point).or_default().push(start);
+                    }
+                }
+                run = (sign != 0).then_some((point, sign));
+            }
+        }
+    }
+
+    trace_loops(&next, &points.points)
+        .into_iter()
+        .filter(|outline| outline.len() >= 3)
+        .collect()
+}
+
+/// Keeps the outlines of [`CompositeShadow2d`]s up to date with their children, and releases
+/// the children of composites that were removed.
+pub fn update_composite_shadows(
+    mut commands: Commands,
+    mut composites: Query<(
+        Entity,
+        &mut CompositeShadow2d,
+        Option<&Children>,
+        Option<&Static>,
+        ChangeTrackers<CompositeShadow2d>,
+    )>,
+    shadows: Query<
+        (
+            &Shadow2d,
+            &Transform,
+            Option<&MergedShadow2d>,
+            ChangeTrackers<Shadow2d>,
+            ChangeTrackers<Transform>,
+        ),
+        Without<CompositeShadow2dPart>,
+    >,
+    parts: Query<(), With<CompositeShadow2dPart>>,
+    children_query: Query<&Children>,
+    removed: RemovedComponents<CompositeShadow2d>,
+) {
+    for entity in removed.iter() {
+        if composites.contains(entity) {
+            continue;
+        }
+        for child in children_query
+            .get(entity)
+            .into_iter()
+            .flat_map(|c| c.iter())
+        {
+            if parts.contains(child) {
+                commands.entity(child).despawn_recursive();
+            } else {
+                commands.entity(child).remove::<MergedShadow2d>();
+            }
+        }
+    }
+
+    for (entity, mut composite, children, is_static, composite_tracker) in &mut composites {
+        let children = children.map_or(&[][..], |children| &**children);
+        let mut first_shadow = None;
+        let mut merged = HashSet::default();
+        let chunk_size = composite.chunk_size;
+        // Chunks with a polygon that was added, changed or removed.
+        let mut touched = HashSet::default();
+        for &child in children {
+            let (shadow, transform, is_merged, shadow_tracker, transform_tracker) =
+                match shadows.get(child) {
+                    Ok(shadow) if shadow.0.closed && shadow.0.points.len() >= 3 => shadow,
+                    // Open casters keep casting on their own.
+                    Ok((_, _, Some(_), ..)) => {
+                        commands.entity(child).remove::<MergedShadow2d>();
+                        continue;
+                    }
+                    _ => continue,
+                };
+            merged.insert(child);
+            first_shadow.get_or_insert(shadow);
+            if is_merged.is_none() {
+                commands.entity(child).insert(MergedShadow2d);
+            }
+            if composite.polygons.contains_key(&child)
+                && !shadow_tracker.is_changed()
+                && !transform_tracker.is_changed()
+            {
+                continue;
+            }
+            let matrix = transform.compute_matrix();
+            let mut polygon = shadow
+                .points
+                .iter()
+                .map(|point| matrix.transform_point3(point.extend(0.0)).truncate())
+                .collect::<Vec<_>>();
+            if signed_area(&polygon) < 0.0 {
+                polygon.reverse();
+            }
+            let polygon = CompositePolygon::new(polygon);
+            touched.insert(polygon.chunk(chunk_size));
+            if let Some(old) = composite.polygons.insert(child, polygon) {
+                touched.insert(old.chunk(chunk_size));
+            }
+        }
+
+        // Every merged child has a polygon by now, any others left the composite.
+        if composite.polygons.len() != merged.len() {
+            let left = composite
+                .polygons
+                .keys()
+                .filter(|child| !merged.contains(*child))
+                .copied()
+                .collect::<Vec<_>>();
+            for child in left {
+                if let Some(old) = composite.polygons.remove(&child) {
+                    touched.insert(old.chunk(chunk_size));
+                }
+            }
+        }
+        let appearance =
+            first_shadow.map(|shadow| (shadow.opacity, shadow.tint, shadow.transmission));
+        let everything = composite_tracker.is_added()
+            || appearance != composite.appearance
+            || composite.built_chunk_size != Some(chunk_size);
+        if touched.is_empty() && !everything {
+            continue;
+        }
+
+        let composite = &mut *composite;
+        if composite_tracker.is_added() {
+            // Parts that weren't spawned by this composite, e.g. loaded with a scene.
+            for &child in children {
+                if parts.contains(child) {
+                    commands.entity(child).despawn_recursive();
+                }
+            }
+            composite.chunks.clear();
+        }
+        composite.appearance = appearance;
+        composite.built_chunk_size = Some(chunk_size);
+        if everything {
+            touched.extend(composite.chunks.keys().copied());
+            touched.extend(
+                composite
+                    .polygons
+                    .values()
+                    .map(|polygon| polygon.chunk(chunk_size)),
+            );
+        }
+
+        let mut members = HashMap::<IVec2, Vec<Entity>>::default();
+        for (child, polygon) in &composite.polygons {
+            let chunk = polygon.chunk(chunk_size);
+            if touched.contains(&chunk) {
+                members.entry(chunk).or_default().push(*child);
+            }
+        }
+        let mut touched = touched.into_iter().collect::<Vec<_>>();
+        touched.sort_unstable_by_key(|chunk| (chunk.x, chunk.y));
+
+        let template = first_shadow.cloned().unwrap_or_default();
+        for chunk in touched {
+            let mut unused_parts = composite
+                .chunks
+                .remove(&chunk)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|part| parts.contains(*part))
+                .rev()
+                .collect::<Vec<_>>();
+            let mut children = members.remove(&chunk).unwrap_or_default();
+            children.sort_unstable();
+            let chunk_polygons = children
+                .iter()
+                .map(|child| composite.polygons[child].points.clone())
+                .collect::<Vec<_>>();
+            let mut chunk_parts = Vec::new();
+            for points in polygon_union(&chunk_polygons) {
+                let shadow = Shadow2d {
+                    closed: true,
+                    points,
+                    ..template.clone()
+                };
+                let part = match unused_parts.pop() {
+                    Some(part) => {
+                        commands.entity(part).insert(shadow);
+                        part
+                    }
+                    None => {
+                        let mut part = commands.spawn((
+                            shadow,
+                            CompositeShadow2dPart,
+                            TransformBundle::default(),
+                        ));
+                        if is_static.is_some() {
+                            part.insert(Static);
+                        }
+                        let part = part.id();
+                        commands.entity(entity).add_child(part);
+                        part
+                    }
+                };
+                chunk_parts.push(part);
+            }
+            for part in unused_parts {
+                commands.entity(part).despawn_recursive();
+            }
+            if !chunk_parts.is_empty() {
+                composite.chunks.insert(chunk, chunk_parts);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f32, y: f32) -> Vec<Vec2> {
+        vec![
+            Vec2::new(x, y),
+            Vec2::new(x + 1.0, y),
+            Vec2::new(x + 1.0, y + 1.0),
+            Vec2::new(x, y + 1.0),
+        ]
+    }
+
+    /// Corner count and signed area of each loop, largest first.
+    fn loops(polygons: &[Vec<Vec2>]) -> Vec<(usize, f32)> {
+        let mut loops = polygon_union(polygons)
+            .iter()
+            .map(|outline| (outline.len(), signed_area(outline)))
+            .collect::<Vec<_>>();
+        loops.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        loops
+    }
+
+    fn assert_loops(polygons: &[Vec<Vec2>], expected: &[(usize, f32)]) {
+        let loops = loops(polygons);
+        assert_eq!(loops.len(), expected.len(), "{loops:?}");
+        for ((len, area), (expected_len, expected_area)) in loops.iter().zip(expected) {
+            assert_eq!(len, expected_len, "{loops:?}");
+            assert!((area - expected_area).abs() < 1e-4, "{loops:?}");
+        }
+    }
+
+    #[test]
+    fn neighbours_merge_into_one_outline() {
+        assert_loops(&[square(0.0, 0.0), square(1.0, 0.0)], &[(4, 2.0)]);
+        let tiles = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+        let tiles = tiles.map(|(x, y)| square(x, y));
+        assert_loops(&tiles, &[(4, 4.0)]);
+    }
+
+    #[test]
+    fn long_edges_cancel_several_short_ones() {
+        let wide = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert_loops(&[wide, square(0.0, 1.0), square(1.0, 1.0)], &[(4, 4.0)]);
+    }
+
+    #[test]
+    fn enclosed_space_becomes_a_hole() {
+        let tiles = (0..3)
+            .flat_map(|x| (0..3).map(move |y| (x, y)))
+            .filter(|tile| *tile != (1, 1))
+            .map(|(x, y)| square(x as f32, y as f32))
+            .collect::<Vec<_>>();
+        assert_loops(&tiles, &[(4, 9.0), (4, -1.0)]);
+    }
+
+    #[test]
+    fn separate_polygons_keep_their_outlines() {
+        assert_loops(&[square(0.0, 0.0), square(3.0, 0.0)], &[(4, 1.0), (4, 1.0)]);
+    }
+
+    #[test]
+    fn clockwise_polygons_are_reversed() {
+        let mut clockwise = square(0.0, 0.0);
+        clockwise.reverse();
+        assert_loops(&[clockwise, square(1.0, 0.0)], &[(4, 2.0)]);
+    }
+
+    #[test]
+    fn nearly_matching_edges_still_cancel() {
+        // The shared edge sits on either side of a multiple of half the weld distance, and one
+        // side of it is slightly skewed.
+        let x = 3.5 * WELD_EPSILON;
+        let left = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(x - 1e-6, 0.0),
+            Vec2::new(x - 1e-6, 1.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        let right = vec![
+            Vec2::new(x + 1e-6, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(x + 1e-6, 1.0 + 2e-6),
+        ];
+        assert_loops(&[left, right], &[(4, 2.0)]);
+    }
+
+    #[test]
+    fn children_are_chunked_by_their_center() {
+        let polygon = CompositePolygon::new(square(3.0, -1.0));
+        assert_eq!(polygon.chunk(4.0), IVec2::new(0, -1));
+        assert_eq!(polygon.chunk(2.0), IVec2::new(1, -1));
+        assert_eq!(polygon.chunk(0.0), IVec2::ZERO);
+    }
+
+    #[test]
+    fn editing_a_tile_only_merges_its_chunk() {
+        let mut world = World::new();
+        let mut stage = SystemStage::single_threaded().with_system(update_composite_shadows);
+        let tiles = (0..8)
+            .map(|x| {
+                let shadow = Shadow2d {
+                    closed: true,
+                    points: square(0.0, 0.0),
+                    ..default()
+                };
+                let transform = Transform::from_xyz(x as f32, 0.0, 0.0);
+                world
+                    .spawn((shadow, TransformBundle::from_transform(transform)))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let composite = CompositeShadow2d {
+            chunk_size: 4.0,
+            ..default()
+        };
+        world
+            .spawn((composite, TransformBundle::default()))
+            .push_children(&tiles);
+
+        let mut part_query = world.query_filtered::<Entity, With<CompositeShadow2dPart>>();
+        stage.run(&mut world);
+        assert_eq!(part_query.iter(&world).count(), 2);
+        world.clear_trackers();
+
+        // Shrinking the first tile leaves a gap in the first chunk.
+        world.get_mut::<Shadow2d>(tiles[0]).unwrap().points = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.5, 0.0),
+            Vec2::new(0.5, 1.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        stage.run(&mut world);
+        let mut changed = world
+            .query_filtered::<(&Shadow2d, ChangeTrackers<Shadow2d>), With<CompositeShadow2dPart>>();
+        let mut parts = changed
+            .iter(&world)
+            .map(|(shadow, tracker)| (signed_area(&shadow.points), tracker.is_changed()))
+            .collect::<Vec<_>>();
+        parts.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(parts, vec![(0.5, true), (3.0, true), (4.0, false)]);
+    }
+}
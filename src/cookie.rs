@@ -0,0 +1,200 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    prelude::{
+        Assets, Component, Handle, Image, Query, ReflectComponent, ReflectDefault, Res,
+        TextureAtlas, Time, Vec2, Vec4,
+    },
+    reflect::Reflect,
+};
+
+/// A cookie (gobo) multiplying the output of the [`PointLight2d`](crate::PointLight2d) on the
+/// same entity, e.g. for fan blades, water caustics or a flickering projector.
+///
+/// The cookie covers the light quad, `scale` times in each direction, and wraps around at its
+/// edges. Bright texels let the light through, their color tints it and their alpha scales it.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dCookie {
+    pub atlas: Handle<TextureAtlas>,
+    /// Atlas indices played in order. Empty plays every frame of the atlas.
+    pub frames: Vec<usize>,
+    /// Frames per second, `0.0` holds the first frame.
+    pub fps: f32,
+    /// Scroll speed, in cookie sizes per second.
+    pub scroll: Vec2,
+    /// Counter-clockwise rotation speed, in radians per second.
+    pub rotation_speed: f32,
+    /// Cookie repeats across the light quad.
+    pub scale: f32,
+    pub elapsed: f32,
+}
+
+impl Default for Light2dCookie {
+    fn default() -> Self {
+        Self::new(Handle::default())
+    }
+}
+
+impl Light2dCookie {
+    pub fn new(atlas: Handle<TextureAtlas>) -> Self {
+        Self {
+            atlas,
+            frames: Vec::new(),
+            fps: 0.0,
+            scroll: Vec2::ZERO,
+            rotation_speed: 0.0,
+            scale: 1.0,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn with_frames(mut self, frames: Vec<usize>, fps: f32) -> Self {
+        self.frames = frames;
+        self.fps = fps;
+        self
+    }
+
+    pub fn with_scroll(mut self, scroll: Vec2) -> Self {
+        self.scroll = scroll;
+        self
+    }
+
+    pub fn with_rotation_speed(mut self, rotation_speed: f32) -> Self {
+        self.rotation_speed = rotation_speed;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Atlas index shown at `elapsed`, out of `frame_count` atlas frames.
+    pub fn frame(&self, frame_count: usize) -> usize {
+        let len = if self.frames.is_empty() {
+            frame_count
+        } else {
+            self.frames.len()
+        };
+        if len == 0 {
+            return 0;
+        }
+        let step = if self.fps > 0.0 {
+            (self.elapsed * self.fps) as usize % len
+        } else {
+            0
+        };
+        if self.frames.is_empty() {
+            step
+        } else {
+            self.frames[step]
+        }
+    }
+
+    /// Scroll offset at `elapsed`, in cookie sizes, wrapped to `0.0..1.0`.
+    pub fn offset(&self) -> Vec2 {
+        let offset = self.scroll * self.elapsed;
+        offset - offset.floor()
+    }
+
+    /// Rotation at `elapsed`, in radians, wrapped to `0.0..TAU`.
+    pub fn rotation(&self) -> f32 {
+        (self.rotation_speed * self.elapsed).rem_euclid(TAU)
+    }
+
+    /// The image, the uv rectangle `(min, max)` of the current frame in it, and
+    /// `(offset, rotation, scale)`, as the light shader reads them.
+    pub fn sample_params(
+        &self,
+        atlases: &Assets<TextureAtlas>,
+    ) -> Option<(Handle<Image>, Vec4, Vec4)> {
+        self.atlas_sample_params(atlases.get(&self.atlas)?)
+    }
+
+    fn atlas_sample_params(&self, atlas: &TextureAtlas) -> Option<(Handle<Image>, Vec4, Vec4)> {
+        let rect = atlas.textures.get(self.frame(atlas.textures.len()))?;
+        // Inset by half a texel, so linear filtering next to the frame's edges, where the shader
+        // wraps, doesn't blend in the neighbouring frames.
+        let inset = Vec2::splat(0.5).min(rect.size() / 2.0);
+        let min = (rect.min + inset) / atlas.size;
+        let max = (rect.max - inset) / atlas.size;
+        let offset = self.offset();
+        Some((
+            atlas.texture.clone(),
+            Vec4::new(min.x, min.y, max.x, max.y),
+            Vec4::new(offset.x, offset.y, self.rotation(), self.scale),
+        ))
+    }
+}
+
+pub fn animate_light_cookies(time: Res<Time>, mut query: Query<&mut Light2dCookie>) {
+    let delta = time.delta_seconds();
+    for mut cookie in &mut query {
+        cookie.elapsed += delta;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::Rect, utils::default};
+
+    use super::*;
+
+    /// Four 8x8 frames in a row.
+    fn atlas() -> TextureAtlas {
+        let mut atlas = TextureAtlas::new_empty(Handle::default(), Vec2::new(32.0, 8.0));
+        for x in 0..4 {
+            let min = Vec2::new(x as f32 * 8.0, 0.0);
+            atlas.add_texture(Rect::from_corners(min, min + 8.0));
+        }
+        atlas
+    }
+
+    fn rect(cookie: &Light2dCookie) -> Vec4 {
+        cookie.atlas_sample_params(&atlas()).unwrap().1
+    }
+
+    #[test]
+    fn frames_play_in_order() {
+        let mut cookie = Light2dCookie::default().with_frames(vec![3, 1], 2.0);
+        assert_eq!(cookie.frame(4), 3);
+        cookie.elapsed = 0.75;
+        assert_eq!(cookie.frame(4), 1);
+        cookie.elapsed = 1.0;
+        assert_eq!(cookie.frame(4), 3);
+
+        let cookie = Light2dCookie {
+            fps: 4.0,
+            elapsed: 1.5,
+            ..default()
+        };
+        assert_eq!(cookie.frame(4), 2);
+        assert_eq!(cookie.frame(0), 0);
+    }
+
+    #[test]
+    fn rects_are_inset_by_half_a_texel() {
+        assert_eq!(
+            rect(&Light2dCookie::default()),
+            Vec4::new(0.5 / 32.0, 0.5 / 8.0, 7.5 / 32.0, 7.5 / 8.0)
+        );
+        let cookie = Light2dCookie {
+            frames: vec![2],
+            ..default()
+        };
+        assert_eq!(
+            rect(&cookie),
+            Vec4::new(16.5 / 32.0, 0.5 / 8.0, 23.5 / 32.0, 7.5 / 8.0)
+        );
+    }
+
+    #[test]
+    fn missing_frames_are_not_sampled() {
+        let cookie = Light2dCookie {
+            frames: vec![4],
+            ..default()
+        };
+        assert!(cookie.atlas_sample_params(&atlas()).is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::{
+    core_pipeline::core_2d::Transparent2d,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_phase::AddRenderCommand,
+        render_resource::SpecializedRenderPipelines,
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::{
+    affine_2d, distance_to_segment, light_radius, render::debug_lines, shadow_edges,
+    MergedShadow2d, PointLight2d, Shadow2d,
+};
+
+/// Draws light bounds, spot cones, [`Shadow2d`] outlines and the shadow volumes they extrude,
+/// on top of every 2d camera.
+#[derive(Default)]
+pub struct Light2dDebugPlugin;
+
+impl Plugin for Light2dDebugPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(mut shaders) = app.world.get_resource_mut::<Assets<Shader>>() {
+            let shader = Shader::from_wgsl(include_str!("render/debug_lines.wgsl"));
+            shaders.set_untracked(debug_lines::DEBUG_LINES_SHADER_HANDLE, shader);
+        }
+
+        app.init_resource::<Light2dDebugSettings>()
+            .init_resource::<Light2dDebugLines>()
+            .add_plugin(ExtractResourcePlugin::<Light2dDebugLines>::default())
+            .add_system_to_stage(CoreStage::First, clear_debug_lines)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                draw_light_debug.after(TransformSystem::TransformPropagate),
+            );
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<debug_lines::DebugLinesPipeline>()
+                .init_resource::<SpecializedRenderPipelines<debug_lines::DebugLinesPipeline>>()
+                .init_resource::<debug_lines::DebugLinesMeta>()
+                .add_render_command::<Transparent2d, debug_lines::DrawDebugLines>()
+                .add_system_to_stage(RenderStage::Queue, debug_lines::queue_debug_lines);
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct Light2dDebugSettings {
+    /// Outer radius, inner radius and the edges of the fully lit cone of every light.
+    pub lights: bool,
+    /// Caster outlines, with arrows along their winding. Counter-clockwise casters are drawn in
+    /// `counter_clockwise_color`, clockwise ones in `clockwise_color`, and the ends of open
+    /// casters are marked with crosses.
+    pub casters: bool,
+    /// The area behind each caster edge that is in range of a light.
+    pub shadow_volumes: bool,
+    pub light_color: Color,
+    pub counter_clockwise_color: Color,
+    pub clockwise_color: Color,
+    pub shadow_volume_color: Color,
+}
+
+impl Default for Light2dDebugSettings {
+    fn default() -> Self {
+        Self {
+            lights: true,
+            casters: true,
+            shadow_volumes: true,
+            light_color: Color::YELLOW,
+            counter_clockwise_color: Color::GREEN,
+            clockwise_color: Color::ORANGE_RED,
+            shadow_volume_color: Color::rgba(0.5, 0.5, 1.0, 0.5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light2dDebugLine {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Color,
+}
+
+/// Lines drawn by [`Light2dDebugPlugin`] this frame. Cleared at the start of every frame, other
+/// systems can add their own.
+#[derive(Resource, Debug, Clone, Default, ExtractResource)]
+pub struct Light2dDebugLines {
+    pub lines: Vec<Light2dDebugLine>,
+}
+
+impl Light2dDebugLines {
+    pub fn line(&mut self, start: Vec2, end: Vec2, color: Color) {
+        self.lines.push(Light2dDebugLine {
+            start: start.extend(0.0),
+            end: end.extend(0.0),
+            color,
+        });
+    }
+
+    /// Closed polyline through `points`.
+    pub fn polygon(&mut self, points: &[Vec2], color: Color) {
+        for (i, start) in points.iter().enumerate() {
+            self.line(*start, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    pub fn arrow(&mut self, start: Vec2, end: Vec2, head: f32, color: Color) {
+        self.line(start, end, color);
+        let back = (start - end).normalize_or_zero() * head;
+        self.line(end, end + Mat2::from_angle(PI / 6.0) * back, color);
+        self.line(end, end + Mat2::from_angle(-PI / 6.0) * back, color);
+    }
+
+    pub fn cross(&mut self, center: Vec2, size: f32, color: Color) {
+        let half = size / 2.0;
+        self.line(
+            center - Vec2::splat(half),
+            center + Vec2::splat(half),
+            color,
+        );
+        self.line(
+            center + Vec2::new(-half, half),
+            center + Vec2::new(half, -half),
+            color,
+        );
+    }
+}
+
+fn clear_debug_lines(mut lines: ResMut<Light2dDebugLines>) {
+    lines.lines.clear();
+}
+
+const CIRCLE_SEGMENTS: usize = 48;
+
+pub fn draw_light_debug(
+    settings: Res<Light2dDebugSettings>,
+    mut lines: ResMut<Light2dDebugLines>,
+    lights: Query<(&PointLight2d, &GlobalTransform, &ComputedVisibility)>,
+    casters: Query<(&Shadow2d, &GlobalTransform), Without<MergedShadow2d>>,
+) {
+    let lines = &mut *lines;
+
+    if settings.lights {
+        for (light, transform, visibility) in &lights {
+            if !visibility.is_visible() {
+                continue;
+            }
+            let affine = affine_2d(transform);
+            // Same local space as `light.wgsl`, a unit quad around the light.
+            let circle = |radius: f32| {
+                (0..CIRCLE_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                        affine.transform_point2(Vec2::new(angle.cos(), angle.sin()) * radius)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            lines.polygon(&circle(0.5), settings.light_color);
+            if light.inner_radius > 0.0 && light.inner_radius < 1.0 {
+                lines.polygon(&circle(0.5 * light.inner_radius), settings.light_color);
+            }
+
+            // The cone is fully lit within `outer_angle - inner_angle` (as a fraction of a half
+            // turn) of the light's local -y axis, and fades out towards +y.
+            let edge = light.outer_angle - light.inner_angle;
+            if edge > 0.0 && edge < 1.0 {
+                let center = affine.transform_point2(Vec2::ZERO);
+                let angle = PI * (1.0 - edge);
+                for side in [angle, -angle] {
+                    let direction = Mat2::from_angle(side) * Vec2::NEG_Y;
+                    lines.line(
+                        center,
+                        affine.transform_point2(direction * 0.5),
+                        settings.light_color,
+                    );
+                }
+            }
+        }
+    }
+
+    if settings.casters {
+        for (shadow, transform) in &casters {
+            let edges = shadow_edges(shadow, transform).collect::<Vec<_>>();
+            let area = edges
+                .iter()
+                .map(|(start, end)| start.perp_dot(*end))
+                .sum::<f32>();
+            let color = if area >= 0.0 {
+                settings.counter_clockwise_color
+            } else {
+                settings.clockwise_color
+            };
+            for (start, end) in &edges {
+                lines.line(*start, *end, color);
+                let length = start.distance(*end);
+                let middle = (*start + *end) / 2.0;
+                let direction = (*end - *start).normalize_or_zero() * length * 0.1;
+                lines.arrow(middle - direction, middle + direction, length * 0.1, color);
+            }
+            if !shadow.closed {
+                if let (Some((first, _)), Some((_, last))) = (edges.first(), edges.last()) {
+                    let size = first.distance(*last).max(1.0) * 0.05;
+                    lines.cross(*first, size, color);
+                    lines.cross(*last, size, color);
+                }
+            }
+        }
+    }
+
+    if settings.shadow_volumes {
+        for (_, light_transform, visibility) in &lights {
+            if !visibility.is_visible() {
+                continue;
+            }
+            let light_position = light_transform.translation().truncate();
+            let radius = light_radius(light_transform);
+            for (shadow, transform) in &casters {
+                for (start, end) in shadow_edges(shadow, transform) {
+                    if distance_to_segment(light_position, start, end) >= radius {
+                        continue;
+                    }
+                    let extrude = |point: Vec2| {
+                        let offset = point - light_position;
+                        if offset.length() >= radius {
+                            point
+                        } else {
+                            light_position + offset.normalize_or_zero() * radius
+                        }
+                    };
+                    lines.polygon(
+                        &[start, end, extrude(end), extrude(start)],
+                        settings.shadow_volume_color,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, Error, LoadContext, LoadedAsset},
+    prelude::Image,
+    reflect::TypeUuid,
+    render::{
+        render_resource::TextureFormat,
+        texture::{CompressedImageFormats, ImageType},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// A falloff curve a [`PointLight2d`](crate::PointLight2d) can pick by handle instead of the
+/// built-in `falloff_intensity` family.
+///
+/// The curve maps the light's combined radius and angle attenuation, from `0.0` at the outer
+/// edge to `1.0` at the center, to the light's brightness.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "4f0e3a57-6a39-4c1e-9d0f-0c2b8b1f6a42"]
+pub enum Light2dFalloff {
+    /// Keys sorted by position, see [`Self::sorted`]. The `.falloff.ron` loader sorts them.
+    Keyframes {
+        keys: Vec<Light2dFalloffKey>,
+        #[serde(default)]
+        interpolation: Light2dFalloffInterpolation,
+    },
+    /// Evenly spaced from the outer edge to the center, both included.
+    Samples(Vec<f32>),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Light2dFalloffKey {
+    pub position: f32,
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Light2dFalloffInterpolation {
+    #[default]
+    Linear,
+    Step,
+}
+
+impl Light2dFalloff {
+    /// Number of samples a curve is baked into.
+    pub const RESOLUTION: usize = 2048;
+
+    /// Sorts the keys of [`Self::Keyframes`] by position.
+    pub fn sorted(mut self) -> Self {
+        if let Self::Keyframes { keys, .. } = &mut self {
+            keys.sort_by(|a, b| a.position.total_cmp(&b.position));
+        }
+        self
+    }
+
+    pub fn linear() -> Self {
+        Self::Keyframes {
+            keys: vec![
+                Light2dFalloffKey {
+                    position: 0.0,
+                    value: 0.0,
+                },
+                Light2dFalloffKey {
+                    position: 1.0,
+                    value: 1.0,
+                },
+            ],
+            interpolation: Light2dFalloffInterpolation::Linear,
+        }
+    }
+
+    /// Inverse-square falloff, windowed so that it reaches zero at the light's outer edge.
+    /// Larger `sharpness` concentrates more of the light around the center.
+    pub fn inverse_square(sharpness: f32) -> Self {
+        let edge = 1.0 / (1.0 + sharpness);
+        Self::from_fn(|position| {
+            let distance = 1.0 - position;
+            let value = 1.0 / (1.0 + sharpness * distance * distance);
+            ((value - edge) / (1.0 - edge)).clamp(0.0, 1.0)
+        })
+    }
+
+    /// Hard bands of equal width, brightest at the center.
+    pub fn stepped(steps: u32) -> Self {
+        let steps = steps.max(1);
+        Self::Keyframes {
+            keys: (0..=steps)
+                .map(|step| {
+                    let value = step as f32 / steps as f32;
+                    Light2dFalloffKey {
+                        position: value,
+                        value,
+                    }
+                })
+                .collect(),
+            interpolation: Light2dFalloffInterpolation::Step,
+        }
+    }
+
+    pub fn from_fn(f: impl Fn(f32) -> f32) -> Self {
+        Self::Samples(
+            (0..Self::RESOLUTION)
+                .map(|x| f(Self::position(x)))
+                .collect(),
+        )
+    }
+
+    /// Reads the red channel of the first row of a gradient image, left being the outer edge
+    /// of the light. sRGB images are converted to linear values. Returns `None` for texture
+    /// formats that can't be read on the CPU and for images with less data than their width.
+    pub fn from_gradient(image: &Image) -> Option<Self> {
+        let width = image.texture_descriptor.size.width as usize;
+        let (pixel_size, red_offset, srgb) = match image.texture_descriptor.format {
+            TextureFormat::R8Unorm => (1, 0, false),
+            TextureFormat::Rgba8Unorm => (4, 0, false),
+            TextureFormat::Rgba8UnormSrgb => (4, 0, true),
+            TextureFormat::Bgra8Unorm => (4, 2, false),
+            TextureFormat::Bgra8UnormSrgb => (4, 2, true),
+            TextureFormat::R32Float => {
+                return Some(Self::Samples(
+                    image
+                        .data
+                        .get(..width * 4)?
+                        .chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                        .collect(),
+                ))
+            }
+            _ => return None,
+        };
+        Some(Self::Samples(
+            image
+                .data
+                .get(..width * pixel_size)?
+                .chunks_exact(pixel_size)
+                .map(|pixel| {
+                    let value = pixel[red_offset] as f32 / 255.0;
+                    if srgb {
+                        srgb_to_linear(value)
+                    } else {
+                        value
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn evaluate(&self, position: f32) -> f32 {
+        let position = position.clamp(0.0, 1.0);
+        match self {
+            Self::Keyframes {
+                keys,
+                interpolation,
+            } => {
+                let (first, last) = match (keys.first(), keys.last()) {
+                    (Some(first), Some(last)) => (first, last),
+                    _ => return position,
+                };
+                if position <= first.position {
+                    return first.value;
+                }
+                for window in keys.windows(2) {
+                    let (a, b) = (&window[0], &window[1]);
+                    if position < b.position {
+                        return match interpolation {
+                            Light2dFalloffInterpolation::Linear => {
+                                let t = (position - a.position) / (b.position - a.position);
+                                a.value + (b.value - a.value) * t
+                            }
+                            Light2dFalloffInterpolation::Step => a.value,
+                        };
+                    }
+                }
+                last.value
+            }
+            Self::Samples(samples) => {
+                if samples.is_empty() {
+                    return position;
+                }
+                let x = position * (samples.len() - 1) as f32;
+                let index = x.floor() as usize;
+                let next = (index + 1).min(samples.len() - 1);
+                let t = x - index as f32;
+                samples[index] + (samples[next] - samples[index]) * t
+            }
+        }
+    }
+
+    /// Samples the curve at [`Self::RESOLUTION`] evenly spaced positions from `0.0` to `1.0`,
+    /// both included, like [`Self::Samples`] is evaluated.
+    pub fn bake(&self) -> Vec<f32> {
+        (0..Self::RESOLUTION)
+            .map(|x| self.evaluate(Self::position(x)))
+            .collect()
+    }
+
+    /// Position of sample `x` of [`Self::RESOLUTION`].
+    fn position(x: usize) -> f32 {
+        x as f32 / (Self::RESOLUTION - 1) as f32
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Loads [`Light2dFalloff`] curves from `.falloff.ron` files.
+#[derive(Default)]
+pub struct Light2dFalloffLoader;
+
+impl AssetLoader for Light2dFalloffLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let falloff = ron::de::from_bytes::<Light2dFalloff>(bytes)?.sorted();
+            load_context.set_default_asset(LoadedAsset::new(falloff));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["falloff.ron"]
+    }
+}
+
+/// Loads [`Light2dFalloff`] curves from gradient images named `.falloff.png`, read with
+/// [`Light2dFalloff::from_gradient`] as sRGB.
+#[derive(Default)]
+pub struct Light2dFalloffGradientLoader;
+
+impl AssetLoader for Light2dFalloffGradientLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let image = Image::from_buffer(
+                bytes,
+                ImageType::Extension("png"),
+                CompressedImageFormats::NONE,
+                true,
+            )?;
+            let falloff = Light2dFalloff::from_gradient(&image).ok_or_else(|| {
+                Error::msg(format!(
+                    "{:?} has a texture format that can't be read as a falloff",
+                    image.texture_descriptor.format
+                ))
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(falloff));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["falloff.png"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    use super::*;
+
+    fn key(position: f32, value: f32) -> Light2dFalloffKey {
+        Light2dFalloffKey { position, value }
+    }
+
+    /// A two pixel wide gradient.
+    fn gradient(format: TextureFormat, data: Vec<u8>) -> Image {
+        Image::new(
+            Extent3d {
+                width: 2,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            format,
+        )
+    }
+
+    #[test]
+    fn from_fn_samples_both_ends() {
+        let falloff = Light2dFalloff::from_fn(|position| position);
+        for position in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert!((falloff.evaluate(position) - position).abs() < 1e-6);
+        }
+        match Light2dFalloff::from_fn(|position| position) {
+            Light2dFalloff::Samples(samples) => {
+                assert_eq!(samples.len(), Light2dFalloff::RESOLUTION);
+                assert_eq!(samples[0], 0.0);
+                assert_eq!(samples[Light2dFalloff::RESOLUTION - 1], 1.0);
+            }
+            Light2dFalloff::Keyframes { .. } => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn bake_round_trips_samples() {
+        let falloff = Light2dFalloff::inverse_square(8.0);
+        let baked = Light2dFalloff::Samples(falloff.bake());
+        for i in 0..=20 {
+            let position = i as f32 / 20.0;
+            assert!((baked.evaluate(position) - falloff.evaluate(position)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn keyframes_interpolate() {
+        let linear = Light2dFalloff::Keyframes {
+            keys: vec![key(0.0, 0.0), key(0.5, 1.0), key(1.0, 0.0)],
+            interpolation: Light2dFalloffInterpolation::Linear,
+        };
+        assert_eq!(linear.evaluate(0.25), 0.5);
+        assert_eq!(linear.evaluate(0.5), 1.0);
+        assert_eq!(linear.evaluate(0.75), 0.5);
+        // Clamped to the curve's range.
+        assert_eq!(linear.evaluate(-1.0), 0.0);
+        assert_eq!(linear.evaluate(2.0), 0.0);
+
+        let step = Light2dFalloff::stepped(2);
+        assert_eq!(step.evaluate(0.25), 0.0);
+        assert_eq!(step.evaluate(0.5), 0.5);
+        assert_eq!(step.evaluate(0.99), 0.5);
+        assert_eq!(step.evaluate(1.0), 1.0);
+    }
+
+    #[test]
+    fn keyframes_in_any_order() {
+        let sorted = Light2dFalloff::Keyframes {
+            keys: vec![key(0.0, 0.2), key(0.4, 0.6), key(1.0, 1.0)],
+            interpolation: Light2dFalloffInterpolation::Linear,
+        };
+        let shuffled = Light2dFalloff::Keyframes {
+            keys: vec![key(1.0, 1.0), key(0.0, 0.2), key(0.4, 0.6)],
+            interpolation: Light2dFalloffInterpolation::Linear,
+        }
+        .sorted();
+        for i in 0..=10 {
+            let position = i as f32 / 10.0;
+            assert_eq!(sorted.evaluate(position), shuffled.evaluate(position));
+        }
+    }
+
+    #[test]
+    fn sorted_orders_the_keys() {
+        let falloff = ron::de::from_str::<Light2dFalloff>(
+            "Keyframes(keys: [(position: 1.0, value: 1.0), (position: 0.0, value: 0.0)])",
+        )
+        .unwrap()
+        .sorted();
+        match &falloff {
+            Light2dFalloff::Keyframes { keys, .. } => {
+                assert_eq!(keys[0].position, 0.0);
+                assert_eq!(keys[1].position, 1.0);
+            }
+            Light2dFalloff::Samples(_) => unreachable!(),
+        }
+        assert_eq!(falloff.evaluate(0.5), 0.5);
+    }
+
+    #[test]
+    fn empty_curves_are_linear() {
+        let keyframes = Light2dFalloff::Keyframes {
+            keys: Vec::new(),
+            interpolation: Light2dFalloffInterpolation::Linear,
+        };
+        assert_eq!(keyframes.evaluate(0.3), 0.3);
+        assert_eq!(Light2dFalloff::Samples(Vec::new()).evaluate(0.3), 0.3);
+    }
+
+    #[test]
+    fn from_gradient_converts_srgb() {
+        let data = vec![0, 0, 0, 255, 188, 0, 0, 255];
+        let linear =
+            Light2dFalloff::from_gradient(&gradient(TextureFormat::Rgba8Unorm, data.clone()))
+                .unwrap();
+        assert!((linear.evaluate(1.0) - 188.0 / 255.0).abs() < 1e-6);
+        let srgb =
+            Light2dFalloff::from_gradient(&gradient(TextureFormat::Rgba8UnormSrgb, data)).unwrap();
+        assert_eq!(srgb.evaluate(0.0), 0.0);
+        assert!((srgb.evaluate(1.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn from_gradient_rejects_short_data() {
+        let mut image = gradient(TextureFormat::Rgba8Unorm, vec![0; 8]);
+        image.data.truncate(6);
+        assert!(Light2dFalloff::from_gradient(&image).is_none());
+    }
+}
@@ -0,0 +1,331 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::render::fog_of_war::FogOfWarReadback;
+
+/// Marks a light whose area is revealed by every [`FogOfWar`], whether a camera sees it or not.
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct VisionLight2d;
+
+/// Cells of a [`FogOfWar`] that have been seen at least once, `0` for never and `255` for fully
+/// revealed, bottom row first. Serialize it to keep exploration in save games.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Reflect, FromReflect)]
+pub struct FogOfWarExplored {
+    pub size: UVec2,
+    pub cells: Vec<u8>,
+}
+
+impl FogOfWarExplored {
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            cells: vec![0; size.x as usize * size.y as usize],
+        }
+    }
+
+    /// `0` outside the grid.
+    pub fn get(&self, cell: UVec2) -> u8 {
+        if cell.x >= self.size.x || cell.y >= self.size.y {
+            return 0;
+        }
+        let index = cell.y as usize * self.size.x as usize + cell.x as usize;
+        self.cells.get(index).copied().unwrap_or(0)
+    }
+
+    /// Whether there is one cell per cell of `size`, which a deserialized value doesn't ensure.
+    pub fn is_valid(&self) -> bool {
+        self.cells.len() == self.size.x as usize * self.size.y as usize
+    }
+
+    /// A copy with `size` cells, keeping the cells both grids share.
+    pub fn resized(&self, size: UVec2) -> Self {
+        let mut resized = Self::new(size);
+        for y in 0..size.y.min(self.size.y) {
+            for x in 0..size.x.min(self.size.x) {
+                resized.cells[y as usize * size.x as usize + x as usize] =
+                    self.get(UVec2::new(x, y));
+            }
+        }
+        resized
+    }
+}
+
+/// A grid over the world that starts dark, is revealed by [`VisionLight2d`] lights and
+/// remembers explored areas at `explored_intensity`.
+///
+/// The vision lights are drawn into `image` by the light pipeline, one texel per cell, so
+/// shadows, caster opacity and transmission, falloffs and cookies all apply. The fog is
+/// composited over every 2D camera like a [`Light2dOverlay`](crate::render::Light2dOverlay).
+/// `image` is read back without waiting on the GPU, so `explored` and
+/// [`visibility`](Self::visibility) lag a frame or two behind.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct FogOfWar {
+    /// World position of the bottom-left corner of the grid.
+    pub origin: Vec2,
+    /// Cells of the grid, and texels of `image` and `explored_image`, which are resized when it
+    /// changes.
+    pub size: UVec2,
+    pub cell_size: f32,
+    pub color: Color,
+    /// Brightness of explored cells that no vision light currently reaches, in `0.0..=1.0`.
+    pub explored_intensity: f32,
+    /// Replace it to restore a saved game. One that doesn't match `size` is resized to it.
+    pub explored: FogOfWarExplored,
+    /// Light texture the vision lights are drawn into, in [`TextureFormat::Rgba8Unorm`].
+    pub image: Handle<Image>,
+    /// `explored` scaled by `explored_intensity`, in [`TextureFormat::R8Unorm`].
+    pub explored_image: Handle<Image>,
+    /// Coverage of the cells by the vision lights, as last read back.
+    #[reflect(ignore)]
+    visible: Vec<u8>,
+    /// The cells and intensity last written to `explored_image`.
+    #[reflect(ignore)]
+    uploaded: Option<(Vec<u8>, f32)>,
+}
+
+impl FogOfWar {
+    pub fn new(
+        images: &mut Assets<Image>,
+        origin: Vec2,
+        size: UVec2,
+        cell_size: f32,
+        explored_intensity: f32,
+    ) -> Self {
+        let extent = grid_extent(size);
+        let mut image = Image::new_fill(
+            extent,
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8Unorm,
+        );
+        image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_DST
+            | TextureUsages::COPY_SRC
+            | TextureUsages::RENDER_ATTACHMENT;
+        let explored_image =
+            Image::new_fill(extent, TextureDimension::D2, &[0], TextureFormat::R8Unorm);
+        Self {
+            origin,
+            size,
+            cell_size,
+            color: Color::BLACK,
+            explored_intensity,
+            explored: FogOfWarExplored::new(size),
+            image: images.add(image),
+            explored_image: images.add(explored_image),
+            visible: Vec::new(),
+            uploaded: None,
+        }
+    }
+
+    /// World-space size of the whole grid.
+    pub fn world_size(&self) -> Vec2 {
+        self.size.as_vec2() * self.cell_size
+    }
+
+    pub fn cell_center(&self, cell: UVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + 0.5) * self.cell_size
+    }
+
+    /// Revealed brightness of `cell`, in `0.0..=1.0`.
+    pub fn visibility(&self, cell: UVec2) -> f32 {
+        if cell.x >= self.size.x || cell.y >= self.size.y {
+            return 0.0;
+        }
+        let index = cell.y as usize * self.size.x as usize + cell.x as usize;
+        let visible = self
+            .visible
+            .get(index)
+            .map_or(0.0, |visible| *visible as f32 / 255.0);
+        let explored = self.explored.get(cell) as f32 / 255.0 * self.explored_intensity;
+        visible.max(explored)
+    }
+}
+
+impl Default for FogOfWar {
+    fn default() -> Self {
+        Self {
+            origin: Vec2::ZERO,
+            size: UVec2::ZERO,
+            cell_size: 1.0,
+            color: Color::BLACK,
+            explored_intensity: 0.5,
+            explored: FogOfWarExplored::default(),
+            image: Handle::default(),
+            explored_image: Handle::default(),
+            visible: Vec::new(),
+            uploaded: None,
+        }
+    }
+}
+
+fn grid_extent(size: UVec2) -> Extent3d {
+    Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Resizes the image behind `handle` to `size` texels, clearing it. Returns whether it was
+/// resized.
+fn resize_image(images: &mut Assets<Image>, handle: &Handle<Image>, size: UVec2) -> bool {
+    let extent = grid_extent(size);
+    // Only borrowed mutably when resized, which marks the image as modified.
+    match images.get(handle) {
+        Some(image) if image.texture_descriptor.size != extent => {}
+        _ => return false,
+    }
+    images.get_mut(handle).unwrap().resize(extent);
+    true
+}
+
+/// Explores the cells the vision lights covered, as read back from the GPU, and keeps
+/// [`FogOfWar::image`] and [`FogOfWar::explored_image`] up to date with the grid.
+pub fn update_fog_of_war(
+    mut fogs: Query<(Entity, &mut FogOfWar)>,
+    readback: Res<FogOfWarReadback>,
+    mut images: Option<ResMut<Assets<Image>>>,
+) {
+    let mut coverage = readback.take();
+
+    for (entity, mut fog) in &mut fogs {
+        let fog = &mut *fog;
+        let cell_count = fog.size.x as usize * fog.size.y as usize;
+        if cell_count == 0 {
+            continue;
+        }
+        if fog.explored.size != fog.size || !fog.explored.is_valid() {
+            warn!(
+                "FogOfWarExplored of {entity:?} doesn't match its {}x{} grid and was resized",
+                fog.size.x, fog.size.y
+            );
+            fog.explored = fog.explored.resized(fog.size);
+        }
+        if let Some(images) = images.as_mut() {
+            resize_image(images, &fog.image, fog.size);
+            if resize_image(images, &fog.explored_image, fog.size) {
+                fog.uploaded = None;
+            }
+        }
+        if fog.visible.len() != cell_count {
+            fog.visible.clear();
+        }
+
+        // Coverage read back before a resize doesn't fit the grid.
+        if let Some(visible) = coverage.remove(&entity).filter(|v| v.len() == cell_count) {
+            for (explored, visible) in fog.explored.cells.iter_mut().zip(&visible) {
+                *explored = (*explored).max(*visible);
+            }
+            fog.visible = visible;
+        }
+
+        let intensity = fog.explored_intensity.clamp(0.0, 1.0);
+        if fog
+            .uploaded
+            .as_ref()
+            .map_or(false, |(cells, uploaded_intensity)| {
+                *uploaded_intensity == intensity && *cells == fog.explored.cells
+            })
+        {
+            continue;
+        }
+        if let Some(image) = images
+            .as_mut()
+            .and_then(|images| images.get_mut(&fog.explored_image))
+            .filter(|image| image.data.len() == cell_count)
+        {
+            // Image rows run top to bottom, grid rows bottom to top.
+            image.data = fog
+                .explored
+                .cells
+                .chunks_exact(fog.size.x as usize)
+                .rev()
+                .flatten()
+                .map(|cell| (*cell as f32 * intensity).round() as u8)
+                .collect();
+            fog.uploaded = Some((fog.explored.cells.clone(), intensity));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explored_cells_outside_the_grid_are_unexplored() {
+        let mut explored = FogOfWarExplored::new(UVec2::new(2, 2));
+        explored.cells[3] = 255;
+        assert_eq!(explored.get(UVec2::new(1, 1)), 255);
+        assert_eq!(explored.get(UVec2::new(2, 0)), 0);
+        assert_eq!(explored.get(UVec2::new(0, 2)), 0);
+    }
+
+    #[test]
+    fn deserialized_cells_are_checked() {
+        let explored: FogOfWarExplored =
+            ron::from_str("(size: (4, 4), cells: [255, 255])").unwrap();
+        assert!(!explored.is_valid());
+        // Missing cells are unexplored rather than out of bounds.
+        assert_eq!(explored.get(UVec2::new(3, 3)), 0);
+        let resized = explored.resized(explored.size);
+        assert!(resized.is_valid());
+        assert_eq!(&resized.cells[..3], &[255, 255, 0]);
+    }
+
+    #[test]
+    fn resizing_the_grid_resizes_the_images() {
+        // `Assets` can only be created through an `AssetServer`.
+        let mut app = App::new();
+        app.add_plugin(AssetPlugin::default()).add_asset::<Image>();
+        let mut world = std::mem::take(&mut app.world);
+        let mut fog = FogOfWar::new(
+            &mut world.resource_mut::<Assets<Image>>(),
+            Vec2::ZERO,
+            UVec2::new(2, 2),
+            1.0,
+            1.0,
+        );
+        fog.explored.cells = vec![255, 0, 0, 255];
+        let (image, explored_image) = (fog.image.clone(), fog.explored_image.clone());
+        world.insert_resource(FogOfWarReadback::default());
+        let fog = world.spawn(fog).id();
+        let mut stage = SystemStage::single_threaded().with_system(update_fog_of_war);
+        stage.run(&mut world);
+
+        world.get_mut::<FogOfWar>(fog).unwrap().size = UVec2::new(3, 1);
+        stage.run(&mut world);
+        let images = world.resource::<Assets<Image>>();
+        for handle in [&image, &explored_image] {
+            assert_eq!(
+                images.get(handle).unwrap().texture_descriptor.size,
+                grid_extent(UVec2::new(3, 1))
+            );
+        }
+        assert_eq!(images.get(&image).unwrap().data.len(), 3 * 4);
+        // The first row is kept and uploaded.
+        assert_eq!(images.get(&explored_image).unwrap().data, vec![255, 0, 0]);
+        let fog = world.get::<FogOfWar>(fog).unwrap();
+        assert_eq!(fog.explored.cells, vec![255, 0, 0]);
+    }
+
+    #[test]
+    fn resizing_keeps_the_shared_cells() {
+        let mut explored = FogOfWarExplored::new(UVec2::new(3, 2));
+        explored.cells = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(
+            explored.resized(UVec2::new(2, 3)).cells,
+            vec![1, 2, 4, 5, 0, 0]
+        );
+        assert_eq!(
+            explored.resized(UVec2::new(4, 2)).cells,
+            vec![1, 2, 3, 0, 4, 5, 6, 0]
+        );
+    }
+}
@@ -1,59 +1,220 @@
+mod animation;
+mod composite_shadow;
+mod cookie;
+mod debug;
+mod falloff;
+mod fog_of_war;
 mod light_2d;
+mod lightmap;
+mod mesh_shadow;
+mod preset;
+mod query;
+mod reference;
 pub mod render;
+mod util;
+mod visibility;
 
 use bevy::{
     core_pipeline::core_2d::Transparent2d,
     prelude::*,
     render::{
-        extract_component::UniformComponentPlugin, render_phase::AddRenderCommand,
-        render_resource::SpecializedRenderPipelines, RenderApp, RenderStage,
+        extract_component::UniformComponentPlugin,
+        render_asset::RenderAssetPlugin,
+        render_graph::{RenderGraph, SlotInfo, SlotType},
+        render_phase::AddRenderCommand,
+        render_resource::SpecializedRenderPipelines,
+        RenderApp, RenderStage,
     },
 };
 
+pub use animation::*;
+pub use composite_shadow::*;
+pub use cookie::*;
+pub use debug::*;
+pub use falloff::*;
+pub use fog_of_war::*;
 pub use light_2d::*;
+pub use lightmap::*;
+pub use mesh_shadow::*;
+pub use preset::*;
+pub use query::*;
+pub use reference::*;
+pub use visibility::*;
 
 use render::{
-    light::{Light2dPipeline, Light2dUniform, LIGHT_SHADER_HANDLE},
+    ambient_occlusion::{
+        queue_ambient_occlusion_bind_groups, Light2dAmbientOcclusionMeta,
+        Light2dAmbientOcclusionPipeline, AMBIENT_OCCLUSION_SHADER_HANDLE,
+    },
+    fog_of_war::{
+        extract_fogs_of_war, map_fog_of_war_readbacks, prepare_fog_of_war_readbacks,
+        queue_fogs_of_war, DrawFogOfWar, FogOfWarMeta, FogOfWarPipeline, FogOfWarReadback,
+        FogOfWarReadbackBuffers, FOG_OF_WAR_SHADER_HANDLE,
+    },
+    gi::{
+        extract_emissive_sprites, prepare_emissive_sprites, prepare_gi_textures,
+        queue_gi_bind_groups, ExtractedEmissiveSprites, Light2dGiMeta, Light2dGiPipeline,
+        GI_EMISSION_SHADER_HANDLE, GI_SHADER_HANDLE,
+    },
+    graph::{self, prepare_light_overlay_textures, Light2dPassNode},
+    light::{
+        Light2dImageBindGroups, Light2dPipeline, Light2dShading, Light2dUniform,
+        LIGHT_SHADER_HANDLE,
+    },
     overlay::{
-        DrawOverlay, Light2dOverlayPipeline, OverlayImageBindGroups, OverlayMeta,
-        OVERLAY_SHADER_HANDLE, queue_light_overlay_bind_group,
+        queue_light_overlay_bind_group, DrawOverlay, Light2dOverlayPipeline,
+        OverlayImageBindGroups, OverlayMeta, OVERLAY_SHADER_HANDLE,
+    },
+    sdf::{
+        extract_sprite_occluders, prepare_sdf_occluders, prepare_sdf_textures,
+        queue_sdf_bind_groups, ExtractedSpriteOccluders, Light2dSdfMeta, Light2dSdfPipeline,
+        SDF_FLOOD_SHADER_HANDLE, SDF_OCCLUDER_SHADER_HANDLE, SDF_SHADER_HANDLE,
+    },
+    shadow::{
+        extract_shadows, prepare_shadow_edges, ExtractedShadows2d, Shadow2dEdgeTexture,
+        Shadow2dPipeline, SHADOW_SHADER_HANDLE,
     },
 };
-use render::{DrawLight, LightMeta};
+use render::{DrawLight, DrawVolumetricLight, LightMeta};
 
 #[derive(Default)]
 pub struct Light2dPlugin;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
 pub enum LightSystem {
+    AnimateLights,
     ExtractLights,
+    QueueSdfBindGroups,
 }
 
 impl Plugin for Light2dPlugin {
     fn build(&self, app: &mut App) {
-        let mut shaders = app.world.resource_mut::<Assets<Shader>>();
-        let light_shader = Shader::from_wgsl(include_str!("render/light.wgsl"));
-        shaders.set_untracked(LIGHT_SHADER_HANDLE, light_shader);
-        let overlay_shader = Shader::from_wgsl(include_str!("render/overlay.wgsl"));
-        shaders.set_untracked(OVERLAY_SHADER_HANDLE, overlay_shader);
-        // let shadow_shader = Shader::from_wgsl(include_str!("render/shadow.wgsl"));
-        // shaders.set_untracked(SHADOW_SHADER_HANDLE, shadow_shader);
+        // Headless apps, e.g. a dedicated server using `Light2dQuery`, have no shaders.
+        if let Some(mut shaders) = app.world.get_resource_mut::<Assets<Shader>>() {
+            let light_shader = Shader::from_wgsl(include_str!("render/light.wgsl"));
+            shaders.set_untracked(LIGHT_SHADER_HANDLE, light_shader);
+            let shadow_shader = Shader::from_wgsl(include_str!("render/shadow.wgsl"));
+            shaders.set_untracked(SHADOW_SHADER_HANDLE, shadow_shader);
+            let overlay_shader = Shader::from_wgsl(include_str!("render/overlay.wgsl"));
+            shaders.set_untracked(OVERLAY_SHADER_HANDLE, overlay_shader);
+            let occluder_shader = Shader::from_wgsl(include_str!("render/sdf_occluder.wgsl"));
+            shaders.set_untracked(SDF_OCCLUDER_SHADER_HANDLE, occluder_shader);
+            let flood_shader = Shader::from_wgsl(include_str!("render/sdf_flood.wgsl"));
+            shaders.set_untracked(SDF_FLOOD_SHADER_HANDLE, flood_shader);
+            let sdf_shader = Shader::from_wgsl(include_str!("render/sdf.wgsl"));
+            shaders.set_untracked(SDF_SHADER_HANDLE, sdf_shader);
+            let gi_shader = Shader::from_wgsl(include_str!("render/gi.wgsl"));
+            shaders.set_untracked(GI_SHADER_HANDLE, gi_shader);
+            let emission_shader = Shader::from_wgsl(include_str!("render/gi_emission.wgsl"));
+            shaders.set_untracked(GI_EMISSION_SHADER_HANDLE, emission_shader);
+            let ao_shader = Shader::from_wgsl(include_str!("render/ambient_occlusion.wgsl"));
+            shaders.set_untracked(AMBIENT_OCCLUSION_SHADER_HANDLE, ao_shader);
+            let fog_shader = Shader::from_wgsl(include_str!("render/fog_of_war.wgsl"));
+            shaders.set_untracked(FOG_OF_WAR_SHADER_HANDLE, fog_shader);
+        }
+
+        register_types(app);
+        // Falloff curves and presets are loaded by the `AssetPlugin`, which headless apps may
+        // leave out.
+        if app.world.contains_resource::<AssetServer>() {
+            app.add_asset::<Light2dFalloff>()
+                .init_asset_loader::<Light2dFalloffLoader>()
+                .init_asset_loader::<Light2dFalloffGradientLoader>()
+                .add_asset::<LightPreset>()
+                .init_asset_loader::<LightPresetLoader>()
+                .add_system(apply_light_presets.before(LightSystem::AnimateLights));
+        }
+        app.add_plugin(UniformComponentPlugin::<Light2dUniform>::default())
+            .add_plugin(RenderAssetPlugin::<Light2dFalloff>::default())
+            .add_system(init_light_animations.before(LightSystem::AnimateLights))
+            .add_system(animate_lights.label(LightSystem::AnimateLights))
+            .add_system(animate_light_cookies.label(LightSystem::AnimateLights))
+            .add_event::<LightSensor2dEvent>()
+            .add_system(update_light_sensors.after(LightSystem::AnimateLights))
+            .init_resource::<FogOfWarReadback>()
+            .add_system(update_fog_of_war)
+            .add_system(update_composite_shadows)
+            .add_system(finish_lightmap_bakes)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                bake_lightmaps.after(TransformSystem::TransformPropagate),
+            );
+
+        // Meshes come with the render plugins, headless apps don't have them.
+        if app.world.contains_resource::<Assets<Mesh>>() {
+            app.add_system(update_shadows_from_meshes);
+        }
 
-        app.register_type::<PointLight2d>()
-            .add_plugin(UniformComponentPlugin::<Light2dUniform>::default());
+        let shading = app
+            .world
+            .get_resource::<Light2dShading>()
+            .copied()
+            .unwrap_or_default();
+        let fog_of_war_readback = app.world.resource::<FogOfWarReadback>().clone();
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
+                .insert_resource(shading)
                 .init_resource::<Light2dPipeline>()
                 .init_resource::<SpecializedRenderPipelines<Light2dPipeline>>()
+                .init_resource::<Shadow2dPipeline>()
+                .init_resource::<SpecializedRenderPipelines<Shadow2dPipeline>>()
                 .init_resource::<LightMeta>()
+                .init_resource::<Light2dImageBindGroups>()
+                .init_resource::<ExtractedShadows2d>()
+                .init_resource::<Shadow2dEdgeTexture>()
                 .add_render_command::<Transparent2d, DrawLight>()
+                .add_render_command::<Transparent2d, DrawVolumetricLight>()
+                .add_system_to_stage(
+                    RenderStage::Extract,
+                    extract_shadows.before(LightSystem::ExtractLights),
+                )
                 .add_system_to_stage(
                     RenderStage::Extract,
                     render::extract_lights.label(LightSystem::ExtractLights),
                 )
+                .add_system_to_stage(RenderStage::Prepare, prepare_shadow_edges)
                 .add_system_to_stage(RenderStage::Queue, render::queue_light_bind_group)
-                .add_system_to_stage(RenderStage::Queue, render::queue_lights)
+                .add_system_to_stage(
+                    RenderStage::Queue,
+                    render::queue_lights.after(LightSystem::QueueSdfBindGroups),
+                )
+                //
+                .init_resource::<Light2dSdfPipeline>()
+                .init_resource::<Light2dSdfMeta>()
+                .init_resource::<ExtractedSpriteOccluders>()
+                .add_system_to_stage(RenderStage::Extract, extract_sprite_occluders)
+                .add_system_to_stage(RenderStage::Prepare, prepare_sdf_occluders)
+                .add_system_to_stage(RenderStage::Prepare, prepare_sdf_textures)
+                .add_system_to_stage(
+                    RenderStage::Queue,
+                    queue_sdf_bind_groups.label(LightSystem::QueueSdfBindGroups),
+                )
+                //
+                .init_resource::<Light2dGiPipeline>()
+                .init_resource::<SpecializedRenderPipelines<Light2dGiPipeline>>()
+                .init_resource::<Light2dGiMeta>()
+                .init_resource::<ExtractedEmissiveSprites>()
+                .add_system_to_stage(RenderStage::Extract, extract_emissive_sprites)
+                .add_system_to_stage(RenderStage::Prepare, prepare_emissive_sprites)
+                .add_system_to_stage(RenderStage::Prepare, prepare_gi_textures)
+                .add_system_to_stage(RenderStage::Queue, queue_gi_bind_groups)
+                //
+                .init_resource::<Light2dAmbientOcclusionPipeline>()
+                .init_resource::<SpecializedRenderPipelines<Light2dAmbientOcclusionPipeline>>()
+                .init_resource::<Light2dAmbientOcclusionMeta>()
+                .add_system_to_stage(RenderStage::Queue, queue_ambient_occlusion_bind_groups)
+                //
+                .insert_resource(fog_of_war_readback)
+                .init_resource::<FogOfWarReadbackBuffers>()
+                .init_resource::<FogOfWarPipeline>()
+                .init_resource::<SpecializedRenderPipelines<FogOfWarPipeline>>()
+                .init_resource::<FogOfWarMeta>()
+                .add_render_command::<Transparent2d, DrawFogOfWar>()
+                .add_system_to_stage(RenderStage::Extract, extract_fogs_of_war)
+                .add_system_to_stage(RenderStage::Prepare, prepare_fog_of_war_readbacks)
+                .add_system_to_stage(RenderStage::Queue, queue_fogs_of_war)
+                .add_system_to_stage(RenderStage::Cleanup, map_fog_of_war_readbacks)
                 //
                 .init_resource::<OverlayImageBindGroups>()
                 .init_resource::<Light2dOverlayPipeline>()
@@ -61,7 +222,249 @@ impl Plugin for Light2dPlugin {
                 .init_resource::<OverlayMeta>()
                 .add_render_command::<Transparent2d, DrawOverlay>()
                 .add_system_to_stage(RenderStage::Extract, render::extract_cameras)
-                .add_system_to_stage(RenderStage::Queue, queue_light_overlay_bind_group);
+                .add_system_to_stage(RenderStage::Queue, queue_light_overlay_bind_group)
+                .add_system_to_stage(RenderStage::Prepare, prepare_light_overlay_textures);
+
+            let light_pass_node = Light2dPassNode::new(&mut render_app.world);
+            let mut light_graph = RenderGraph::default();
+            light_graph.add_node(graph::node::LIGHT_PASS, light_pass_node);
+            let input_node_id = light_graph.set_input(vec![SlotInfo::new(
+                graph::input::VIEW_ENTITY,
+                SlotType::Entity,
+            )]);
+            light_graph
+                .add_slot_edge(
+                    input_node_id,
+                    graph::input::VIEW_ENTITY,
+                    graph::node::LIGHT_PASS,
+                    Light2dPassNode::IN_VIEW,
+                )
+                .unwrap();
+            render_app
+                .world
+                .resource_mut::<RenderGraph>()
+                .add_sub_graph(graph::NAME, light_graph);
         };
     }
 }
+
+/// Registers every reflected type of the crate, so scenes can save and load them.
+fn register_types(app: &mut App) {
+    app.register_type::<PointLight2d>()
+        .register_type::<Option<Handle<Light2dFalloff>>>()
+        .register_type::<Shadow2d>()
+        .register_type::<Light2dVolumetric>()
+        .register_type::<Shadow2dFromMesh>()
+        .register_type::<CompositeShadow2d>()
+        .register_type::<CompositeShadow2dPart>()
+        .register_type::<MergedShadow2d>()
+        .register_type::<SpriteOccluder2d>()
+        .register_type::<Emissive2d>()
+        .register_type::<Vec<Vec2>>()
+        .register_type::<Light2dFlicker>()
+        .register_type::<Light2dPulse>()
+        .register_type::<Light2dColorKey>()
+        .register_type::<Vec<Light2dColorKey>>()
+        .register_type::<Light2dColorGradient>()
+        .register_type::<Light2dCurveKey>()
+        .register_type::<Vec<Light2dCurveKey>>()
+        .register_type::<Light2dCurve>()
+        .register_type::<Light2dFadeIn>()
+        .register_type::<Light2dFadeOut>()
+        .register_type::<Light2dAnimationBase>()
+        .register_type::<Light2dCookie>()
+        .register_type::<Vec<usize>>()
+        .register_type::<LightSensor2d>()
+        .register_type::<Vec<f32>>()
+        .register_type::<VisionLight2d>()
+        .register_type::<FogOfWarExplored>()
+        .register_type::<Vec<u8>>()
+        .register_type::<FogOfWar>()
+        .register_type::<Static>()
+        .register_type::<Lightmap2d>();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::HandleId,
+        ecs::{entity::EntityMap, system::SystemState},
+        scene::{serde::SceneDeserializer, DynamicScene},
+        utils::HashSet,
+    };
+    use serde::de::DeserializeSeed;
+
+    use super::*;
+
+    #[test]
+    fn components_round_trip_through_scenes() {
+        let mut app = App::new();
+        app.register_type::<Color>()
+            .register_type::<Vec2>()
+            .register_type::<UVec2>()
+            .register_type::<HandleId>()
+            .register_type::<Handle<Image>>()
+            .register_type::<Handle<TextureAtlas>>()
+            .register_type::<Handle<Light2dFalloff>>();
+        register_types(&mut app);
+
+        let keys = vec![
+            Light2dColorKey {
+                time: 0.0,
+                color: Color::ORANGE,
+            },
+            Light2dColorKey {
+                time: 1.5,
+                color: Color::rgba(0.2, 0.4, 0.6, 0.8),
+            },
+        ];
+        let mut animation_base = Light2dAnimationBase::default();
+        animation_base.color = Color::CYAN;
+        app.world.spawn((
+            PointLight2d {
+                color: Color::rgba(0.9, 0.7, 0.3, 0.75),
+                intensity: 2.5,
+                inner_radius: 0.25,
+                falloff: Some(Handle::weak(HandleId::random::<Light2dFalloff>())),
+                ..default()
+            },
+            Light2dVolumetric {
+                density: 0.25,
+                decay: 2.0,
+            },
+            Light2dCookie {
+                frames: vec![2, 0, 1],
+                scroll: Vec2::new(0.5, -0.25),
+                ..Light2dCookie::new(Handle::weak(HandleId::random::<TextureAtlas>()))
+            },
+            Static,
+            VisionLight2d,
+            Light2dFlicker::new(7, 12.0, 0.2),
+            Light2dPulse::new(0.5, 0.75),
+            Light2dColorGradient::new(keys, false),
+            Light2dCurve::muzzle_flash(0.3),
+            Light2dFadeIn::new(1.0),
+            Light2dFadeOut::new(2.0),
+            animation_base,
+            LightSensor2d::new(vec![0.1, 0.5, 0.9]),
+        ));
+        app.world.spawn((
+            Shadow2d {
+                closed: true,
+                points: vec![Vec2::ZERO, Vec2::X, Vec2::ONE],
+                opacity: 0.5,
+                tint: Color::GREEN,
+                ..default()
+            },
+            Shadow2dFromMesh,
+            CompositeShadow2d::default(),
+            CompositeShadow2dPart,
+            MergedShadow2d,
+            SpriteOccluder2d {
+                alpha_threshold: 0.25,
+            },
+            Emissive2d {
+                color: Color::RED,
+                intensity: 3.0,
+            },
+        ));
+        // Some fields of the fog of war are private.
+        let mut fog_of_war = FogOfWar::default();
+        fog_of_war.origin = Vec2::new(-8.0, 4.0);
+        fog_of_war.size = UVec2::new(4, 2);
+        fog_of_war.explored = FogOfWarExplored::new(fog_of_war.size);
+        app.world.spawn((
+            fog_of_war,
+            Lightmap2d {
+                hdr: true,
+                ..Lightmap2d::from_image(
+                    Handle::weak(HandleId::random::<Image>()),
+                    Vec2::new(1.0, 2.0),
+                    UVec2::new(16, 8),
+                    0.5,
+                )
+            },
+        ));
+
+        let registry = app.world.resource::<AppTypeRegistry>().clone();
+        let scene = DynamicScene::from_world(&app.world, &registry);
+        let ron = scene.serialize_ron(&registry).unwrap();
+        let mut deserializer = ron::de::Deserializer::from_str(&ron).unwrap();
+        let loaded = SceneDeserializer {
+            type_registry: &registry.read(),
+        }
+        .deserialize(&mut deserializer)
+        .unwrap();
+        let mut world = World::new();
+        world.insert_resource(registry.clone());
+        let mut entity_map = EntityMap::default();
+        loaded.write_to_world(&mut world, &mut entity_map).unwrap();
+
+        let registry = registry.read();
+        let mut compared = HashSet::default();
+        for dynamic_entity in &scene.entities {
+            let original = Entity::from_raw(dynamic_entity.entity);
+            let entity = entity_map.get(original).unwrap();
+            for component in &dynamic_entity.components {
+                let type_name = component.type_name();
+                let reflect_component = registry
+                    .get_with_name(type_name)
+                    .and_then(|registration| registration.data::<ReflectComponent>())
+                    .unwrap();
+                let expected = reflect_component.reflect(&app.world, original).unwrap();
+                let actual = reflect_component.reflect(&world, entity).unwrap();
+                assert_eq!(
+                    expected.reflect_partial_eq(actual),
+                    Some(true),
+                    "{type_name} changed going through\n{ron}"
+                );
+                compared.insert(type_name.to_string());
+            }
+        }
+
+        // Every component of the crate is covered.
+        let prefix = concat!(env!("CARGO_CRATE_NAME"), "::");
+        for registration in registry.iter() {
+            if registration.data::<ReflectComponent>().is_some()
+                && registration.type_name().starts_with(prefix)
+            {
+                assert!(
+                    compared.contains(registration.type_name()),
+                    "{} isn't tested",
+                    registration.type_name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn light_levels_are_queried_headless() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(Light2dPlugin);
+        let light = TransformBundle::from_transform(
+            Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(100.0, 100.0, 1.0)),
+        );
+        app.world.spawn((PointLight2d::default(), light.clone()));
+        // Hidden lights don't count, the same as for the renderer.
+        app.world.spawn((
+            PointLight2d::default(),
+            light.clone(),
+            ComputedVisibility::INVISIBLE,
+        ));
+        app.world.spawn((
+            PointLight2d::default(),
+            TransformBundle::from_transform(Transform {
+                translation: Vec3::new(500.0, 0.0, 0.0),
+                ..light.local
+            }),
+        ));
+        app.update();
+
+        let mut state = SystemState::<Light2dQuery>::new(&mut app.world);
+        let query = state.get_mut(&mut app.world);
+        assert_eq!(query.light_level(Vec2::new(10.0, 0.0)), 1.0);
+        assert_eq!(query.light_level(Vec2::new(200.0, 0.0)), 0.0);
+    }
+}
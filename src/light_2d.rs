@@ -1,31 +1,302 @@
+use std::fmt;
+
 use bevy::{
-    prelude::{Color, Component, Vec2},
+    prelude::{Color, Component, Handle, ReflectComponent, ReflectDefault, Vec2, Vec3, Vec4},
     reflect::{Reflect, TypeUuid},
 };
 
+use crate::Light2dFalloff;
+
+/// A light drawn as a unit quad scaled by its transform.
+///
+/// Use [`PointLight2d::builder`] to have the parameters checked against their ranges.
+/// Out-of-range values are clamped when the light is extracted for rendering.
 #[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
 #[repr(C)]
 pub struct PointLight2d {
+    /// Tint. Its alpha is how strongly the light covers what is below it in the light texture.
     pub color: Color,
+    /// Multiplies the tint, `0.0` or more. Values above `1.0` need a floating point light
+    /// texture, e.g. `Rgba16Float`, to not be clipped.
+    pub intensity: f32,
+    /// Shape of the built-in falloff, in `0.0..=1.0` from soft to sharp.
     pub falloff_intensity: f32,
+    /// In `0.0..=outer_angle`.
     pub inner_angle: f32,
+    /// In `inner_angle..=1.0`. `inner_angle == outer_angle` lights the full circle.
     pub outer_angle: f32,
+    /// Fraction of the radius lit at full brightness, in `0.0..=1.0`.
     pub inner_radius: f32,
+    /// Custom falloff curve, loaded from a `.falloff.ron` curve or a `.falloff.png` gradient. When
+    /// set, `falloff_intensity` is ignored.
+    pub falloff: Option<Handle<Light2dFalloff>>,
+    /// How much [`Shadow2d`] casters darken this light, in `0.0..=1.0`. `0.0` ignores them.
+    pub shadow_intensity: f32,
+}
+
+/// Smallest radius and angle span the shader divides by, so the attenuation never becomes
+/// infinite.
+const MIN_SPAN: f32 = 1.0 / 1024.0;
+
+impl PointLight2d {
+    pub fn builder() -> PointLight2dBuilder {
+        PointLight2dBuilder {
+            light: Self::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Light2dError> {
+        let [_, _, _, alpha] = self.color.as_rgba_f32();
+        for (field, value) in [
+            ("color.a", alpha),
+            ("falloff_intensity", self.falloff_intensity),
+            ("inner_angle", self.inner_angle),
+            ("outer_angle", self.outer_angle),
+            ("inner_radius", self.inner_radius),
+            ("shadow_intensity", self.shadow_intensity),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(Light2dError::OutOfRange { field, value });
+            }
+        }
+        if !(self.intensity >= 0.0 && self.intensity.is_finite()) {
+            return Err(Light2dError::InvalidIntensity(self.intensity));
+        }
+        if self.inner_angle > self.outer_angle {
+            return Err(Light2dError::InnerAngleAboveOuter {
+                inner_angle: self.inner_angle,
+                outer_angle: self.outer_angle,
+            });
+        }
+        Ok(())
+    }
+
+    /// A copy with every parameter clamped to its range, `NaN` being replaced by the default.
+    pub fn sanitized(&self) -> Self {
+        let default = Self::default();
+        let clamp = |value: f32, min: f32, max: f32, default: f32| {
+            if value.is_nan() {
+                default
+            } else {
+                value.clamp(min, max)
+            }
+        };
+        let mut color = self.color;
+        color.set_a(clamp(color.a(), 0.0, 1.0, 1.0));
+        let outer_angle = clamp(self.outer_angle, 0.0, 1.0, default.outer_angle);
+        Self {
+            color,
+            intensity: if self.intensity.is_finite() {
+                self.intensity.max(0.0)
+            } else {
+                default.intensity
+            },
+            falloff_intensity: clamp(self.falloff_intensity, 0.0, 1.0, default.falloff_intensity),
+            inner_angle: clamp(self.inner_angle, 0.0, outer_angle, default.inner_angle),
+            outer_angle,
+            inner_radius: clamp(self.inner_radius, 0.0, 1.0, default.inner_radius),
+            falloff: self.falloff.clone(),
+            shadow_intensity: clamp(self.shadow_intensity, 0.0, 1.0, default.shadow_intensity),
+        }
+    }
+
+    /// Linear tint times `intensity`, with the tint's alpha.
+    pub fn linear_color(&self) -> Vec4 {
+        let [r, g, b, a] = self.color.as_linear_rgba_f32();
+        Vec4::new(
+            r * self.intensity,
+            g * self.intensity,
+            b * self.intensity,
+            a,
+        )
+    }
+
+    /// Fraction of the light left, per linear channel, after passing casters that let
+    /// `transmission` through.
+    pub fn shadow_visibility(&self, transmission: Vec3) -> Vec3 {
+        Vec3::ONE - self.shadow_intensity * (Vec3::ONE - transmission)
+    }
+
+    /// `1 / (1 - inner_radius)`, finite even for `inner_radius == 1.0`.
+    pub fn inner_radius_mult(&self) -> f32 {
+        1.0 / (1.0 - self.inner_radius).max(MIN_SPAN)
+    }
+
+    /// `1 / (outer_angle - inner_angle)`, finite even for equal angles.
+    pub fn inner_angle_mult(&self) -> f32 {
+        1.0 / (self.outer_angle - self.inner_angle).max(MIN_SPAN)
+    }
 }
 
 impl Default for PointLight2d {
     fn default() -> Self {
         Self {
             color: Color::WHITE,
+            intensity: 1.0,
             falloff_intensity: 1.0,
             inner_angle: 1.0,
             outer_angle: 1.0,
             inner_radius: 1.0,
+            falloff: None,
+            shadow_intensity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light2dError {
+    OutOfRange { field: &'static str, value: f32 },
+    InnerAngleAboveOuter { inner_angle: f32, outer_angle: f32 },
+    InvalidIntensity(f32),
+}
+
+impl fmt::Display for Light2dError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Light2dError::OutOfRange { field, value } => {
+                write!(f, "`{field}` is {value}, expected a value in 0.0..=1.0")
+            }
+            Light2dError::InnerAngleAboveOuter {
+                inner_angle,
+                outer_angle,
+            } => write!(
+                f,
+                "`inner_angle` ({inner_angle}) is larger than `outer_angle` ({outer_angle})"
+            ),
+            Light2dError::InvalidIntensity(intensity) => {
+                write!(
+                    f,
+                    "`intensity` is {intensity}, expected a finite value of 0.0 or more"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Light2dError {}
+
+/// Builds a [`PointLight2d`], starting from the defaults, and checks it with
+/// [`PointLight2d::validate`].
+#[derive(Debug, Clone)]
+pub struct PointLight2dBuilder {
+    light: PointLight2d,
+}
+
+impl PointLight2dBuilder {
+    /// Its alpha, in `0.0..=1.0`, is how strongly the light covers what is below it.
+    pub fn color(mut self, color: Color) -> Self {
+        self.light.color = color;
+        self
+    }
+
+    /// Finite and `0.0` or more.
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.light.intensity = intensity;
+        self
+    }
+
+    /// Sets the tint to the color of a black body at `kelvin`, keeping its alpha.
+    pub fn temperature_kelvin(mut self, kelvin: f32) -> Self {
+        let alpha = self.light.color.a();
+        self.light.color = color_temperature(kelvin);
+        self.light.color.set_a(alpha);
+        self
+    }
+
+    /// In `0.0..=1.0`, from soft to sharp.
+    pub fn falloff_intensity(mut self, falloff_intensity: f32) -> Self {
+        self.light.falloff_intensity = falloff_intensity;
+        self
+    }
+
+    /// Both in `0.0..=1.0`, with `inner_angle <= outer_angle`. Equal angles light the full
+    /// circle.
+    pub fn angles(mut self, inner_angle: f32, outer_angle: f32) -> Self {
+        self.light.inner_angle = inner_angle;
+        self.light.outer_angle = outer_angle;
+        self
+    }
+
+    /// Fraction of the radius lit at full brightness, in `0.0..=1.0`.
+    pub fn inner_radius(mut self, inner_radius: f32) -> Self {
+        self.light.inner_radius = inner_radius;
+        self
+    }
+
+    /// Replaces the built-in falloff, `falloff_intensity` is then ignored.
+    pub fn falloff(mut self, falloff: Handle<Light2dFalloff>) -> Self {
+        self.light.falloff = Some(falloff);
+        self
+    }
+
+    /// In `0.0..=1.0`, `0.0` ignores [`Shadow2d`] casters.
+    pub fn shadow_intensity(mut self, shadow_intensity: f32) -> Self {
+        self.light.shadow_intensity = shadow_intensity;
+        self
+    }
+
+    pub fn build(self) -> Result<PointLight2d, Light2dError> {
+        self.light.validate()?;
+        Ok(self.light)
+    }
+}
+
+/// The color of a black body at `kelvin`, clamped to `1000.0..=40000.0`: about 1900 for a
+/// candle, 2700 for an incandescent bulb, 5500 for daylight and 6500 for an overcast sky.
+///
+/// Uses Tanner Helland's fit of the CIE 1964 black body colors.
+pub fn color_temperature(kelvin: f32) -> Color {
+    let temperature = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let red = if temperature <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (temperature - 60.0).powf(-0.13320476)
+    };
+    let green = if temperature <= 66.0 {
+        99.4708 * temperature.ln() - 161.11957
+    } else {
+        288.12216 * (temperature - 60.0).powf(-0.07551485)
+    };
+    let blue = if temperature >= 66.0 {
+        255.0
+    } else if temperature <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (temperature - 10.0).ln() - 305.0448
+    };
+    Color::rgb(
+        (red / 255.0).clamp(0.0, 1.0),
+        (green / 255.0).clamp(0.0, 1.0),
+        (blue / 255.0).clamp(0.0, 1.0),
+    )
+}
+
+/// Light shafts for the [`PointLight2d`] on the same entity: light scattered along the rays from
+/// the light reaches into the shadows behind [`Shadow2d`] casters, e.g. through windows and
+/// tree canopies. The light's shadows are first drawn into a mask texture of the view, which a
+/// second pass of the light radially blurs towards the light into the same light texture.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Light2dVolumetric {
+    /// Brightness of the shafts, `0.0` or more.
+    pub density: f32,
+    /// How quickly the blur samples weigh less away from the light, per light radius. `0.0` or
+    /// more.
+    pub decay: f32,
+}
+
+impl Default for Light2dVolumetric {
+    fn default() -> Self {
+        Self {
+            density: 0.5,
+            decay: 4.0,
         }
     }
 }
 
 #[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
 #[repr(C)]
 // #[derive(Debug, TypeUuid, Clone)]
 // #[uuid = "b7e962fa-c102-4369-ac86-ea026a9aa1b3"]
@@ -33,4 +304,232 @@ pub struct Shadow2d {
     pub closed: bool,
     // cull_mode
     pub points: Vec<Vec2>,
+    /// How much light the caster blocks, in `0.0..=1.0`, e.g. lower for fences and foliage.
+    pub opacity: f32,
+    /// Color filter applied to the light passing through, e.g. for stained glass.
+    pub tint: Color,
+    /// How much of the blocked light passes through filtered by `tint`, in `0.0..=1.0`.
+    pub transmission: f32,
+}
+
+impl Shadow2d {
+    /// Fraction of the light, per linear channel, that gets through the caster.
+    pub fn transmittance(&self) -> Vec3 {
+        let [r, g, b, _] = self.tint.as_linear_rgba_f32();
+        let filtered =
+            Vec3::new(r, g, b).clamp(Vec3::ZERO, Vec3::ONE) * self.transmission.clamp(0.0, 1.0);
+        Vec3::ONE - self.opacity.clamp(0.0, 1.0) * (Vec3::ONE - filtered)
+    }
+}
+
+impl Default for Shadow2d {
+    fn default() -> Self {
+        Self {
+            closed: false,
+            points: Vec::new(),
+            opacity: 1.0,
+            tint: Color::WHITE,
+            transmission: 0.0,
+        }
+    }
+}
+
+/// Makes the opaque pixels of the `Sprite` on the same entity occlude lights in
+/// [`Light2dShadowMode::Sdf`](crate::render::Light2dShadowMode::Sdf) views.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct SpriteOccluder2d {
+    /// Texels with a lower alpha let light through.
+    pub alpha_threshold: f32,
+}
+
+impl Default for SpriteOccluder2d {
+    fn default() -> Self {
+        Self {
+            alpha_threshold: 0.5,
+        }
+    }
+}
+
+/// Makes the `Sprite` on the same entity glow into its surroundings in views with
+/// [`Light2dGlobalIllumination`](crate::render::Light2dGlobalIllumination). The sprite itself
+/// isn't brightened.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Emissive2d {
+    /// Multiplied with the sprite's texture.
+    pub color: Color,
+    pub intensity: f32,
+}
+
+impl Default for Emissive2d {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_temperature_goes_from_red_to_blue() {
+        let candle = color_temperature(1900.0);
+        assert_eq!(candle.r(), 1.0);
+        assert!(candle.g() > candle.b());
+        assert!(candle.g() < 1.0);
+
+        // Around 6600 K the fit is white.
+        let white = color_temperature(6600.0);
+        for channel in [white.r(), white.g(), white.b()] {
+            assert!(channel > 0.99, "{white:?}");
+        }
+
+        let sky = color_temperature(20000.0);
+        assert_eq!(sky.b(), 1.0);
+        assert!(sky.r() < sky.b());
+    }
+
+    #[test]
+    fn color_temperature_is_clamped() {
+        assert_eq!(color_temperature(0.0), color_temperature(1000.0));
+        assert_eq!(color_temperature(1.0e6), color_temperature(40000.0));
+        assert_eq!(color_temperature(1000.0).b(), 0.0);
+    }
+
+    #[test]
+    fn linear_color_scales_the_tint_but_not_the_alpha() {
+        let light = PointLight2d {
+            color: Color::rgba_linear(1.0, 0.5, 0.0, 0.25),
+            intensity: 2.0,
+            ..PointLight2d::default()
+        };
+        assert_eq!(light.linear_color(), Vec4::new(2.0, 1.0, 0.0, 0.25));
+
+        // sRGB tints are converted first.
+        let light = PointLight2d {
+            color: Color::rgb(0.5, 0.5, 0.5),
+            intensity: 1.0,
+            ..PointLight2d::default()
+        };
+        let linear = light.linear_color();
+        assert!((linear.x - 0.214).abs() < 0.001, "{linear}");
+        assert_eq!(linear.w, 1.0);
+    }
+
+    #[test]
+    fn validate_names_the_invalid_field() {
+        assert_eq!(PointLight2d::default().validate(), Ok(()));
+        let light = PointLight2d {
+            falloff_intensity: 1.5,
+            ..PointLight2d::default()
+        };
+        assert_eq!(
+            light.validate(),
+            Err(Light2dError::OutOfRange {
+                field: "falloff_intensity",
+                value: 1.5
+            })
+        );
+        let light = PointLight2d {
+            color: Color::rgba(1.0, 1.0, 1.0, -0.5),
+            ..PointLight2d::default()
+        };
+        assert_eq!(
+            light.validate(),
+            Err(Light2dError::OutOfRange {
+                field: "color.a",
+                value: -0.5
+            })
+        );
+        let light = PointLight2d {
+            intensity: -1.0,
+            ..PointLight2d::default()
+        };
+        assert_eq!(light.validate(), Err(Light2dError::InvalidIntensity(-1.0)));
+        let light = PointLight2d {
+            inner_angle: 0.5,
+            outer_angle: 0.25,
+            ..PointLight2d::default()
+        };
+        assert_eq!(
+            light.validate(),
+            Err(Light2dError::InnerAngleAboveOuter {
+                inner_angle: 0.5,
+                outer_angle: 0.25
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_nan_and_infinity() {
+        let light = PointLight2d {
+            inner_radius: f32::NAN,
+            ..PointLight2d::default()
+        };
+        assert!(matches!(
+            light.validate(),
+            Err(Light2dError::OutOfRange { field: "inner_radius", value }) if value.is_nan()
+        ));
+        let light = PointLight2d {
+            shadow_intensity: f32::NAN,
+            ..PointLight2d::default()
+        };
+        assert!(matches!(
+            light.validate(),
+            Err(Light2dError::OutOfRange { field: "shadow_intensity", value }) if value.is_nan()
+        ));
+        for intensity in [f32::NAN, f32::INFINITY] {
+            let light = PointLight2d {
+                intensity,
+                ..PointLight2d::default()
+            };
+            assert!(matches!(
+                light.validate(),
+                Err(Light2dError::InvalidIntensity(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sanitized_lights_are_valid() {
+        let light = PointLight2d {
+            color: Color::rgba(1.0, 0.5, 0.0, 2.0),
+            intensity: -3.0,
+            falloff_intensity: -1.0,
+            inner_angle: 0.75,
+            outer_angle: 0.5,
+            inner_radius: 4.0,
+            shadow_intensity: f32::NAN,
+            ..PointLight2d::default()
+        }
+        .sanitized();
+        assert_eq!(light.validate(), Ok(()));
+        assert_eq!(light.color, Color::rgba(1.0, 0.5, 0.0, 1.0));
+        assert_eq!(light.intensity, 0.0);
+        assert_eq!(light.falloff_intensity, 0.0);
+        // The cone never opens wider at its inner edge than at its outer one.
+        assert_eq!((light.inner_angle, light.outer_angle), (0.5, 0.5));
+        assert_eq!(light.inner_radius, 1.0);
+        assert_eq!(light.shadow_intensity, 1.0);
+        // What the shader divides by stays finite.
+        assert!(light.inner_radius_mult().is_finite());
+        assert!(light.inner_angle_mult().is_finite());
+
+        let light = PointLight2d {
+            color: Color::rgba(1.0, 1.0, 1.0, f32::NAN),
+            intensity: f32::INFINITY,
+            inner_angle: f32::NAN,
+            outer_angle: f32::NAN,
+            ..PointLight2d::default()
+        }
+        .sanitized();
+        assert_eq!(light.validate(), Ok(()));
+        assert_eq!(light.color.a(), 1.0);
+        assert_eq!(light.intensity, 1.0);
+        assert_eq!((light.inner_angle, light.outer_angle), (1.0, 1.0));
+    }
 }
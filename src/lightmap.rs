@@ -0,0 +1,341 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use image::{
+    error::{
+        ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError, UnsupportedErrorKind,
+    },
+    ImageError, ImageFormat, RgbaImage,
+};
+
+use crate::{
+    util::f32_to_f16_bits, Light2dFalloff, Light2dReferenceRenderer, MergedShadow2d, PointLight2d,
+    ReferenceView2d, Shadow2d,
+};
+
+/// Marks a [`PointLight2d`] or [`Shadow2d`] that never moves, so it can be baked into a
+/// [`Lightmap2d`].
+///
+/// Static lights whose whole radius is covered by a baked lightmap are no longer drawn every
+/// frame. Static lights are
+/// only occluded by static casters.
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct Static;
+
+/// The combined contribution of every [`Static`] light over a region of the world, drawn into
+/// the light texture together with the dynamic lights. Spawn it with a `SpatialBundle` so it is
+/// visible to cameras.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct Lightmap2d {
+    /// World position of the bottom-left corner of the lightmap.
+    pub origin: Vec2,
+    pub size: UVec2,
+    pub texel_size: f32,
+    /// Multiplied with the baked light.
+    pub tint: Color,
+    pub image: Handle<Image>,
+    /// Set to (re)bake `image` at the end of the frame. The bake runs in the background and
+    /// the static lights keep being drawn until it is done, then it is cleared.
+    pub bake: bool,
+    /// Bake into an `Rgba16Float` image, which keeps light brighter than white. Otherwise the
+    /// bake is clamped into `Rgba8UnormSrgb`.
+    pub hdr: bool,
+}
+
+impl Lightmap2d {
+    /// A lightmap that is baked on the next frame.
+    pub fn new(images: &mut Assets<Image>, origin: Vec2, size: UVec2, texel_size: f32) -> Self {
+        let image = images.add(Image::new_fill(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        Self {
+            origin,
+            size,
+            texel_size,
+            tint: Color::WHITE,
+            image,
+            bake: true,
+            hdr: false,
+        }
+    }
+
+    /// A lightmap baked earlier, e.g. loaded from a PNG written by [`Self::save_png`].
+    pub fn from_image(image: Handle<Image>, origin: Vec2, size: UVec2, texel_size: f32) -> Self {
+        Self {
+            origin,
+            size,
+            texel_size,
+            tint: Color::WHITE,
+            image,
+            bake: false,
+            hdr: false,
+        }
+    }
+
+    /// World-space size of the lightmap.
+    pub fn world_size(&self) -> Vec2 {
+        self.size.as_vec2() * self.texel_size
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        let local = point - self.origin;
+        local.cmpge(Vec2::ZERO).all() && local.cmplt(self.world_size()).all()
+    }
+
+    /// Whether the whole circle lies inside the lightmap.
+    pub fn contains_circle(&self, center: Vec2, radius: f32) -> bool {
+        let local = center - self.origin;
+        (local - radius).cmpge(Vec2::ZERO).all() && (local + radius).cmple(self.world_size()).all()
+    }
+
+    pub fn texel_center(&self, texel: UVec2) -> Vec2 {
+        self.origin + (texel.as_vec2() + 0.5) * self.texel_size
+    }
+
+    /// Writes the baked image to `path` as a PNG, see [`save_png`]. Fails if the image isn't
+    /// loaded.
+    pub fn save_png(
+        &self,
+        images: &Assets<Image>,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ImageError> {
+        match images.get(&self.image) {
+            Some(image) => save_png(image, path),
+            None => Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::Generic("the lightmap image isn't loaded".to_string()),
+            ))),
+        }
+    }
+}
+
+/// Writes an `Rgba8UnormSrgb` image, such as a baked lightmap or a reference render, to `path`
+/// as a PNG. Other formats, e.g. of [`Lightmap2d::hdr`] lightmaps, aren't supported.
+pub fn save_png(image: &Image, path: impl AsRef<Path>) -> Result<(), ImageError> {
+    let format = image.texture_descriptor.format;
+    if !matches!(
+        format,
+        TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+    ) {
+        return Err(ImageError::Unsupported(
+            UnsupportedError::from_format_and_kind(
+                ImageFormatHint::Exact(ImageFormat::Png),
+                UnsupportedErrorKind::GenericFeature(format!("saving {format:?} images")),
+            ),
+        ));
+    }
+    let size = image.texture_descriptor.size;
+    let buffer =
+        RgbaImage::from_raw(size.width, size.height, image.data.clone()).ok_or_else(|| {
+            ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            ))
+        })?;
+    buffer.save_with_format(path, ImageFormat::Png)
+}
+
+/// Reads a PNG, e.g. one written by [`save_png`], as an `Rgba8UnormSrgb` image.
+pub fn load_png(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let buffer =
+        image::load_from_memory_with_format(&std::fs::read(path)?, ImageFormat::Png)?.to_rgba8();
+    Ok(Image::new(
+        Extent3d {
+            width: buffer.width(),
+            height: buffer.height(),
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        buffer.into_raw(),
+        TextureFormat::Rgba8UnormSrgb,
+    ))
+}
+
+impl Default for Lightmap2d {
+    fn default() -> Self {
+        Self::from_image(Handle::default(), Vec2::ZERO, UVec2::ZERO, 1.0)
+    }
+}
+
+/// A bake of a [`Lightmap2d`] running on the [`AsyncComputeTaskPool`].
+#[derive(Component)]
+pub struct Lightmap2dBake {
+    _task: Task<()>,
+    result: Arc<Mutex<Option<Image>>>,
+}
+
+/// Starts rendering every [`Static`] light, occluded by [`Static`] casters, into the lightmaps
+/// that have [`Lightmap2d::bake`] set. Runs after transform propagation so lights spawned this
+/// frame are already in place.
+///
+/// The lights are rendered like the light pass does, see [`Light2dReferenceRenderer`]. Each
+/// texel stores the color divided by its alpha, so that drawing the lightmap with alpha blending
+/// gives the same result as drawing the lights one by one.
+#[allow(clippy::type_complexity)]
+pub fn bake_lightmaps(
+    mut commands: Commands,
+    lightmaps: Query<(Entity, &Lightmap2d), Without<Lightmap2dBake>>,
+    lights: Query<(&PointLight2d, &GlobalTransform, Option<&Visibility>), With<Static>>,
+    casters: Query<(&Shadow2d, &GlobalTransform), (With<Static>, Without<MergedShadow2d>)>,
+    falloffs: Option<Res<Assets<Light2dFalloff>>>,
+) {
+    for (entity, lightmap) in &lightmaps {
+        if !lightmap.bake {
+            continue;
+        }
+        let lights = lights
+            .iter()
+            .filter(|(_, _, visibility)| {
+                visibility.map_or(true, |visibility| visibility.is_visible)
+            })
+            .map(|(light, transform, _)| (light.clone(), *transform))
+            .collect::<Vec<_>>();
+        let casters = casters
+            .iter()
+            .map(|(shadow, transform)| (shadow.clone(), *transform))
+            .collect::<Vec<_>>();
+        let falloffs = lights
+            .iter()
+            .filter_map(|(light, _)| {
+                let handle = light.falloff.as_ref()?;
+                let falloff = falloffs.as_ref()?.get(handle)?;
+                Some((handle.clone_weak(), falloff.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+        let view = ReferenceView2d {
+            center: lightmap.origin + lightmap.world_size() / 2.0,
+            size: lightmap.size,
+            scale: lightmap.texel_size,
+        };
+        let hdr = lightmap.hdr;
+
+        let result = Arc::new(Mutex::new(None));
+        let task_result = result.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let pixels = Light2dReferenceRenderer::default()
+                .render_linear(&view, &lights, &casters, &falloffs);
+            let image = lightmap_image(view.size, &pixels, hdr);
+            *task_result.lock().unwrap() = Some(image);
+        });
+        commands.entity(entity).insert(Lightmap2dBake {
+            _task: task,
+            result,
+        });
+    }
+}
+
+/// Replaces the images of the lightmaps whose bake is done.
+pub fn finish_lightmap_bakes(
+    mut commands: Commands,
+    mut lightmaps: Query<(Entity, &mut Lightmap2d, &Lightmap2dBake)>,
+    mut images: Option<ResMut<Assets<Image>>>,
+) {
+    for (entity, mut lightmap, bake) in &mut lightmaps {
+        let image = match bake.result.lock().unwrap().take() {
+            Some(image) => image,
+            None => continue,
+        };
+        if let Some(target) = images
+            .as_mut()
+            .and_then(|images| images.get_mut(&lightmap.image))
+        {
+            *target = image;
+        }
+        lightmap.bake = false;
+        commands.entity(entity).remove::<Lightmap2dBake>();
+    }
+}
+
+/// Encodes premultiplied light texture pixels as a lightmap image.
+fn lightmap_image(size: UVec2, pixels: &[Vec4], hdr: bool) -> Image {
+    let texels = pixels.iter().map(|pixel| {
+        let color = if pixel.w > 0.0 {
+            pixel.truncate() / pixel.w
+        } else {
+            Vec3::ZERO
+        };
+        Color::rgba_linear(color.x, color.y, color.z, pixel.w.min(1.0))
+    });
+    let (data, format) = if hdr {
+        let data = texels
+            .flat_map(|texel| texel.as_linear_rgba_f32().map(f32_to_f16_bits))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        (data, TextureFormat::Rgba16Float)
+    } else {
+        let data = texels
+            .flat_map(|texel| {
+                texel
+                    .as_rgba_f32()
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+        (data, TextureFormat::Rgba8UnormSrgb)
+    };
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circles_must_lie_inside_the_lightmap() {
+        let lightmap = Lightmap2d::from_image(
+            Handle::default(),
+            Vec2::new(-10.0, 0.0),
+            UVec2::new(20, 10),
+            1.0,
+        );
+        assert!(lightmap.contains_circle(Vec2::new(0.0, 5.0), 5.0));
+        assert!(lightmap.contains(Vec2::new(0.0, 8.0)));
+        assert!(!lightmap.contains_circle(Vec2::new(0.0, 8.0), 5.0));
+        assert!(!lightmap.contains_circle(Vec2::new(-8.0, 5.0), 5.0));
+    }
+
+    #[test]
+    fn lightmap_texels_are_unpremultiplied() {
+        let pixels = [Vec4::new(0.25, 0.0, 0.5, 0.5), Vec4::ZERO];
+        let image = lightmap_image(UVec2::new(2, 1), &pixels, true);
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Rgba16Float);
+        let texels = image
+            .data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(texels, [0x3800, 0, 0x3c00, 0x3800, 0, 0, 0, 0]);
+
+        let image = lightmap_image(UVec2::new(2, 1), &pixels, false);
+        assert_eq!(
+            image.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(image.data[3], 128);
+        assert_eq!(image.data[2], 255);
+        assert_eq!(&image.data[4..], &[0, 0, 0, 0]);
+    }
+}
@@ -52,10 +52,10 @@ fn setup(
 
     let parent = commands.spawn(Camera2dBundle::default()).id();
     let child = commands
-        .spawn(Light2dOverlay {
-            image: image_handle.clone(),
-            size: UVec2::new(size.width, size.height),
-        })
+        .spawn(Light2dOverlay::new(
+            image_handle.clone(),
+            UVec2::new(size.width, size.height),
+        ))
         .id();
     commands.entity(parent).push_children(&[child]);
 
@@ -87,6 +87,7 @@ fn setup(
                 inner_angle: 1.0,
                 outer_angle: 1.0,
                 inner_radius: 0.3,
+                ..default()
             },
         ));
     }
@@ -108,6 +109,7 @@ fn setup(
                 Vec2::new(0.0, 1.0),
                 Vec2::new(1.0, 1.0),
             ],
+            ..default()
         },
     ));
 }
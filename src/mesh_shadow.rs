@@ -0,0 +1,301 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{PrimitiveTopology, VertexAttributeValues},
+    sprite::Mesh2dHandle,
+    utils::{HashMap, HashSet},
+};
+
+use crate::Shadow2d;
+
+/// Keeps the [`Shadow2d`] of an entity in sync with the outer boundary of its `Mesh2dHandle`,
+/// inserting one if needed. Only the points of an existing caster are replaced, so its opacity
+/// and tint are kept.
+#[derive(Component, Debug, Clone, Default, Reflect)]
+#[reflect(Component, Default)]
+pub struct Shadow2dFromMesh;
+
+/// Outer boundary of a `TriangleList` mesh, in its local xy plane: the loop of edges belonging to
+/// a single triangle that encloses the largest area. Holes and separate islands are left out, and
+/// so are chains that don't close, e.g. where triangles are wound inconsistently.
+pub fn mesh_outline(mesh: &Mesh) -> Option<Vec<Vec2>> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return None;
+    }
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+        VertexAttributeValues::Float32x3(positions) => positions
+            .iter()
+            .map(|[x, y, _]| Vec2::new(*x, *y))
+            .collect::<Vec<_>>(),
+        VertexAttributeValues::Float32x2(positions) => {
+            positions.iter().map(|[x, y]| Vec2::new(*x, *y)).collect()
+        }
+        _ => return None,
+    };
+    let indices = match mesh.indices() {
+        Some(indices) => indices.iter().collect::<Vec<_>>(),
+        None => (0..positions.len()).collect(),
+    };
+
+    // Meshes often split vertices for uvs or normals, weld them back by position.
+    let mut welded = HashMap::default();
+    let mut points = Vec::new();
+    let mut vertex = |index: usize| -> Option<usize> {
+        let point = *positions.get(index)?;
+        // `+ 0.0` turns -0.0 into 0.0 so both get the same key.
+        let key = ((point.x + 0.0).to_bits(), (point.y + 0.0).to_bits());
+        Some(*welded.entry(key).or_insert_with(|| {
+            points.push(point);
+            points.len() - 1
+        }))
+    };
+
+    // Interior edges are shared by two triangles, boundary edges by one. The direction of the
+    // first triangle using an edge is kept so the outline follows the mesh winding.
+    let mut edges = HashMap::<(usize, usize), (usize, usize, u32)>::default();
+    for triangle in indices.chunks_exact(3) {
+        let corners = [
+            vertex(triangle[0])?,
+            vertex(triangle[1])?,
+            vertex(triangle[2])?,
+        ];
+        for i in 0..3 {
+            let (start, end) = (corners[i], corners[(i + 1) % 3]);
+            if start == end {
+                continue;
+            }
+            edges
+                .entry((start.min(end), start.max(end)))
+                .or_insert((start, end, 0))
+                .2 += 1;
+        }
+    }
+
+    let mut next = HashMap::<usize, Vec<usize>>::default();
+    for (start, end, count) in edges.values() {
+        if *count == 1 {
+            next.entry(*start).or_default().push(*end);
+        }
+    }
+
+    let mut best: Option<(f32, Vec<Vec2>)> = None;
+    for outline in trace_loops(&next, &points) {
+        let area = outline
+            .iter()
+            .enumerate()
+            .map(|(i, point)| point.perp_dot(outline[(i + 1) % outline.len()]))
+            .sum::<f32>()
+            .abs();
+        if best
+            .as_ref()
+            .map_or(true, |(best_area, _)| area > *best_area)
+        {
+            best = Some((area, outline));
+        }
+    }
+    best.map(|(_, outline)| outline)
+}
+
+/// The closed loops formed by the edges from each point to the points `next` lists, walking
+/// every edge once, as positions in `points`. Chains that don't close are left out.
+pub(crate) fn trace_loops(next: &HashMap<usize, Vec<usize>>, points: &[Vec2]) -> Vec<Vec<Vec2>> {
+    let mut visited = HashSet::default();
+    let mut loops = Vec::new();
+    let mut starts = next.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+    for first in starts {
+        while let Some(&second) = next
+            .get(&first)
+            .and_then(|ends| ends.iter().find(|end| !visited.contains(&(first, **end))))
+        {
+            let mut outline = vec![first];
+            let mut current = second;
+            let mut closed = true;
+            visited.insert((first, second));
+            while current != first {
+                outline.push(current);
+                let end = match next.get(&current).and_then(|ends| {
+                    ends.iter()
+                        .copied()
+                        .find(|end| !visited.contains(&(current, *end)))
+                }) {
+                    Some(end) => end,
+                    None => {
+                        closed = false;
+                        break;
+                    }
+                };
+                visited.insert((current, end));
+                current = end;
+            }
+            if closed {
+                loops.push(outline.into_iter().map(|point| points[point]).collect());
+            }
+        }
+    }
+    loops
+}
+
+/// Rebuilds the casters of [`Shadow2dFromMesh`] entities when the component is added, the mesh
+/// handle changes or the `Mesh` asset is (re)loaded.
+pub fn update_shadows_from_meshes(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    mut events: EventReader<AssetEvent<Mesh>>,
+    mut query: Query<
+        (
+            Entity,
+            &Mesh2dHandle,
+            Option<&mut Shadow2d>,
+            ChangeTrackers<Mesh2dHandle>,
+            ChangeTrackers<Shadow2dFromMesh>,
+        ),
+        With<Shadow2dFromMesh>,
+    >,
+) {
+    let mut updated = HashSet::default();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                updated.insert(handle.id());
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    for (entity, handle, shadow, mesh_tracker, from_mesh_tracker) in &mut query {
+        if !mesh_tracker.is_changed()
+            && !from_mesh_tracker.is_changed()
+            && !updated.contains(&handle.0.id())
+        {
+            continue;
+        }
+        let mesh = match meshes.get(&handle.0) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let points = match mesh_outline(mesh) {
+            Some(points) => points,
+            None => {
+                warn!("Shadow2dFromMesh on {entity:?} needs a triangle list mesh with positions");
+                continue;
+            }
+        };
+        match shadow {
+            Some(mut shadow) => {
+                shadow.closed = true;
+                shadow.points = points;
+            }
+            None => {
+                commands.entity(entity).insert(Shadow2d {
+                    closed: true,
+                    points,
+                    ..default()
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::Indices;
+
+    use super::*;
+
+    fn mesh(positions: &[[f32; 2]], indices: &[u32]) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            positions
+                .iter()
+                .map(|[x, y]| [*x, *y, 0.0])
+                .collect::<Vec<_>>(),
+        );
+        mesh.set_indices(Some(Indices::U32(indices.to_vec())));
+        mesh
+    }
+
+    fn area(outline: &[Vec2]) -> f32 {
+        outline
+            .iter()
+            .enumerate()
+            .map(|(i, point)| point.perp_dot(outline[(i + 1) % outline.len()]))
+            .sum::<f32>()
+            / 2.0
+    }
+
+    #[test]
+    fn quad_outline_follows_the_winding() {
+        let quad = mesh(
+            &[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+            &[0, 1, 2, 0, 2, 3],
+        );
+        let outline = mesh_outline(&quad).unwrap();
+        assert_eq!(outline.len(), 4);
+        assert_eq!(area(&outline), 1.0);
+    }
+
+    #[test]
+    fn split_vertices_are_welded() {
+        // Two triangles with their own copies of the shared corners, one of them at -0.0.
+        let quad = mesh(
+            &[
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [-0.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 1.0],
+            ],
+            &[0, 1, 2, 3, 4, 5],
+        );
+        let outline = mesh_outline(&quad).unwrap();
+        assert_eq!(outline.len(), 4);
+        assert_eq!(area(&outline), 1.0);
+    }
+
+    #[test]
+    fn holes_are_left_out() {
+        // A 3x3 square ring around a 1x1 hole.
+        let positions = [
+            [0.0, 0.0],
+            [3.0, 0.0],
+            [3.0, 3.0],
+            [0.0, 3.0],
+            [1.0, 1.0],
+            [2.0, 1.0],
+            [2.0, 2.0],
+            [1.0, 2.0],
+        ];
+        let mut indices = Vec::new();
+        for i in 0..4 {
+            let (outer, next_outer) = (i, (i + 1) % 4);
+            let (inner, next_inner) = (i + 4, (i + 1) % 4 + 4);
+            indices.extend_from_slice(&[outer, next_outer, next_inner]);
+            indices.extend_from_slice(&[outer, next_inner, inner]);
+        }
+        let outline = mesh_outline(&mesh(&positions, &indices)).unwrap();
+        assert_eq!(outline.len(), 4);
+        assert_eq!(area(&outline), 9.0);
+    }
+
+    #[test]
+    fn open_chains_are_discarded() {
+        // The second triangle is wound the other way, so no boundary chain closes.
+        let triangles = mesh(
+            &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]],
+            &[0, 1, 2, 1, 2, 3],
+        );
+        assert_eq!(mesh_outline(&triangles), None);
+    }
+
+    #[test]
+    fn only_triangle_lists_have_outlines() {
+        let mut lines = Mesh::new(PrimitiveTopology::LineList);
+        lines.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0]],
+        );
+        assert_eq!(mesh_outline(&lines), None);
+    }
+}
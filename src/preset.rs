@@ -0,0 +1,245 @@
+use bevy::{
+    asset::{AssetLoader, AssetPath, BoxedFuture, Error, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color_temperature, Light2dAnimationBase, Light2dColorGradient, Light2dColorKey, Light2dCurve,
+    Light2dFadeIn, Light2dFadeOut, Light2dFalloff, Light2dFlicker, Light2dPulse, PointLight2d,
+};
+
+/// A light description shared by every entity holding a `Handle<LightPreset>`, loaded from
+/// `.light2d.ron` files. Editing the file updates all of them when hot reloading is enabled.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "b3a1f6d2-52c4-4d8e-8f0a-7c9e4b1d2a63"]
+pub struct LightPreset {
+    #[serde(default)]
+    pub kind: LightPresetKind,
+    pub color: Color,
+    /// Replaces the rgb part of `color` with a black body color, see [`color_temperature`].
+    #[serde(default)]
+    pub temperature_kelvin: Option<f32>,
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    #[serde(default = "default_falloff_intensity")]
+    pub falloff_intensity: f32,
+    #[serde(default = "default_inner_radius")]
+    pub inner_radius: f32,
+    #[serde(default = "default_shadow_intensity")]
+    pub shadow_intensity: f32,
+    /// Path of a `.falloff.ron` curve or `.falloff.png` gradient, relative to the assets folder.
+    #[serde(default)]
+    pub falloff: Option<String>,
+    #[serde(default)]
+    pub animation: Option<LightPresetAnimation>,
+    #[serde(skip)]
+    pub falloff_handle: Option<Handle<Light2dFalloff>>,
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_falloff_intensity() -> f32 {
+    1.0
+}
+
+fn default_inner_radius() -> f32 {
+    1.0
+}
+
+fn default_shadow_intensity() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum LightPresetKind {
+    #[default]
+    Point,
+    /// A cone pointing along the light's local y axis, angles are fractions of a full turn as
+    /// in [`PointLight2d`].
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LightPresetAnimation {
+    Flicker {
+        #[serde(default)]
+        seed: u32,
+        frequency: f32,
+        amount: f32,
+    },
+    Pulse {
+        frequency: f32,
+        amount: f32,
+    },
+    Gradient {
+        keys: Vec<Light2dColorKey>,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+
+impl LightPreset {
+    pub fn point_light(&self) -> PointLight2d {
+        let (inner_angle, outer_angle) = match self.kind {
+            LightPresetKind::Point => (1.0, 1.0),
+            LightPresetKind::Spot {
+                inner_angle,
+                outer_angle,
+            } => (inner_angle, outer_angle),
+        };
+        let mut color = self.color;
+        if let Some(kelvin) = self.temperature_kelvin {
+            let alpha = color.a();
+            color = color_temperature(kelvin);
+            color.set_a(alpha);
+        }
+        PointLight2d {
+            color,
+            intensity: self.intensity,
+            falloff_intensity: self.falloff_intensity,
+            inner_angle,
+            outer_angle,
+            inner_radius: self.inner_radius,
+            falloff: self.falloff_handle.clone(),
+            shadow_intensity: self.shadow_intensity,
+        }
+    }
+}
+
+/// Loads [`LightPreset`]s from `.light2d.ron` files, along with their falloff curve.
+#[derive(Default)]
+pub struct LightPresetLoader;
+
+impl AssetLoader for LightPresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut preset = ron::de::from_bytes::<LightPreset>(bytes)?;
+            let mut dependencies = Vec::new();
+            if let Some(path) = &preset.falloff {
+                let path = AssetPath::from(path.as_str()).to_owned();
+                preset.falloff_handle = Some(load_context.get_handle(path.clone()));
+                dependencies.push(path);
+            }
+            load_context
+                .set_default_asset(LoadedAsset::new(preset).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["light2d.ron"]
+    }
+}
+
+/// Replaces the light and animation components of entities with a `Handle<LightPreset>` when
+/// the handle is set or the preset asset is (re)loaded.
+pub fn apply_light_presets(
+    mut commands: Commands,
+    presets: Res<Assets<LightPreset>>,
+    mut events: EventReader<AssetEvent<LightPreset>>,
+    query: Query<(
+        Entity,
+        &Handle<LightPreset>,
+        ChangeTrackers<Handle<LightPreset>>,
+    )>,
+) {
+    let mut updated = HashSet::new();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                updated.insert(handle.id());
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    for (entity, handle, tracker) in &query {
+        if !tracker.is_changed() && !updated.contains(&handle.id()) {
+            continue;
+        }
+        let preset = match presets.get(handle) {
+            Some(preset) => preset,
+            None => continue,
+        };
+        let mut entity = commands.entity(entity);
+        entity.insert(preset.point_light()).remove::<(
+            Light2dFlicker,
+            Light2dPulse,
+            Light2dColorGradient,
+            Light2dCurve,
+            Light2dFadeIn,
+            Light2dFadeOut,
+            Light2dAnimationBase,
+        )>();
+        match &preset.animation {
+            Some(LightPresetAnimation::Flicker {
+                seed,
+                frequency,
+                amount,
+            }) => {
+                entity.insert(Light2dFlicker::new(*seed, *frequency, *amount));
+            }
+            Some(LightPresetAnimation::Pulse { frequency, amount }) => {
+                entity.insert(Light2dPulse::new(*frequency, *amount));
+            }
+            Some(LightPresetAnimation::Gradient { keys, repeat }) => {
+                entity.insert(Light2dColorGradient::new(keys.clone(), *repeat));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn torch_preset_loads() {
+        let preset =
+            ron::de::from_str::<LightPreset>(include_str!("../assets/lights/torch.light2d.ron"))
+                .unwrap();
+        assert_eq!(preset.kind, LightPresetKind::Point);
+        assert_eq!(preset.intensity, 1.0);
+        assert_eq!(preset.inner_radius, 0.2);
+        assert!(matches!(
+            preset.animation,
+            Some(LightPresetAnimation::Flicker { seed: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn point_light_maps_spot_angles_and_temperature() {
+        let preset = ron::de::from_str::<LightPreset>(
+            "(
+                kind: Spot(inner_angle: 0.25, outer_angle: 0.5),
+                color: Rgba(red: 0.0, green: 1.0, blue: 0.0, alpha: 0.8),
+                temperature_kelvin: Some(1900.0),
+            )",
+        )
+        .unwrap();
+        let light = preset.point_light();
+        assert_eq!((light.inner_angle, light.outer_angle), (0.25, 0.5));
+        let mut color = color_temperature(1900.0);
+        color.set_a(0.8);
+        assert_eq!(light.color, color);
+
+        let point = LightPreset {
+            kind: LightPresetKind::Point,
+            temperature_kelvin: None,
+            ..preset
+        };
+        let light = point.point_light();
+        assert_eq!((light.inner_angle, light.outer_angle), (1.0, 1.0));
+        assert_eq!(light.color, Color::rgba(0.0, 1.0, 0.0, 0.8));
+    }
+}
@@ -0,0 +1,294 @@
+use std::f32::consts::{E, PI};
+
+use bevy::{ecs::system::SystemParam, math::Affine2, prelude::*};
+
+use crate::{Light2dFalloff, PointLight2d, Shadow2dCasters};
+
+/// Attenuation of `light` at the world-space `point`, in `0.0..=1.0`, without occlusion.
+///
+/// Mirrors `light.wgsl`: the light is the unit quad scaled and placed by `transform`, its
+/// radius and cone come from the point light lookup and its falloff from either `falloff` or
+/// the built-in `falloff_intensity` family.
+pub fn point_light_attenuation(
+    light: &PointLight2d,
+    transform: &GlobalTransform,
+    falloff: Option<&Light2dFalloff>,
+    point: Vec2,
+) -> f32 {
+    let local = affine_2d(transform).inverse().transform_point2(point);
+    // Same orientation as the light quad UVs, v grows downwards.
+    let offset = Vec2::new(local.x, -local.y);
+    let offset_length = offset.length();
+    if !offset_length.is_finite() || offset_length >= 0.5 {
+        return 0.0;
+    }
+
+    let distance = (1.0 - 2.0 * offset_length).clamp(0.0, 1.0);
+    let angle_cos = if offset_length > 0.0 {
+        offset.y / offset_length
+    } else {
+        1.0
+    };
+    let angle = 1.0 - angle_cos.clamp(-1.0, 1.0).acos() / PI;
+
+    let light = light.sanitized();
+    let radius_attenuation = saturate(light.inner_radius_mult() * distance);
+    let angle_attenuation = saturate(angle * light.inner_angle_mult());
+    let attenuation = radius_attenuation * angle_attenuation;
+
+    match falloff {
+        Some(falloff) => falloff.evaluate(attenuation),
+        None => attenuation.powf(E.powf(1.5 - 3.0 * light.falloff_intensity)),
+    }
+}
+
+/// The xy part of `transform`. Light quads are usually scaled by zero along z, which makes the
+/// full matrix singular.
+pub fn affine_2d(transform: &GlobalTransform) -> Affine2 {
+    let matrix = transform.compute_matrix();
+    Affine2::from_cols(
+        matrix.x_axis.truncate().truncate(),
+        matrix.y_axis.truncate().truncate(),
+        matrix.w_axis.truncate().truncate(),
+    )
+}
+
+/// World radius of a light, from its transform.
+pub fn light_radius(transform: &GlobalTransform) -> f32 {
+    let affine = affine_2d(transform);
+    0.5 * affine.x_axis.length().max(affine.y_axis.length())
+}
+
+fn saturate(value: f32) -> f32 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(0.0, 1.0)
+    }
+}
+
+/// Reads light levels at arbitrary world points from the same data the renderer uses. Works
+/// without a `RenderApp`, e.g. on a dedicated server.
+///
+/// Like the renderer, lights hidden in the hierarchy according to their `ComputedVisibility`
+/// are left out. Only the render plugins compute it, so headless apps should spawn lights
+/// without one, e.g. with a `TransformBundle` rather than a `SpatialBundle`.
+#[derive(SystemParam)]
+pub struct Light2dQuery<'w, 's> {
+    lights: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static PointLight2d,
+            &'static GlobalTransform,
+            Option<&'static ComputedVisibility>,
+        ),
+    >,
+    casters: Shadow2dCasters<'w, 's>,
+    falloffs: Option<Res<'w, Assets<Light2dFalloff>>>,
+}
+
+impl<'w, 's> Light2dQuery<'w, 's> {
+    /// Contribution of a single light at `point`, including occlusion by
+    /// [`Shadow2d`](crate::Shadow2d) casters.
+    pub fn light_attenuation(&self, entity: Entity, point: Vec2) -> f32 {
+        match self.lights.get(entity) {
+            Ok((_, light, transform, visibility)) => self
+                .attenuation(light, transform, visibility, point)
+                .max_element(),
+            Err(_) => 0.0,
+        }
+    }
+
+    /// Sum of the brightness (`color.a` times `intensity` times attenuation) of every light
+    /// reaching `point`.
+    pub fn light_level(&self, point: Vec2) -> f32 {
+        self.lights
+            .iter()
+            .map(|(_, light, transform, visibility)| {
+                light.color.a()
+                    * light.intensity
+                    * self
+                        .attenuation(light, transform, visibility, point)
+                        .max_element()
+            })
+            .sum()
+    }
+
+    /// Linear color of the light reaching `point`, weighted by each light's brightness and
+    /// filtered by translucent casters.
+    pub fn light_color(&self, point: Vec2) -> Color {
+        let color = self
+            .lights
+            .iter()
+            .map(|(_, light, transform, visibility)| {
+                let color = light.linear_color();
+                color.truncate() * color.w * self.attenuation(light, transform, visibility, point)
+            })
+            .fold(Vec3::ZERO, |sum, color| sum + color);
+        Color::rgb_linear(color.x, color.y, color.z)
+    }
+
+    /// Whether any [`Shadow2d`](crate::Shadow2d) caster blocks the segment from `from` to `to`.
+    pub fn is_occluded(&self, from: Vec2, to: Vec2) -> bool {
+        self.casters.is_occluded(from, to)
+    }
+
+    /// Per linear channel, translucent casters filter the light.
+    fn attenuation(
+        &self,
+        light: &PointLight2d,
+        transform: &GlobalTransform,
+        visibility: Option<&ComputedVisibility>,
+        point: Vec2,
+    ) -> Vec3 {
+        // Lights off-screen still light gameplay, so only the hierarchy is checked.
+        if visibility.map_or(false, |visibility| !visibility.is_visible_in_hierarchy()) {
+            return Vec3::ZERO;
+        }
+        let falloff = light
+            .falloff
+            .as_ref()
+            .and_then(|handle| self.falloffs.as_ref()?.get(handle));
+        let attenuation = point_light_attenuation(light, transform, falloff, point);
+        if attenuation <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let transmission = self
+            .casters
+            .transmission(transform.translation().truncate(), point);
+        attenuation * light.sanitized().shadow_visibility(transmission)
+    }
+}
+
+/// Tracks the light level at its entity's position and sends [`LightSensor2dEvent`]s when the
+/// level crosses one of `thresholds`.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct LightSensor2d {
+    /// Ascending light levels. Bands are counted from the bottom, so unsorted thresholds
+    /// report the wrong crossings.
+    pub thresholds: Vec<f32>,
+    pub level: f32,
+    /// Number of thresholds the current level is at or above.
+    pub band: usize,
+}
+
+impl LightSensor2d {
+    pub fn new(thresholds: Vec<f32>) -> Self {
+        Self {
+            thresholds,
+            level: 0.0,
+            band: 0,
+        }
+    }
+
+    /// Moves the sensor to `level` and returns the thresholds it crossed in the order they were
+    /// crossed, each with whether it was crossed rising.
+    pub fn update(&mut self, level: f32) -> Vec<(f32, bool)> {
+        debug_assert!(
+            self.thresholds.windows(2).all(|pair| pair[0] <= pair[1]),
+            "LightSensor2d thresholds must be ascending: {:?}",
+            self.thresholds
+        );
+        // The thresholds may have been shortened since the last update.
+        let previous = self.band.min(self.thresholds.len());
+        let band = self
+            .thresholds
+            .iter()
+            .filter(|threshold| level >= **threshold)
+            .count();
+        let crossed = if band > previous {
+            self.thresholds[previous..band]
+                .iter()
+                .map(|threshold| (*threshold, true))
+                .collect()
+        } else {
+            self.thresholds[band..previous]
+                .iter()
+                .rev()
+                .map(|threshold| (*threshold, false))
+                .collect()
+        };
+        self.level = level;
+        self.band = band;
+        crossed
+    }
+}
+
+impl Default for LightSensor2d {
+    fn default() -> Self {
+        Self::new(vec![0.5])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LightSensor2dEvent {
+    pub entity: Entity,
+    pub level: f32,
+    /// The threshold that was crossed.
+    pub threshold: f32,
+    /// `true` when the level rose above `threshold`, `false` when it fell below it.
+    pub rising: bool,
+}
+
+pub fn update_light_sensors(
+    light_query: Light2dQuery,
+    mut sensors: Query<(Entity, &mut LightSensor2d, &GlobalTransform)>,
+    mut events: EventWriter<LightSensor2dEvent>,
+) {
+    for (entity, mut sensor, transform) in &mut sensors {
+        let level = light_query.light_level(transform.translation().truncate());
+        for (threshold, rising) in sensor.update(level) {
+            events.send(LightSensor2dEvent {
+                entity,
+                level,
+                threshold,
+                rising,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensor_reports_each_crossed_threshold() {
+        let mut sensor = LightSensor2d::new(vec![0.25, 0.5, 0.75]);
+        assert_eq!(sensor.update(0.1), vec![]);
+        assert_eq!(sensor.update(0.6), vec![(0.25, true), (0.5, true)]);
+        assert_eq!(sensor.band, 2);
+        assert_eq!(sensor.update(0.6), vec![]);
+        assert_eq!(sensor.update(1.0), vec![(0.75, true)]);
+        assert_eq!(
+            sensor.update(0.0),
+            vec![(0.75, false), (0.5, false), (0.25, false)]
+        );
+        assert_eq!(sensor.band, 0);
+        assert_eq!(sensor.level, 0.0);
+    }
+
+    #[test]
+    fn sensor_thresholds_are_inclusive() {
+        let mut sensor = LightSensor2d::new(vec![0.5]);
+        assert_eq!(sensor.update(0.5), vec![(0.5, true)]);
+        assert_eq!(sensor.update(0.49), vec![(0.5, false)]);
+    }
+
+    #[test]
+    fn sensor_survives_shortened_thresholds() {
+        let mut sensor = LightSensor2d::new(vec![0.25, 0.5, 0.75]);
+        sensor.update(1.0);
+        sensor.thresholds.truncate(1);
+        assert_eq!(sensor.update(1.0), vec![]);
+        assert_eq!(sensor.band, 1);
+
+        // Like a sensor deserialized with a band past its thresholds.
+        sensor.band = 5;
+        assert_eq!(sensor.update(0.0), vec![(0.25, false)]);
+        assert_eq!(sensor.band, 0);
+    }
+}
@@ -0,0 +1,396 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    utils::HashMap,
+};
+
+use crate::{
+    affine_2d, caster_transmission,
+    render::{
+        create_falloff_curve_image, create_falloff_lookup_image, create_point_light_lookup_image,
+    },
+    Light2dFalloff, PointLight2d, Shadow2d,
+};
+
+/// A 32-bit float lookup texture, sampled like the GPU does with the default linear,
+/// clamp-to-edge sampler.
+struct LookupTexture {
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: Vec<f32>,
+}
+
+impl LookupTexture {
+    fn from_image(image: &Image) -> Self {
+        let channels = match image.texture_descriptor.format {
+            TextureFormat::R32Float => 1,
+            TextureFormat::Rgba32Float => 4,
+            format => panic!("unsupported lookup texture format {format:?}"),
+        };
+        Self {
+            width: image.texture_descriptor.size.width as usize,
+            height: image.texture_descriptor.size.height as usize,
+            channels,
+            data: image
+                .data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+        }
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Vec4 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        let index = (y * self.width + x) * self.channels;
+        let mut texel = [0.0; 4];
+        texel[..self.channels].copy_from_slice(&self.data[index..index + self.channels]);
+        Vec4::from(texel)
+    }
+
+    fn sample(&self, uv: Vec2) -> Vec4 {
+        let position = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let base = position.floor();
+        let t = position - base;
+        let (x, y) = (base.x as isize, base.y as isize);
+        let top = self.texel(x, y).lerp(self.texel(x + 1, y), t.x);
+        let bottom = self.texel(x, y + 1).lerp(self.texel(x + 1, y + 1), t.x);
+        top.lerp(bottom, t.y)
+    }
+}
+
+/// The part of the world a [`Light2dReferenceRenderer`] draws.
+#[derive(Debug, Clone)]
+pub struct ReferenceView2d {
+    /// World position of the center of the image.
+    pub center: Vec2,
+    /// Image size in pixels.
+    pub size: UVec2,
+    /// World units per pixel.
+    pub scale: f32,
+}
+
+impl ReferenceView2d {
+    pub fn pixel_center(&self, pixel: UVec2) -> Vec2 {
+        let offset = pixel.as_vec2() + 0.5 - self.size.as_vec2() / 2.0;
+        self.center + Vec2::new(offset.x, -offset.y) * self.scale
+    }
+}
+
+/// A software version of the light pass: it evaluates `light.wgsl` per pixel from the same
+/// lookup textures and blends the lights in the same order, so its output can be compared
+/// against golden images on machines without a GPU.
+///
+/// Casters shadow the lights behind them as in [`Light2dQuery`](crate::Light2dQuery).
+/// [`Light2dCookie`](crate::Light2dCookie)s and [`Light2dVolumetric`](crate::Light2dVolumetric)
+/// shafts are not drawn.
+pub struct Light2dReferenceRenderer {
+    point_light_lookup: LookupTexture,
+    falloff_lookup: LookupTexture,
+}
+
+impl Default for Light2dReferenceRenderer {
+    fn default() -> Self {
+        Self {
+            point_light_lookup: LookupTexture::from_image(&create_point_light_lookup_image(
+                TextureFormat::Rgba32Float,
+            )),
+            falloff_lookup: LookupTexture::from_image(&create_falloff_lookup_image(
+                TextureFormat::R32Float,
+            )),
+        }
+    }
+}
+
+impl Light2dReferenceRenderer {
+    /// Renders the light texture of `view` as an `Rgba8UnormSrgb` image.
+    pub fn render(
+        &self,
+        view: &ReferenceView2d,
+        lights: &[(PointLight2d, GlobalTransform)],
+        casters: &[(Shadow2d, GlobalTransform)],
+        falloffs: Option<&Assets<Light2dFalloff>>,
+    ) -> Image {
+        let falloffs = lights
+            .iter()
+            .filter_map(|(light, _)| {
+                let handle = light.falloff.as_ref()?;
+                Some((handle.clone_weak(), falloffs?.get(handle)?.clone()))
+            })
+            .collect();
+        let data = self
+            .render_linear(view, lights, casters, &falloffs)
+            .into_iter()
+            .flat_map(|pixel| {
+                Color::rgba_linear(pixel.x, pixel.y, pixel.z, pixel.w)
+                    .as_rgba_f32()
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+
+        Image::new(
+            Extent3d {
+                width: view.size.x,
+                height: view.size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    /// The pixels of the light texture of `view` in linear color, rows top to bottom, as the
+    /// lights leave them: color premultiplied by alpha. `falloffs` holds the curves of the lights
+    /// that have one.
+    pub fn render_linear(
+        &self,
+        view: &ReferenceView2d,
+        lights: &[(PointLight2d, GlobalTransform)],
+        casters: &[(Shadow2d, GlobalTransform)],
+        falloffs: &HashMap<Handle<Light2dFalloff>, Light2dFalloff>,
+    ) -> Vec<Vec4> {
+        // Lights are drawn back to front, like the sorted transparent phase.
+        let mut lights = lights.iter().collect::<Vec<_>>();
+        lights.sort_by(|(_, a), (_, b)| a.translation().z.total_cmp(&b.translation().z));
+        let lights = lights
+            .into_iter()
+            .map(|(light, transform)| {
+                let falloff = light
+                    .falloff
+                    .as_ref()
+                    .and_then(|handle| falloffs.get(handle))
+                    .map(|falloff| {
+                        LookupTexture::from_image(&create_falloff_curve_image(
+                            &falloff.bake(),
+                            TextureFormat::R32Float,
+                        ))
+                    });
+                (light, transform, affine_2d(transform).inverse(), falloff)
+            })
+            .collect::<Vec<_>>();
+
+        let mut pixels = Vec::with_capacity(view.size.x as usize * view.size.y as usize);
+        for y in 0..view.size.y {
+            for x in 0..view.size.x {
+                let point = view.pixel_center(UVec2::new(x, y));
+                // The light texture is cleared to transparent black.
+                let mut target = Vec4::ZERO;
+                for (light, transform, inverse, falloff) in &lights {
+                    let local = inverse.transform_point2(point);
+                    if local.abs().cmpgt(Vec2::splat(0.5)).any() {
+                        continue;
+                    }
+                    let light_position = transform.translation().truncate();
+                    let transmission = casters
+                        .iter()
+                        .map(|(shadow, caster_transform)| {
+                            caster_transmission(shadow, caster_transform, light_position, point)
+                        })
+                        .fold(Vec3::ONE, |transmission, caster| transmission * caster);
+                    let uv = Vec2::new(local.x + 0.5, 0.5 - local.y);
+                    let color = filter_light(
+                        self.shade(light, falloff.as_ref(), uv),
+                        light.sanitized().shadow_visibility(transmission),
+                    );
+                    // `BlendState::ALPHA_BLENDING`
+                    target =
+                        (color * color.w).truncate().extend(color.w) + target * (1.0 - color.w);
+                }
+                pixels.push(target);
+            }
+        }
+        pixels
+    }
+
+    /// The fragment shader of `light.wgsl`, in linear color.
+    fn shade(&self, light: &PointLight2d, falloff: Option<&LookupTexture>, uv: Vec2) -> Vec4 {
+        // r = distance, g = angle, b = x direction, a = y direction
+        let lookup = self.point_light_lookup.sample(uv);
+
+        let light = light.sanitized();
+        let radius_attenuation = saturate(light.inner_radius_mult() * lookup.x);
+        let angle_attenuation = saturate(lookup.y * light.inner_angle_mult());
+        let attenuation = radius_attenuation * angle_attenuation;
+
+        let attenuation = match falloff {
+            Some(falloff) => falloff.sample(Vec2::new(attenuation, 0.5)).x,
+            None => {
+                self.falloff_lookup
+                    .sample(Vec2::new(attenuation, light.falloff_intensity))
+                    .x
+            }
+        };
+
+        let color = light.linear_color();
+        color.truncate().extend(color.w * attenuation)
+    }
+}
+
+/// The largest difference between two channels of `a` and `b`, or `None` if their sizes or
+/// formats differ. Meant for 8-bit formats, such as the output of
+/// [`Light2dReferenceRenderer::render`].
+pub fn image_difference(a: &Image, b: &Image) -> Option<u8> {
+    if a.texture_descriptor.size != b.texture_descriptor.size
+        || a.texture_descriptor.format != b.texture_descriptor.format
+        || a.data.len() != b.data.len()
+    {
+        return None;
+    }
+    Some(
+        a.data
+            .iter()
+            .zip(&b.data)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0),
+    )
+}
+
+/// Whether `actual` matches `expected`, e.g. a golden image, to within `tolerance` in every
+/// channel. Leave some tolerance for float differences between machines.
+pub fn images_match(expected: &Image, actual: &Image, tolerance: u8) -> bool {
+    image_difference(expected, actual).map_or(false, |difference| difference <= tolerance)
+}
+
+/// `filter_light` in `light.wgsl`: the brightest channel of `tint` scales the coverage, its
+/// color tints the light.
+fn filter_light(color: Vec4, filter: Vec3) -> Vec4 {
+    let strength = filter.max_element();
+    if strength > 0.0 {
+        (color.truncate() * filter / strength).extend(color.w * strength)
+    } else {
+        color.truncate().extend(0.0)
+    }
+}
+
+/// `saturate` in WGSL, with NaN mapped to zero as GPUs do in practice.
+fn saturate(value: f32) -> f32 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_png, save_png};
+
+    const GOLDEN: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/golden/reference_lights.png"
+    );
+
+    fn lit_scene() -> (
+        ReferenceView2d,
+        Vec<(PointLight2d, GlobalTransform)>,
+        Vec<(Shadow2d, GlobalTransform)>,
+    ) {
+        let view = ReferenceView2d {
+            center: Vec2::new(0.5, -0.25),
+            size: UVec2::new(64, 48),
+            scale: 0.25,
+        };
+        let lights = vec![
+            (
+                PointLight2d {
+                    color: Color::rgba_linear(1.0, 0.8, 0.6, 1.0),
+                    falloff_intensity: 0.5,
+                    inner_radius: 0.0,
+                    ..default()
+                },
+                Transform::from_xyz(-2.3, 0.7, 0.0)
+                    .with_scale(Vec3::new(12.0, 12.0, 1.0))
+                    .into(),
+            ),
+            (
+                PointLight2d {
+                    color: Color::rgba_linear(0.3, 0.5, 1.0, 0.8),
+                    intensity: 1.5,
+                    falloff_intensity: 0.7,
+                    inner_angle: 0.2,
+                    outer_angle: 0.45,
+                    inner_radius: 0.3,
+                    ..default()
+                },
+                Transform::from_xyz(3.1, -1.9, 1.0)
+                    .with_rotation(Quat::from_rotation_z(0.5))
+                    .with_scale(Vec3::new(10.0, 10.0, 1.0))
+                    .into(),
+            ),
+        ];
+        let casters = vec![
+            (
+                Shadow2d {
+                    closed: true,
+                    points: vec![
+                        Vec2::new(-0.6, -0.4),
+                        Vec2::new(0.6, -0.4),
+                        Vec2::new(0.6, 0.4),
+                        Vec2::new(-0.6, 0.4),
+                    ],
+                    ..default()
+                },
+                Transform::from_xyz(0.35, 0.15, 0.0)
+                    .with_rotation(Quat::from_rotation_z(0.3))
+                    .into(),
+            ),
+            (
+                Shadow2d {
+                    closed: false,
+                    points: vec![Vec2::new(-1.2, 0.0), Vec2::new(1.3, 0.2)],
+                    opacity: 0.9,
+                    tint: Color::rgba_linear(1.0, 0.2, 0.1, 1.0),
+                    transmission: 0.6,
+                },
+                Transform::from_xyz(1.7, 2.1, 0.0).into(),
+            ),
+        ];
+        (view, lights, casters)
+    }
+
+    #[test]
+    fn render_matches_golden_image() {
+        let (view, lights, casters) = lit_scene();
+        let image = Light2dReferenceRenderer::default().render(&view, &lights, &casters, None);
+        // Run with `UPDATE_GOLDEN` set after an intended change of the lighting math.
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            save_png(&image, GOLDEN).unwrap();
+        }
+        let golden = load_png(GOLDEN).unwrap();
+        assert!(
+            images_match(&golden, &image, 2),
+            "differs from {GOLDEN} by up to {:?}",
+            image_difference(&golden, &image)
+        );
+    }
+
+    #[test]
+    fn image_difference_is_per_channel() {
+        let image = |data: Vec<u8>| {
+            Image::new(
+                Extent3d {
+                    width: 2,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+            )
+        };
+        let a = image(vec![10, 20, 30, 255, 0, 0, 0, 0]);
+        let b = image(vec![12, 20, 27, 255, 0, 0, 1, 0]);
+        assert_eq!(image_difference(&a, &b), Some(3));
+        assert!(images_match(&a, &b, 3));
+        assert!(!images_match(&a, &b, 2));
+
+        let mut wide = image(vec![0; 8]);
+        wide.texture_descriptor.size.width = 1;
+        wide.texture_descriptor.size.height = 2;
+        assert_eq!(image_difference(&a, &wide), None);
+    }
+}
@@ -0,0 +1,213 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_asset::RenderAssets,
+        render_phase::TrackedRenderPass,
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+            BlendState, BufferBindingType, CachedRenderPipelineId, DynamicUniformBuffer,
+            MultisampleState, PipelineCache, RenderPipelineDescriptor, ShaderStages, ShaderType,
+            SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+            TextureSampleType, TextureViewDimension,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+    },
+};
+
+use super::{
+    sdf::{offscreen_pipeline_descriptor, Light2dSdfTextures},
+    Light2dAmbientOcclusion, Light2dDebugView, Light2dOverlay,
+};
+
+pub const AMBIENT_OCCLUSION_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597188);
+
+/// Settings of one [`Light2dAmbientOcclusion`] view.
+#[derive(Clone, Copy, ShaderType)]
+pub struct Light2dAmbientOcclusionUniform {
+    /// [`Light2dAmbientOcclusion::radius`] in texels of the light texture.
+    pub radius: f32,
+    pub strength: f32,
+}
+
+#[derive(Resource)]
+pub struct Light2dAmbientOcclusionPipeline {
+    pub layout: BindGroupLayout,
+}
+
+impl FromWorld for Light2dAmbientOcclusionPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(Light2dAmbientOcclusionUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_ambient_occlusion_layout"),
+        });
+
+        Self { layout }
+    }
+}
+
+/// Ambient occlusion is drawn into the light texture, so it has to match its format.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Light2dAmbientOcclusionPipelineKey {
+    pub samples: u32,
+    pub format: TextureFormat,
+}
+
+impl SpecializedRenderPipeline for Light2dAmbientOcclusionPipeline {
+    type Key = Light2dAmbientOcclusionPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut descriptor = offscreen_pipeline_descriptor(
+            "light_ambient_occlusion_pipeline",
+            AMBIENT_OCCLUSION_SHADER_HANDLE.typed(),
+            ("fullscreen_vertex", "fragment"),
+            Vec::new(),
+            vec![self.layout.clone()],
+            key.format,
+            Some(BlendState::ALPHA_BLENDING),
+        );
+        descriptor.multisample = MultisampleState {
+            count: key.samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+        descriptor
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Light2dAmbientOcclusionMeta {
+    uniforms: DynamicUniformBuffer<Light2dAmbientOcclusionUniform>,
+}
+
+/// What it takes to draw the ambient occlusion of a view, on its [`Light2dOverlay`] entity.
+#[derive(Component)]
+pub struct Light2dAmbientOcclusionBindGroup {
+    bind_group: BindGroup,
+    pipeline: CachedRenderPipelineId,
+    /// Of the view's [`Light2dAmbientOcclusionUniform`].
+    offset: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_ambient_occlusion_bind_groups(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    ao_pipeline: Res<Light2dAmbientOcclusionPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<Light2dAmbientOcclusionPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut ao_meta: ResMut<Light2dAmbientOcclusionMeta>,
+    gpu_images: Res<RenderAssets<Image>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &Light2dOverlay,
+        &Light2dDebugView,
+        &Light2dAmbientOcclusion,
+        &Light2dSdfTextures,
+    )>,
+) {
+    if views.is_empty() {
+        return;
+    }
+
+    ao_meta.uniforms.clear();
+    let offsets = views
+        .iter()
+        .map(|(_, view, overlay, _, ambient_occlusion, _)| {
+            ao_meta.uniforms.push(Light2dAmbientOcclusionUniform {
+                radius: ambient_occlusion.radius.max(0.0) * overlay.texels_per_unit(view),
+                strength: ambient_occlusion.strength.clamp(0.0, 1.0),
+            })
+        })
+        .collect::<Vec<_>>();
+    ao_meta.uniforms.write_buffer(&render_device, &render_queue);
+    let uniforms_binding = match ao_meta.uniforms.binding() {
+        Some(binding) => binding,
+        None => return,
+    };
+
+    for ((entity, _, overlay, debug_view, _, sdf_textures), offset) in views.iter().zip(offsets) {
+        // The overdraw and shadow mask views only show the lights.
+        if matches!(
+            debug_view,
+            Light2dDebugView::Overdraw | Light2dDebugView::ShadowMask
+        ) {
+            continue;
+        }
+        let format = match gpu_images.get(&overlay.image) {
+            Some(gpu_image) => gpu_image.texture_format,
+            None => continue,
+        };
+        let pipeline = pipelines.specialize(
+            &mut pipeline_cache,
+            &ao_pipeline,
+            Light2dAmbientOcclusionPipelineKey {
+                samples: overlay.samples,
+                format,
+            },
+        );
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(sdf_textures.result()),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: uniforms_binding.clone(),
+                },
+            ],
+            label: Some("light_ambient_occlusion_bind_group"),
+            layout: &ao_pipeline.layout,
+        });
+        commands
+            .entity(entity)
+            .insert(Light2dAmbientOcclusionBindGroup {
+                bind_group,
+                pipeline,
+                offset,
+            });
+    }
+}
+
+/// Darkens the light texture of a view around its occluders, before anything else is drawn.
+pub fn draw_ambient_occlusion(
+    world: &World,
+    pass: &mut TrackedRenderPass,
+    bind_group: &Light2dAmbientOcclusionBindGroup,
+) {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    if let Some(pipeline) = pipeline_cache.get_render_pipeline(bind_group.pipeline) {
+        pass.set_render_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group.bind_group, &[bind_group.offset]);
+        pass.draw(0..3, 0..1);
+    }
+}
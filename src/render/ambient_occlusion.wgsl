@@ -0,0 +1,40 @@
+struct AmbientOcclusion {
+    radius: f32,
+    strength: f32,
+};
+
+// Nearest occluded texel of each texel of the view, see sdf_flood.wgsl.
+@group(0) @binding(0)
+var sdf_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> ambient_occlusion: AmbientOcclusion;
+
+#import light_2d::sdf
+
+let PI: f32 = 3.141592653589793;
+let AO_SAMPLES: u32 = 8u;
+
+// How far from any occluder `texel` is, from 0.0 against one to 1.0 at the radius.
+fn openness(texel: vec2<f32>, size: vec2<f32>) -> f32 {
+    return saturate((sdf_distance(texel, size) - 1.0) / ambient_occlusion.radius);
+}
+
+// Black, covering the unlit scene by how enclosed the pixel is: the openness of the pixel and
+// of a ring around it, so corners come out darker than straight walls. Lights drawn over it
+// wash it out, like ambient occlusion only dims ambient light.
+@fragment
+fn fragment(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(sdf_texture));
+    if (ambient_occlusion.radius <= 0.0 || sdf_distance(position.xy, size) < 1.0) {
+        return vec4<f32>(0.0);
+    }
+    var total = openness(position.xy, size);
+    for (var i = 0u; i < AO_SAMPLES; i = i + 1u) {
+        let angle = 2.0 * PI * f32(i) / f32(AO_SAMPLES);
+        let offset = vec2<f32>(cos(angle), sin(angle)) * 0.5 * ambient_occlusion.radius;
+        total += openness(position.xy + offset, size);
+    }
+    let open = total / f32(AO_SAMPLES + 1u);
+    let occlusion = ambient_occlusion.strength * (1.0 - smoothstep(0.0, 1.0, open));
+    return vec4<f32>(0.0, 0.0, 0.0, occlusion);
+}
@@ -0,0 +1,258 @@
+use bevy::{
+    core_pipeline::core_2d::Transparent2d,
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem, SystemState,
+    },
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_phase::{
+            BatchedPhaseItem, DrawFunctions, EntityRenderCommand, RenderCommand,
+            RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState,
+            BufferBindingType, BufferUsages, BufferVec, ColorTargetState, ColorWrites,
+            FragmentState, FrontFace, MultisampleState, PipelineCache, PolygonMode, PrimitiveState,
+            PrimitiveTopology, RenderPipelineDescriptor, ShaderStages, ShaderType,
+            SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+            VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+    },
+    utils::FloatOrd,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::Light2dDebugLines;
+
+use super::Light2dOverlay;
+
+pub const DEBUG_LINES_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597148);
+
+#[derive(Resource)]
+pub struct DebugLinesPipeline {
+    pub view_layout: BindGroupLayout,
+}
+
+impl FromWorld for DebugLinesPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let mut system_state: SystemState<Res<RenderDevice>> = SystemState::new(world);
+        let render_device = system_state.get_mut(world);
+
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(ViewUniform::min_size()),
+                },
+                count: None,
+            }],
+            label: Some("debug_lines_view_layout"),
+        });
+
+        Self { view_layout }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct DebugLinesPipelineKey {
+    pub samples: u32,
+    pub hdr: bool,
+}
+
+impl SpecializedRenderPipeline for DebugLinesPipeline {
+    type Key = DebugLinesPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let formats = vec![
+            VertexFormat::Float32x3, // position
+            VertexFormat::Float32x4, // color
+        ];
+
+        let vertex_layout =
+            VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
+
+        RenderPipelineDescriptor {
+            vertex: VertexState {
+                shader: DEBUG_LINES_SHADER_HANDLE.typed::<Shader>(),
+                shader_defs: Vec::new(),
+                entry_point: "vertex".into(),
+                buffers: vec![vertex_layout],
+            },
+            fragment: Some(FragmentState {
+                shader: DEBUG_LINES_SHADER_HANDLE.typed::<Shader>(),
+                shader_defs: Vec::new(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: if key.hdr {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            layout: Some(vec![self.view_layout.clone()]),
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::LineList,
+                strip_index_format: None,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: key.samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            label: Some("light2d_debug_lines_pipeline".into()),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct DebugLineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+#[derive(Resource)]
+pub struct DebugLinesMeta {
+    vertices: BufferVec<DebugLineVertex>,
+    view_bind_group: Option<BindGroup>,
+}
+
+impl Default for DebugLinesMeta {
+    fn default() -> Self {
+        Self {
+            vertices: BufferVec::new(BufferUsages::VERTEX),
+            view_bind_group: None,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct DebugLinesBatch;
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_debug_lines(
+    mut commands: Commands,
+    draw_functions: Res<DrawFunctions<Transparent2d>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut meta: ResMut<DebugLinesMeta>,
+    view_uniforms: Res<ViewUniforms>,
+    debug_lines_pipeline: Res<DebugLinesPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<DebugLinesPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    msaa: Res<Msaa>,
+    lines: Res<Light2dDebugLines>,
+    // Light textures are drawn by their own views, the lines only go on the cameras.
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent2d>), Without<Light2dOverlay>>,
+) {
+    let view_binding = match view_uniforms.uniforms.binding() {
+        Some(view_binding) => view_binding,
+        None => return,
+    };
+    meta.vertices.clear();
+    for line in &lines.lines {
+        let color = line.color.as_linear_rgba_f32();
+        for position in [line.start, line.end] {
+            meta.vertices.push(DebugLineVertex {
+                position: position.into(),
+                color,
+            });
+        }
+    }
+    if meta.vertices.is_empty() {
+        return;
+    }
+    meta.vertices.write_buffer(&render_device, &render_queue);
+    meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding,
+        }],
+        label: Some("debug_lines_view_bind_group"),
+        layout: &debug_lines_pipeline.view_layout,
+    }));
+
+    let draw_function = draw_functions.read().get_id::<DrawDebugLines>().unwrap();
+    let batch = commands.spawn(DebugLinesBatch).id();
+    let vertex_count = meta.vertices.len() as u32;
+    for (view, mut transparent_phase) in &mut views {
+        let pipeline = pipelines.specialize(
+            &mut pipeline_cache,
+            &debug_lines_pipeline,
+            DebugLinesPipelineKey {
+                samples: msaa.samples,
+                hdr: view.hdr,
+            },
+        );
+        transparent_phase.add(Transparent2d {
+            draw_function,
+            pipeline,
+            entity: batch,
+            // On top of everything else.
+            sort_key: FloatOrd(f32::MAX),
+            batch_range: Some(0..vertex_count),
+        });
+    }
+}
+
+pub type DrawDebugLines = (
+    SetItemPipeline,
+    SetDebugLinesViewBindGroup<0>,
+    DrawDebugLinesBatch,
+);
+
+pub struct SetDebugLinesViewBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetDebugLinesViewBindGroup<I> {
+    type Param = (SRes<DebugLinesMeta>, SQuery<Read<ViewUniformOffset>>);
+
+    fn render<'w>(
+        view: Entity,
+        _item: Entity,
+        (meta, view_query): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let view_uniform = view_query.get(view).unwrap();
+        pass.set_bind_group(
+            I,
+            meta.into_inner().view_bind_group.as_ref().unwrap(),
+            &[view_uniform.offset],
+        );
+        RenderCommandResult::Success
+    }
+}
+
+pub struct DrawDebugLinesBatch;
+impl<P: BatchedPhaseItem> RenderCommand<P> for DrawDebugLinesBatch {
+    type Param = SRes<DebugLinesMeta>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: &P,
+        meta: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let meta = meta.into_inner();
+        pass.set_vertex_buffer(0, meta.vertices.buffer().unwrap().slice(..));
+        pass.draw(item.batch_range().as_ref().unwrap().clone(), 0..1);
+        RenderCommandResult::Success
+    }
+}
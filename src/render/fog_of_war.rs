@@ -0,0 +1,594 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    core_pipeline::core_2d::Transparent2d,
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem,
+    },
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::{ExtractedCamera, RenderTarget},
+        render_asset::RenderAssets,
+        render_phase::{
+            BatchedPhaseItem, DrawFunctions, EntityRenderCommand, RenderCommand,
+            RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+            BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, BufferVec,
+            ColorTargetState, ColorWrites, Extent3d, FragmentState, FrontFace, ImageCopyBuffer,
+            ImageDataLayout, MapMode, MultisampleState, PipelineCache, PolygonMode, PrimitiveState,
+            PrimitiveTopology, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
+            ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+            TextureSampleType, TextureViewDimension, VertexBufferLayout, VertexFormat, VertexState,
+            VertexStepMode,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{BevyDefault, GpuImage, TextureFormatPixelInfo},
+        view::{
+            ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms,
+            VisibleEntities,
+        },
+        Extract,
+    },
+    utils::{FloatOrd, HashMap},
+};
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BufferAsyncError, Maintain};
+
+use crate::FogOfWar;
+
+use super::{graph, Light2dDebugView, Light2dOverlay, Light2dShadowMode};
+
+pub const FOG_OF_WAR_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597208);
+
+/// Over the light overlay, see `queue_light_overlay_bind_group`.
+const FOG_OF_WAR_SORT_KEY: f32 = 101.0;
+
+/// The light view of a [`FogOfWar`], on its entity. Only vision lights are drawn into it, see
+/// [`queue_lights`](super::queue_lights).
+#[derive(Component, Clone)]
+pub struct Light2dFogOfWarView {
+    pub origin: Vec2,
+    pub world_size: Vec2,
+    pub color: Color,
+    pub explored_image: Handle<Image>,
+}
+
+/// How much of each cell of every [`FogOfWar`] the vision lights covered when last read back
+/// from the GPU, bottom row first, by entity. Shared by the main and the render world.
+#[derive(Resource, Clone, Default)]
+pub struct FogOfWarReadback(Arc<Mutex<HashMap<Entity, Vec<u8>>>>);
+
+impl FogOfWarReadback {
+    /// The coverage read back since the last call.
+    pub fn take(&self) -> HashMap<Entity, Vec<u8>> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn insert(&self, entity: Entity, coverage: Vec<u8>) {
+        self.0.lock().unwrap().insert(entity, coverage);
+    }
+}
+
+pub fn extract_fogs_of_war(mut commands: Commands, fogs: Extract<Query<(Entity, &FogOfWar)>>) {
+    for (entity, fog) in fogs.iter() {
+        if fog.size.x == 0 || fog.size.y == 0 {
+            continue;
+        }
+        let world_size = fog.world_size();
+        let half_size = world_size / 2.0;
+        commands.get_or_spawn(entity).insert((
+            ExtractedCamera {
+                target: RenderTarget::Image(fog.image.clone()),
+                viewport: None,
+                physical_viewport_size: Some(fog.size),
+                physical_target_size: Some(fog.size),
+                render_graph: graph::NAME.into(),
+                // Before the cameras it is composited into.
+                priority: isize::MIN,
+            },
+            // The grid seen by an orthographic 2D camera, one texel per cell.
+            ExtractedView {
+                projection: Mat4::orthographic_rh(
+                    -half_size.x,
+                    half_size.x,
+                    -half_size.y,
+                    half_size.y,
+                    0.0,
+                    1000.0,
+                ),
+                transform: GlobalTransform::from_translation(
+                    (fog.origin + half_size).extend(999.9),
+                ),
+                hdr: false,
+                viewport: UVec4::new(0, 0, fog.size.x, fog.size.y),
+            },
+            RenderPhase::<Transparent2d>::default(),
+            Light2dOverlay::new(fog.image.clone(), fog.size),
+            Light2dDebugView::None,
+            // Vision lights are often off screen, where there is no distance field.
+            Light2dShadowMode::Edges,
+            Light2dFogOfWarView {
+                origin: fog.origin,
+                world_size,
+                color: fog.color,
+                explored_image: fog.explored_image.clone(),
+            },
+        ));
+    }
+}
+
+/// A buffer the light texture of a [`FogOfWar`] is copied into and mapped from, one copy at a
+/// time so the GPU is never waited on.
+struct FogOfWarReadbackBuffer {
+    buffer: Buffer,
+    size: UVec2,
+    padded_bytes_per_row: u32,
+    /// Whether a copy was made and hasn't been read yet.
+    in_flight: bool,
+    /// Set once the copy is mapped, or failed to.
+    mapped: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+}
+
+#[derive(Resource, Default)]
+pub struct FogOfWarReadbackBuffers {
+    buffers: HashMap<Entity, FogOfWarReadbackBuffer>,
+}
+
+/// The buffer the light texture of a fog of war view is copied into this frame, after its
+/// lights are drawn.
+#[derive(Component)]
+pub struct FogOfWarCopy {
+    buffer: Buffer,
+    padded_bytes_per_row: u32,
+}
+
+impl FogOfWarCopy {
+    pub fn copy(&self, render_context: &mut RenderContext, gpu_image: &GpuImage) {
+        render_context.command_encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: gpu_image.size.x as u32,
+                height: gpu_image.size.y as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// Hands the copies mapped since the last frame to the main world and queues the next ones.
+pub fn prepare_fog_of_war_readbacks(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    readback: Res<FogOfWarReadback>,
+    mut buffers: ResMut<FogOfWarReadbackBuffers>,
+    gpu_images: Res<RenderAssets<Image>>,
+    views: Query<(Entity, &Light2dOverlay), With<Light2dFogOfWarView>>,
+) {
+    // Runs the callbacks of the buffers that finished mapping.
+    render_device.poll(Maintain::Poll);
+    buffers.buffers.retain(|entity, _| views.contains(*entity));
+
+    for (entity, overlay) in &views {
+        let size = overlay.size;
+        // The coverage is read from the alpha channel of 8-bit RGBA texels.
+        match gpu_images.get(&overlay.image) {
+            Some(gpu_image)
+                if gpu_image.texture_format.pixel_size() == 4
+                    && gpu_image.size == size.as_vec2() => {}
+            _ => continue,
+        }
+        let buffer = buffers
+            .buffers
+            .entry(entity)
+            .and_modify(|buffer| {
+                if buffer.size != size {
+                    *buffer = FogOfWarReadbackBuffer::new(&render_device, size);
+                }
+            })
+            .or_insert_with(|| FogOfWarReadbackBuffer::new(&render_device, size));
+
+        if buffer.in_flight {
+            let mapped = match buffer.mapped.lock().unwrap().take() {
+                Some(mapped) => mapped,
+                None => continue,
+            };
+            if mapped.is_ok() {
+                readback.insert(entity, buffer.read());
+                buffer.buffer.unmap();
+            }
+            buffer.in_flight = false;
+        }
+
+        commands.entity(entity).insert(FogOfWarCopy {
+            buffer: buffer.buffer.clone(),
+            padded_bytes_per_row: buffer.padded_bytes_per_row,
+        });
+        buffer.in_flight = true;
+    }
+}
+
+impl FogOfWarReadbackBuffer {
+    fn new(render_device: &RenderDevice, size: UVec2) -> Self {
+        let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(size.x as usize * 4);
+        Self {
+            buffer: render_device.create_buffer(&BufferDescriptor {
+                label: Some("fog_of_war_readback_buffer"),
+                size: (padded_bytes_per_row * size.y as usize) as u64,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            size,
+            padded_bytes_per_row: padded_bytes_per_row as u32,
+            in_flight: false,
+            mapped: Arc::default(),
+        }
+    }
+
+    /// The alpha of every texel of the mapped buffer.
+    fn read(&self) -> Vec<u8> {
+        let data = self.buffer.slice(..).get_mapped_range();
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        let mut coverage = Vec::with_capacity(width * height);
+        // Texture rows run top to bottom, grid rows bottom to top.
+        for row in data.chunks_exact(self.padded_bytes_per_row as usize).rev() {
+            coverage.extend(row[..width * 4].chunks_exact(4).map(|texel| texel[3]));
+        }
+        coverage
+    }
+}
+
+/// Maps the buffers copied into this frame, once the copies have been submitted.
+pub fn map_fog_of_war_readbacks(
+    buffers: Res<FogOfWarReadbackBuffers>,
+    copies: Query<Entity, With<FogOfWarCopy>>,
+) {
+    for entity in &copies {
+        if let Some(buffer) = buffers.buffers.get(&entity) {
+            let mapped = buffer.mapped.clone();
+            buffer
+                .buffer
+                .slice(..)
+                .map_async(MapMode::Read, move |result| {
+                    *mapped.lock().unwrap() = Some(result);
+                });
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct FogOfWarPipeline {
+    pub view_layout: BindGroupLayout,
+    pub material_layout: BindGroupLayout,
+}
+
+impl FromWorld for FogOfWarPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(ViewUniform::min_size()),
+                },
+                count: None,
+            }],
+            label: Some("fog_of_war_view_layout"),
+        });
+
+        let texture = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let sampler = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        };
+        let material_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[texture(0), sampler(1), texture(2), sampler(3)],
+            label: Some("fog_of_war_material_layout"),
+        });
+
+        Self {
+            view_layout,
+            material_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct FogOfWarPipelineKey {
+    pub samples: u32,
+    pub hdr: bool,
+}
+
+impl SpecializedRenderPipeline for FogOfWarPipeline {
+    type Key = FogOfWarPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let formats = vec![
+            VertexFormat::Float32x3, // position
+            VertexFormat::Float32x2, // uv
+            VertexFormat::Float32x4, // color
+        ];
+
+        let vertex_layout =
+            VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
+
+        RenderPipelineDescriptor {
+            vertex: VertexState {
+                shader: FOG_OF_WAR_SHADER_HANDLE.typed::<Shader>(),
+                shader_defs: Vec::new(),
+                entry_point: "vertex".into(),
+                buffers: vec![vertex_layout],
+            },
+            fragment: Some(FragmentState {
+                shader: FOG_OF_WAR_SHADER_HANDLE.typed::<Shader>(),
+                shader_defs: Vec::new(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: if key.hdr {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            layout: Some(vec![self.view_layout.clone(), self.material_layout.clone()]),
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: key.samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            label: Some("fog_of_war_pipeline".into()),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct FogOfWarVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    /// Linear fog color.
+    pub color: [f32; 4],
+}
+
+#[derive(Resource)]
+pub struct FogOfWarMeta {
+    vertices: BufferVec<FogOfWarVertex>,
+    view_bind_group: Option<BindGroup>,
+}
+
+impl Default for FogOfWarMeta {
+    fn default() -> Self {
+        Self {
+            vertices: BufferVec::new(BufferUsages::VERTEX),
+            view_bind_group: None,
+        }
+    }
+}
+
+const QUAD_INDICES: [usize; 6] = [0, 2, 3, 0, 1, 2];
+
+const QUAD_VERTEX_POSITIONS: [Vec2; 4] = [
+    Vec2::new(0., 0.),
+    Vec2::new(1., 0.),
+    Vec2::new(1., 1.),
+    Vec2::new(0., 1.),
+];
+
+const QUAD_UVS: [Vec2; 4] = [
+    Vec2::new(0., 1.),
+    Vec2::new(1., 1.),
+    Vec2::new(1., 0.),
+    Vec2::new(0., 0.),
+];
+
+/// The light texture and explored image of a fog of war view, on its entity.
+#[derive(Component)]
+pub struct FogOfWarBindGroup {
+    bind_group: BindGroup,
+}
+
+/// Composites every [`FogOfWar`] over the 2D cameras, as a quad over its grid.
+#[allow(clippy::too_many_arguments)]
+pub fn queue_fogs_of_war(
+    mut commands: Commands,
+    draw_functions: Res<DrawFunctions<Transparent2d>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut fog_meta: ResMut<FogOfWarMeta>,
+    view_uniforms: Res<ViewUniforms>,
+    fog_pipeline: Res<FogOfWarPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<FogOfWarPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    msaa: Res<Msaa>,
+    mut views: Query<(&mut RenderPhase<Transparent2d>, &ExtractedView), With<VisibleEntities>>,
+    fogs: Query<(Entity, &Light2dOverlay, &Light2dFogOfWarView)>,
+) {
+    let view_binding = match view_uniforms.uniforms.binding() {
+        Some(view_binding) => view_binding,
+        None => return,
+    };
+    fog_meta.vertices.clear();
+    fog_meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding,
+        }],
+        label: Some("fog_of_war_view_bind_group"),
+        layout: &fog_pipeline.view_layout,
+    }));
+
+    let draw_fog_of_war_function = draw_functions.read().get_id::<DrawFogOfWar>().unwrap();
+    let mut index = 0;
+    for (entity, overlay, fog_view) in &fogs {
+        let (light_image, explored_image) = match (
+            gpu_images.get(&overlay.image),
+            gpu_images.get(&fog_view.explored_image),
+        ) {
+            (Some(light_image), Some(explored_image)) => (light_image, explored_image),
+            _ => continue,
+        };
+        commands.entity(entity).insert(FogOfWarBindGroup {
+            bind_group: render_device.create_bind_group(&BindGroupDescriptor {
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&light_image.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&light_image.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&explored_image.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::Sampler(&explored_image.sampler),
+                    },
+                ],
+                label: Some("fog_of_war_bind_group"),
+                layout: &fog_pipeline.material_layout,
+            }),
+        });
+
+        let color = fog_view.color.as_linear_rgba_f32();
+        for i in QUAD_INDICES {
+            fog_meta.vertices.push(FogOfWarVertex {
+                position: (fog_view.origin + QUAD_VERTEX_POSITIONS[i] * fog_view.world_size)
+                    .extend(0.0)
+                    .into(),
+                uv: QUAD_UVS[i].into(),
+                color,
+            });
+        }
+        let item_start = index;
+        index += QUAD_INDICES.len() as u32;
+        let item_end = index;
+
+        for (mut transparent_phase, view) in &mut views {
+            let pipeline = pipelines.specialize(
+                &mut pipeline_cache,
+                &fog_pipeline,
+                FogOfWarPipelineKey {
+                    samples: msaa.samples,
+                    hdr: view.hdr,
+                },
+            );
+            transparent_phase.add(Transparent2d {
+                draw_function: draw_fog_of_war_function,
+                pipeline,
+                entity,
+                sort_key: FloatOrd(FOG_OF_WAR_SORT_KEY),
+                batch_range: Some(item_start..item_end),
+            });
+        }
+    }
+    fog_meta
+        .vertices
+        .write_buffer(&render_device, &render_queue);
+}
+
+pub type DrawFogOfWar = (
+    SetItemPipeline,
+    SetFogOfWarViewBindGroup<0>,
+    SetFogOfWarBindGroup<1>,
+    DrawFogOfWarBatch,
+);
+
+pub struct SetFogOfWarViewBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetFogOfWarViewBindGroup<I> {
+    type Param = (SRes<FogOfWarMeta>, SQuery<Read<ViewUniformOffset>>);
+
+    fn render<'w>(
+        view: Entity,
+        _item: Entity,
+        (fog_meta, view_query): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let view_uniform = view_query.get(view).unwrap();
+        pass.set_bind_group(
+            I,
+            fog_meta.into_inner().view_bind_group.as_ref().unwrap(),
+            &[view_uniform.offset],
+        );
+        RenderCommandResult::Success
+    }
+}
+
+pub struct SetFogOfWarBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetFogOfWarBindGroup<I> {
+    type Param = SQuery<Read<FogOfWarBindGroup>>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        query: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &query.get_inner(item).unwrap().bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct DrawFogOfWarBatch;
+impl<P: BatchedPhaseItem> RenderCommand<P> for DrawFogOfWarBatch {
+    type Param = SRes<FogOfWarMeta>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: &P,
+        fog_meta: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let fog_meta = fog_meta.into_inner();
+        pass.set_vertex_buffer(0, fog_meta.vertices.buffer().unwrap().slice(..));
+        pass.draw(item.batch_range().as_ref().unwrap().clone(), 0..1);
+        RenderCommandResult::Success
+    }
+}
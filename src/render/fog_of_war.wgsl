@@ -0,0 +1,53 @@
+struct View {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    world_position: vec3<f32>,
+    // viewport(x_origin, y_origin, width, height)
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vertex(
+    @location(0) vertex_position: vec3<f32>,
+    @location(1) vertex_uv: vec2<f32>,
+    @location(2) vertex_color: vec4<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.uv = vertex_uv;
+    out.color = vertex_color;
+    out.position = view.view_proj * vec4<f32>(vertex_position, 1.0);
+    return out;
+}
+
+// The vision lights, drawn like any light texture, see light.wgsl.
+@group(1) @binding(0)
+var light_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var light_sampler: sampler;
+// The explored cells, already scaled by the explored intensity.
+@group(1) @binding(2)
+var explored_texture: texture_2d<f32>;
+@group(1) @binding(3)
+var explored_sampler: sampler;
+
+// The fog color, covering whatever the vision lights don't reach this frame and wasn't
+// explored before.
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let visible = textureSample(light_texture, light_sampler, in.uv).a;
+    let explored = textureSample(explored_texture, explored_sampler, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * (1.0 - max(visible, explored)));
+}
@@ -0,0 +1,575 @@
+use std::{num::NonZeroU32, ops::Range};
+
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_asset::RenderAssets,
+        render_phase::TrackedRenderPass,
+        render_resource::{
+            AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+            BlendState, BufferBindingType, BufferUsages, BufferVec, CachedRenderPipelineId,
+            DynamicUniformBuffer, Extent3d, FilterMode, MultisampleState, PipelineCache,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+            TextureViewDescriptor, TextureViewDimension, VertexBufferLayout, VertexFormat,
+            VertexStepMode,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{CachedTexture, TextureCache},
+        view::{ExtractedView, ViewUniformOffset},
+        Extract,
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::Emissive2d;
+
+use super::{
+    sdf::{
+        begin_pass, offscreen_pipeline_descriptor, push_sprite_batch, sprite_bind_group,
+        ExtractedSpriteQuad, Light2dSdfMeta, Light2dSdfPipeline, Light2dSdfTextures,
+        ADDITIVE_BLENDING,
+    },
+    Light2dDebugView, Light2dGlobalIllumination, Light2dOverlay, Light2dShadowMode,
+};
+
+pub const GI_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597168);
+pub const GI_EMISSION_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597178);
+
+/// Emitted light and coverage, see `gi_emission.wgsl`.
+const EMISSION_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Mip levels of the emission texture, the widest cones read the last one.
+const MAX_EMISSION_LEVELS: u32 = 8;
+/// Most cones traced per pixel. Each cone marches the distance field, so many more could stall
+/// the GPU long enough for the driver to reset it.
+pub const MAX_GI_RAYS: u32 = 64;
+
+/// Settings of one [`Light2dGlobalIllumination`] view.
+#[derive(Clone, Copy, ShaderType)]
+pub struct Light2dGiUniform {
+    pub intensity: f32,
+    pub bounce: f32,
+    pub rays: u32,
+    /// [`Light2dGlobalIllumination::max_distance`] in texels of the light texture.
+    pub max_distance: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct EmissiveVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    /// Linear color scaled by the intensity.
+    pub color: [f32; 4],
+}
+
+#[derive(Resource)]
+pub struct Light2dGiPipeline {
+    pub gi_layout: BindGroupLayout,
+    sampler: Sampler,
+    emissive_pipeline: CachedRenderPipelineId,
+    bounce_pipeline: CachedRenderPipelineId,
+    downsample_pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for Light2dGiPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>().clone();
+        let sdf_pipeline = world.resource::<Light2dSdfPipeline>();
+        let view_layout = sdf_pipeline.view_layout.clone();
+        let sprite_layout = sdf_pipeline.sprite_layout.clone();
+
+        let gi_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(Light2dGiUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_gi_layout"),
+        });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            ..default()
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let emissive_pipeline =
+            pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+                "light_gi_emissive_pipeline",
+                GI_EMISSION_SHADER_HANDLE.typed(),
+                ("emissive_vertex", "emissive_fragment"),
+                vec![VertexBufferLayout::from_vertex_formats(
+                    VertexStepMode::Vertex,
+                    vec![
+                        VertexFormat::Float32x2, // position
+                        VertexFormat::Float32x2, // uv
+                        VertexFormat::Float32x4, // color
+                    ],
+                )],
+                vec![view_layout, sprite_layout],
+                EMISSION_TEXTURE_FORMAT,
+                Some(ADDITIVE_BLENDING),
+            ));
+        let bounce_pipeline = pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+            "light_gi_bounce_pipeline",
+            GI_SHADER_HANDLE.typed(),
+            ("fullscreen_vertex", "bounce"),
+            Vec::new(),
+            vec![gi_layout.clone()],
+            EMISSION_TEXTURE_FORMAT,
+            Some(ADDITIVE_BLENDING),
+        ));
+        let downsample_pipeline =
+            pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+                "light_gi_downsample_pipeline",
+                GI_SHADER_HANDLE.typed(),
+                ("fullscreen_vertex", "downsample"),
+                Vec::new(),
+                vec![gi_layout.clone()],
+                EMISSION_TEXTURE_FORMAT,
+                None,
+            ));
+
+        Self {
+            gi_layout,
+            sampler,
+            emissive_pipeline,
+            bounce_pipeline,
+            downsample_pipeline,
+        }
+    }
+}
+
+/// The trace pass draws into the light texture, so it has to match its format.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Light2dGiPipelineKey {
+    pub samples: u32,
+    pub format: TextureFormat,
+}
+
+impl SpecializedRenderPipeline for Light2dGiPipeline {
+    type Key = Light2dGiPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut descriptor = offscreen_pipeline_descriptor(
+            "light_gi_trace_pipeline",
+            GI_SHADER_HANDLE.typed(),
+            ("fullscreen_vertex", "trace"),
+            Vec::new(),
+            vec![self.gi_layout.clone()],
+            key.format,
+            Some(BlendState::ALPHA_BLENDING),
+        );
+        descriptor.multisample = MultisampleState {
+            count: key.samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+        descriptor
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedEmissiveSprite {
+    pub quad: ExtractedSpriteQuad,
+    pub color: [f32; 4],
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedEmissiveSprites {
+    pub sprites: Vec<ExtractedEmissiveSprite>,
+}
+
+pub fn extract_emissive_sprites(
+    mut emissive_sprites: ResMut<ExtractedEmissiveSprites>,
+    sprite_query: Extract<
+        Query<(
+            &Sprite,
+            &Handle<Image>,
+            &GlobalTransform,
+            &ComputedVisibility,
+            &Emissive2d,
+        )>,
+    >,
+) {
+    emissive_sprites.sprites.clear();
+    for (sprite, image, transform, visibility, emissive) in sprite_query.iter() {
+        // Light from off screen isn't traced either.
+        if !visibility.is_visible() || emissive.intensity <= 0.0 {
+            continue;
+        }
+        let [r, g, b, a] = emissive.color.as_linear_rgba_f32();
+        let intensity = emissive.intensity;
+        emissive_sprites.sprites.push(ExtractedEmissiveSprite {
+            quad: ExtractedSpriteQuad::new(sprite, image, transform),
+            color: [r * intensity, g * intensity, b * intensity, a],
+        });
+    }
+}
+
+#[derive(Resource)]
+pub struct Light2dGiMeta {
+    emissive_vertices: BufferVec<EmissiveVertex>,
+    /// Runs of `emissive_vertices` sharing an image.
+    emissive_batches: Vec<(Handle<Image>, Range<u32>)>,
+    sprite_bind_groups: HashMap<Handle<Image>, BindGroup>,
+    uniforms: DynamicUniformBuffer<Light2dGiUniform>,
+}
+
+impl Default for Light2dGiMeta {
+    fn default() -> Self {
+        Self {
+            emissive_vertices: BufferVec::new(BufferUsages::VERTEX),
+            emissive_batches: Vec::new(),
+            sprite_bind_groups: HashMap::default(),
+            uniforms: DynamicUniformBuffer::default(),
+        }
+    }
+}
+
+pub fn prepare_emissive_sprites(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut gi_meta: ResMut<Light2dGiMeta>,
+    emissive_sprites: Res<ExtractedEmissiveSprites>,
+    gpu_images: Res<RenderAssets<Image>>,
+) {
+    let gi_meta = &mut *gi_meta;
+    gi_meta.emissive_vertices.clear();
+    gi_meta.emissive_batches.clear();
+    for sprite in &emissive_sprites.sprites {
+        let gpu_image = match gpu_images.get(&sprite.quad.image) {
+            Some(gpu_image) => gpu_image,
+            None => continue,
+        };
+        let start = gi_meta.emissive_vertices.len() as u32;
+        for (position, uv) in sprite.quad.vertices(gpu_image.size) {
+            gi_meta.emissive_vertices.push(EmissiveVertex {
+                position: position.to_array(),
+                uv: uv.to_array(),
+                color: sprite.color,
+            });
+        }
+        let end = gi_meta.emissive_vertices.len() as u32;
+        push_sprite_batch(
+            &mut gi_meta.emissive_batches,
+            &sprite.quad.image,
+            start..end,
+        );
+    }
+    gi_meta
+        .emissive_vertices
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Bind groups of one [`Light2dGlobalIllumination`] view.
+pub struct Light2dGiBindGroups {
+    /// Reads the previous light texture.
+    bounce: BindGroup,
+    /// Read each mip level of the emission texture but the last.
+    downsample: Vec<BindGroup>,
+    /// Reads the whole emission texture.
+    trace: BindGroup,
+    trace_pipeline: CachedRenderPipelineId,
+    /// Of the view's [`Light2dGiUniform`].
+    offset: u32,
+}
+
+/// Render targets of a [`Light2dGlobalIllumination`] view, on its [`Light2dOverlay`] entity.
+#[derive(Component)]
+pub struct Light2dGiTextures {
+    emission: CachedTexture,
+    /// One view per mip level of `emission`.
+    levels: Vec<TextureView>,
+    settings: Light2dGiUniform,
+    pub bind_groups: Option<Light2dGiBindGroups>,
+}
+
+pub fn prepare_gi_textures(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &Light2dOverlay,
+        &Light2dShadowMode,
+        &Light2dDebugView,
+        &Light2dGlobalIllumination,
+    )>,
+) {
+    for (entity, view, overlay, shadow_mode, debug_view, global_illumination) in &views {
+        // Rays are traced through the shadows' distance field, and the overdraw and shadow mask
+        // views only show the lights.
+        if *shadow_mode == Light2dShadowMode::Edges
+            || matches!(
+                debug_view,
+                Light2dDebugView::Overdraw | Light2dDebugView::ShadowMask
+            )
+        {
+            continue;
+        }
+        let level_count =
+            (32 - overlay.size.max_element().leading_zeros()).min(MAX_EMISSION_LEVELS);
+        let emission = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("light_gi_emission_texture"),
+                size: Extent3d {
+                    width: overlay.size.x,
+                    height: overlay.size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: level_count,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: EMISSION_TEXTURE_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let levels = (0..level_count)
+            .map(|level| {
+                emission.texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    ..default()
+                })
+            })
+            .collect();
+        commands.entity(entity).insert(Light2dGiTextures {
+            emission,
+            levels,
+            settings: Light2dGiUniform {
+                intensity: global_illumination.intensity.max(0.0),
+                bounce: global_illumination.bounce.clamp(0.0, 0.95),
+                rays: global_illumination.rays.clamp(1, MAX_GI_RAYS),
+                max_distance: global_illumination.max_distance.max(0.0)
+                    * overlay.texels_per_unit(view),
+            },
+            bind_groups: None,
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_gi_bind_groups(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    gi_pipeline: Res<Light2dGiPipeline>,
+    sdf_pipeline: Res<Light2dSdfPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<Light2dGiPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut gi_meta: ResMut<Light2dGiMeta>,
+    gpu_images: Res<RenderAssets<Image>>,
+    mut views: Query<(&Light2dOverlay, &Light2dSdfTextures, &mut Light2dGiTextures)>,
+) {
+    let gi_meta = &mut *gi_meta;
+    // Images are replaced wholesale, so don't keep bind groups to old textures around.
+    gi_meta.sprite_bind_groups.clear();
+    if views.is_empty() {
+        return;
+    }
+
+    gi_meta.uniforms.clear();
+    let offsets = views
+        .iter()
+        .map(|(_, _, textures)| gi_meta.uniforms.push(textures.settings))
+        .collect::<Vec<_>>();
+    gi_meta.uniforms.write_buffer(&render_device, &render_queue);
+    let uniforms_binding = match gi_meta.uniforms.binding() {
+        Some(binding) => binding,
+        None => return,
+    };
+
+    for (image, _) in &gi_meta.emissive_batches {
+        if let Some(gpu_image) = gpu_images.get(image) {
+            gi_meta
+                .sprite_bind_groups
+                .entry(image.clone_weak())
+                .or_insert_with(|| {
+                    sprite_bind_group(&render_device, &sdf_pipeline.sprite_layout, gpu_image)
+                });
+        }
+    }
+
+    for ((overlay, sdf_textures, mut textures), offset) in views.iter_mut().zip(offsets) {
+        let light_image = match gpu_images.get(&overlay.image) {
+            Some(gpu_image) => gpu_image,
+            None => continue,
+        };
+        let gi_bind_group = |label, input: &TextureView| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(sdf_textures.result()),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(input),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::Sampler(&gi_pipeline.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: uniforms_binding.clone(),
+                    },
+                ],
+                label: Some(label),
+                layout: &gi_pipeline.gi_layout,
+            })
+        };
+        let trace_pipeline = pipelines.specialize(
+            &mut pipeline_cache,
+            &gi_pipeline,
+            Light2dGiPipelineKey {
+                samples: overlay.samples,
+                format: light_image.texture_format,
+            },
+        );
+        let bind_groups = Light2dGiBindGroups {
+            bounce: gi_bind_group("light_gi_bounce_bind_group", &light_image.texture_view),
+            downsample: textures.levels[..textures.levels.len() - 1]
+                .iter()
+                .map(|level| gi_bind_group("light_gi_downsample_bind_group", level))
+                .collect(),
+            trace: gi_bind_group("light_gi_trace_bind_group", &textures.emission.default_view),
+            trace_pipeline,
+            offset,
+        };
+        textures.bind_groups = Some(bind_groups);
+    }
+}
+
+/// Draws the light emitted by a [`Light2dGlobalIllumination`] view into its emission texture
+/// and downsamples it for the cone trace. Runs after the distance field is ready, returns
+/// whether the emission texture is.
+pub fn run_gi_passes(
+    world: &World,
+    render_context: &mut RenderContext,
+    view_entity: Entity,
+    textures: &Light2dGiTextures,
+) -> bool {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let gi_pipeline = world.resource::<Light2dGiPipeline>();
+    let gi_meta = world.resource::<Light2dGiMeta>();
+    let sdf_meta = world.resource::<Light2dSdfMeta>();
+    let (bind_groups, view_bind_group, view_uniform) = match (
+        textures.bind_groups.as_ref(),
+        sdf_meta.view_bind_group.as_ref(),
+        world.get::<ViewUniformOffset>(view_entity),
+    ) {
+        (Some(bind_groups), Some(view_bind_group), Some(view_uniform)) => {
+            (bind_groups, view_bind_group, view_uniform)
+        }
+        _ => return false,
+    };
+    let pipelines = [
+        gi_pipeline.emissive_pipeline,
+        gi_pipeline.bounce_pipeline,
+        gi_pipeline.downsample_pipeline,
+    ]
+    .map(|id| pipeline_cache.get_render_pipeline(id));
+    let [emissive_pipeline, bounce_pipeline, downsample_pipeline] = match pipelines {
+        [Some(emissive), Some(bounce), Some(downsample)] => [emissive, bounce, downsample],
+        _ => return false,
+    };
+
+    {
+        let mut pass = begin_pass(
+            render_context,
+            "light_gi_emission_pass",
+            &textures.levels[0],
+        );
+        if let Some(buffer) = gi_meta.emissive_vertices.buffer() {
+            pass.set_render_pipeline(emissive_pipeline);
+            pass.set_bind_group(0, view_bind_group, &[view_uniform.offset]);
+            pass.set_vertex_buffer(0, buffer.slice(..));
+            for (image, range) in &gi_meta.emissive_batches {
+                if let Some(bind_group) = gi_meta.sprite_bind_groups.get(image) {
+                    pass.set_bind_group(1, bind_group, &[]);
+                    pass.draw(range.clone(), 0..1);
+                }
+            }
+        }
+        pass.set_render_pipeline(bounce_pipeline);
+        pass.set_bind_group(0, &bind_groups.bounce, &[bind_groups.offset]);
+        pass.draw(0..3, 0..1);
+    }
+
+    for (level, bind_group) in bind_groups.downsample.iter().enumerate() {
+        let mut pass = begin_pass(
+            render_context,
+            "light_gi_downsample_pass",
+            &textures.levels[level + 1],
+        );
+        pass.set_render_pipeline(downsample_pipeline);
+        pass.set_bind_group(0, bind_group, &[bind_groups.offset]);
+        pass.draw(0..3, 0..1);
+    }
+
+    true
+}
+
+/// Traces the indirect light of a [`Light2dGlobalIllumination`] view into the light pass,
+/// before any light is drawn.
+pub fn draw_global_illumination(
+    world: &World,
+    pass: &mut TrackedRenderPass,
+    textures: &Light2dGiTextures,
+) {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    if let Some(bind_groups) = textures.bind_groups.as_ref() {
+        if let Some(pipeline) = pipeline_cache.get_render_pipeline(bind_groups.trace_pipeline) {
+            pass.set_render_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_groups.trace, &[bind_groups.offset]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+struct GlobalIllumination {
+    intensity: f32,
+    bounce: f32,
+    rays: u32,
+    max_distance: f32,
+};
+
+// Nearest occluded texel of each texel of the view, see sdf_flood.wgsl.
+@group(0) @binding(0)
+var sdf_texture: texture_2d<f32>;
+// The previous light texture for `bounce`, the previous mip level for `downsample` and the
+// emission texture for `trace`.
+@group(0) @binding(1)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var input_sampler: sampler;
+@group(0) @binding(3)
+var<uniform> gi: GlobalIllumination;
+
+#import light_2d::sdf
+
+let PI: f32 = 3.141592653589793;
+let GI_MAX_STEPS: u32 = 48u;
+
+// Re-emits the last frame's light in a thin band around the occluders, the lit surfaces. It
+// doesn't hide anything behind it.
+@fragment
+fn bounce(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(sdf_texture));
+    let d = sdf_distance(position.xy, size);
+    if (d < 1.0 || d > 3.0) {
+        return vec4<f32>(0.0);
+    }
+    let light = textureLoad(input_texture, vec2<i32>(position.xy), 0);
+    return vec4<f32>(light.rgb * light.a * gi.bounce, 0.0);
+}
+
+@fragment
+fn downsample(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // Halfway between four texels of the previous level, so the sampler averages them.
+    let uv = 2.0 * position.xy / vec2<f32>(textureDimensions(input_texture));
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0);
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+// Cone traces the emission texture around the pixel. Each cone reads the mip level as wide as
+// it is and stops at the first occluder, the distance field keeps it from stepping over one.
+@fragment
+fn trace(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(sdf_texture));
+    let rays = max(gi.rays, 1u);
+    // Half the width of a cone per texel travelled.
+    let spread = tan(PI / f32(rays));
+    let max_level = f32(textureNumLevels(input_texture) - 1);
+    // Turns the cones by a different angle per pixel, trading banding for noise.
+    let jitter = hash(position.xy);
+    var irradiance = vec3<f32>(0.0);
+    for (var r = 0u; r < rays; r = r + 1u) {
+        let angle = 2.0 * PI * (f32(r) + jitter) / f32(rays);
+        let direction = vec2<f32>(cos(angle), sin(angle));
+        var radiance = vec3<f32>(0.0);
+        var opacity = 0.0;
+        var t = 1.0;
+        for (var i = 0u; i < GI_MAX_STEPS && t < gi.max_distance && opacity < 0.99; i = i + 1u) {
+            let texel = position.xy + direction * t;
+            if (any(texel < vec2<f32>(0.0)) || any(texel >= size)) {
+                break;
+            }
+            let d = sdf_distance(texel, size);
+            if (d < 1.0) {
+                break;
+            }
+            let width = max(2.0 * spread * t, 1.0);
+            let emitted = textureSampleLevel(
+                input_texture,
+                input_sampler,
+                texel / size,
+                min(log2(width), max_level)
+            );
+            radiance += (1.0 - opacity) * emitted.rgb;
+            opacity += (1.0 - opacity) * min(emitted.a, 1.0);
+            t = t + max(min(0.5 * width, d), 1.0);
+        }
+        irradiance += radiance;
+    }
+    irradiance *= gi.intensity / f32(rays);
+
+    // Encoded like a light, see filter_light in light.wgsl.
+    let strength = max(irradiance.r, max(irradiance.g, irradiance.b));
+    if (strength <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(irradiance / strength, min(strength, 1.0));
+}
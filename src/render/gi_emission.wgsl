@@ -0,0 +1,46 @@
+struct View {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    world_position: vec3<f32>,
+    // viewport(x_origin, y_origin, width, height)
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var sprite_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var sprite_sampler: sampler;
+
+struct EmissiveOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn emissive_vertex(
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+) -> EmissiveOutput {
+    var out: EmissiveOutput;
+    out.position = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.uv = uv;
+    out.color = color;
+    return out;
+}
+
+// Emitted light in rgb, how much of what is behind the sprite it hides in a.
+@fragment
+fn emissive_fragment(in: EmissiveOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(sprite_texture, sprite_sampler, in.uv);
+    let coverage = texel.a * in.color.a;
+    return vec4<f32>(in.color.rgb * texel.rgb * coverage, coverage);
+}
@@ -0,0 +1,233 @@
+use bevy::{
+    core_pipeline::core_2d::Transparent2d,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext, SlotInfo, SlotType},
+        render_phase::{DrawFunctions, RenderPhase, TrackedRenderPass},
+        render_resource::{
+            Extent3d, LoadOp, Operations, RenderPassColorAttachment, RenderPassDescriptor,
+            TextureDescriptor, TextureDimension, TextureUsages, TextureView,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::TextureCache,
+        view::ExtractedView,
+    },
+};
+
+use super::{
+    ambient_occlusion::{draw_ambient_occlusion, Light2dAmbientOcclusionBindGroup},
+    fog_of_war::FogOfWarCopy,
+    gi::{draw_global_illumination, run_gi_passes, Light2dGiTextures},
+    sdf::{run_sdf_passes, Light2dSdfTextures},
+    Light2dOverlay, Light2dVolumetricMasks,
+};
+
+pub const NAME: &str = "light_2d";
+
+pub mod input {
+    pub const VIEW_ENTITY: &str = "view_entity";
+}
+
+pub mod node {
+    pub const LIGHT_PASS: &str = "light_pass";
+}
+
+/// Multisampled color target of a light texture, resolved into [`Light2dOverlay::image`].
+#[derive(Component)]
+pub struct Light2dOverlaySampledTexture {
+    pub view: TextureView,
+}
+
+pub fn prepare_light_overlay_textures(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    gpu_images: Res<RenderAssets<Image>>,
+    mut texture_cache: ResMut<TextureCache>,
+    overlays: Query<(Entity, &Light2dOverlay)>,
+) {
+    for (entity, overlay) in &overlays {
+        if overlay.samples <= 1 {
+            continue;
+        }
+        if let Some(gpu_image) = gpu_images.get(&overlay.image) {
+            let texture = texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some("light_overlay_sampled_texture"),
+                    size: Extent3d {
+                        width: overlay.size.x,
+                        height: overlay.size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: overlay.samples,
+                    dimension: TextureDimension::D2,
+                    format: gpu_image.texture_format,
+                    usage: TextureUsages::RENDER_ATTACHMENT,
+                },
+            );
+            commands
+                .entity(entity)
+                .insert(Light2dOverlaySampledTexture {
+                    view: texture.default_view,
+                });
+        }
+    }
+}
+
+/// Draws the lights queued for a [`Light2dOverlay`] view straight into its image.
+pub struct Light2dPassNode {
+    query: QueryState<
+        (
+            &'static ExtractedCamera,
+            &'static RenderPhase<Transparent2d>,
+            &'static Light2dOverlay,
+            Option<&'static Light2dOverlaySampledTexture>,
+            Option<&'static Light2dSdfTextures>,
+            Option<&'static Light2dGiTextures>,
+            Option<&'static Light2dAmbientOcclusionBindGroup>,
+            Option<&'static Light2dVolumetricMasks>,
+            Option<&'static FogOfWarCopy>,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl Light2dPassNode {
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: world.query_filtered(),
+        }
+    }
+}
+
+impl Node for Light2dPassNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Light2dPassNode::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (
+            camera,
+            transparent_phase,
+            overlay,
+            sampled_texture,
+            sdf_textures,
+            gi_textures,
+            ambient_occlusion,
+            volumetric_masks,
+            fog_of_war_copy,
+        ) = if let Ok(result) = self.query.get_manual(world, view_entity) {
+            result
+        } else {
+            return Ok(());
+        };
+        let gpu_image =
+            if let Some(gpu_image) = world.resource::<RenderAssets<Image>>().get(&overlay.image) {
+                gpu_image
+            } else {
+                return Ok(());
+            };
+
+        let sdf_ready = sdf_textures.map_or(false, |textures| {
+            run_sdf_passes(world, render_context, view_entity, textures)
+        });
+        // Indirect light is traced through the distance field.
+        let gi_ready = sdf_ready
+            && gi_textures.map_or(false, |textures| {
+                run_gi_passes(world, render_context, view_entity, textures)
+            });
+
+        let draw_functions = world.resource::<DrawFunctions<Transparent2d>>();
+
+        // Over the whole texture, like the distance field, so light shafts can map world
+        // positions to it without the viewport.
+        for mask in volumetric_masks.iter().flat_map(|masks| &masks.masks) {
+            let render_pass =
+                render_context
+                    .command_encoder
+                    .begin_render_pass(&RenderPassDescriptor {
+                        label: Some("light_volumetric_mask_pass_2d"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: &mask.texture.default_view,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Clear(Color::BLACK.into()),
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+            let mut tracked_pass = TrackedRenderPass::new(render_pass);
+            let mut draw_functions = draw_functions.write();
+            let draw_function = draw_functions.get_mut(mask.item.draw_function).unwrap();
+            draw_function.draw(world, &mut tracked_pass, view_entity, &mask.item);
+        }
+
+        let ops = Operations {
+            load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0).into()),
+            store: true,
+        };
+        let color_attachment = match sampled_texture {
+            Some(sampled_texture) => RenderPassColorAttachment {
+                view: &sampled_texture.view,
+                resolve_target: Some(&gpu_image.texture_view),
+                ops,
+            },
+            None => RenderPassColorAttachment {
+                view: &gpu_image.texture_view,
+                resolve_target: None,
+                ops,
+            },
+        };
+        let pass_descriptor = RenderPassDescriptor {
+            label: Some("light_pass_2d"),
+            color_attachments: &[Some(color_attachment)],
+            depth_stencil_attachment: None,
+        };
+
+        {
+            let render_pass = render_context
+                .command_encoder
+                .begin_render_pass(&pass_descriptor);
+
+            let mut draw_functions = draw_functions.write();
+            let mut tracked_pass = TrackedRenderPass::new(render_pass);
+            if let Some(viewport) = camera.viewport.as_ref() {
+                tracked_pass.set_camera_viewport(viewport);
+            }
+            // Under the indirect light and the lights, which wash it out.
+            if let (true, Some(ambient_occlusion)) = (sdf_ready, ambient_occlusion) {
+                draw_ambient_occlusion(world, &mut tracked_pass, ambient_occlusion);
+            }
+            if let (true, Some(gi_textures)) = (gi_ready, gi_textures) {
+                draw_global_illumination(world, &mut tracked_pass, gi_textures);
+            }
+            for item in &transparent_phase.items {
+                let draw_function = draw_functions.get_mut(item.draw_function).unwrap();
+                draw_function.draw(world, &mut tracked_pass, view_entity, item);
+            }
+        }
+
+        // Read back by the fog of war, once the vision lights are drawn.
+        if let Some(copy) = fog_of_war_copy {
+            copy.copy(render_context, gpu_image);
+        }
+
+        Ok(())
+    }
+}
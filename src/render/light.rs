@@ -1,5 +1,5 @@
 use bevy::{
-    core_pipeline::{core_2d::Transparent2d, tonemapping::Tonemapping},
+    core_pipeline::core_2d::Transparent2d,
     ecs::system::{
         lifetimeless::{Read, SQuery, SRes},
         SystemParamItem, SystemState,
@@ -8,14 +8,16 @@ use bevy::{
     reflect::TypeUuid,
     render::{
         extract_component::{ComponentUniforms, DynamicUniformIndex},
+        render_asset::{PrepareAssetError, RenderAsset, RenderAssets},
         render_phase::{
             BatchedPhaseItem, DrawFunctions, EntityRenderCommand, RenderCommand,
             RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass,
         },
         render_resource::{
-            BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferUsages,
-            BufferVec, PipelineCache, ShaderType, SpecializedRenderPipeline,
-            SpecializedRenderPipelines,
+            AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource,
+            BlendComponent, BlendFactor, BlendOperation, BufferUsages, BufferVec, FilterMode,
+            PipelineCache, Sampler, SamplerDescriptor, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureDescriptor, TextureUsages, WgpuFeatures,
         },
         render_resource::{
             BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
@@ -26,22 +28,33 @@ use bevy::{
             TextureViewDescriptor, TextureViewDimension, VertexBufferLayout, VertexFormat,
             VertexState, VertexStepMode,
         },
-        renderer::{RenderDevice, RenderQueue},
+        renderer::{RenderAdapter, RenderDevice, RenderQueue},
         texture::{
-            BevyDefault, DefaultImageSampler, GpuImage, ImageSampler, TextureFormatPixelInfo,
+            CachedTexture, DefaultImageSampler, GpuImage, ImageSampler, TextureCache,
+            TextureFormatPixelInfo,
         },
         view::ViewUniform,
-        view::{ExtractedView, ViewUniformOffset, ViewUniforms, VisibleEntities},
+        view::{ViewUniformOffset, ViewUniforms, VisibleEntities},
         Extract,
-    }, utils::FloatOrd,
+    },
+    utils::{FloatOrd, HashMap, HashSet},
 };
 use std::f32::consts::E;
 
 use bytemuck::{Pod, Zeroable};
+use wgpu::TextureFormatFeatureFlags;
 
-use crate::PointLight2d;
+use crate::{
+    light_radius, util::f32_to_f16_bits, Light2dCookie, Light2dFalloff, Light2dVolumetric,
+    Lightmap2d, PointLight2d, Static, VisionLight2d,
+};
 
-use super::Light2dOverlay;
+use super::{
+    fog_of_war::Light2dFogOfWarView,
+    sdf::{Light2dSdfTextures, Light2dSdfUniform},
+    shadow::{ExtractedShadows2d, Shadow2dEdgeTexture, Shadow2dPipeline, Shadow2dPipelineKey},
+    Light2dDebugView, Light2dOverlay,
+};
 
 pub const LIGHT_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597128);
@@ -55,29 +68,112 @@ pub struct Light2dUniform {
     pub inner_radius_mult: f32,
     pub inner_angle_mult: f32,
     pub is_full_angle: f32,
+    /// uv rectangle `(min, max)` of the cookie frame in its atlas.
+    pub cookie_rect: Vec4,
+    /// Cookie scroll offset, rotation and scale, see [`Light2dCookie`].
+    pub cookie_transform: Vec4,
+    /// First and number of the [`Shadow2dEdgeTexture`] edges tested against this light.
+    pub shadow_edge_start: u32,
+    pub shadow_edge_count: u32,
+    /// See [`Light2dVolumetric`].
+    pub volumetric_density: f32,
+    pub volumetric_decay: f32,
+    /// See [`PointLight2d::shadow_intensity`].
+    pub shadow_intensity: f32,
+}
+
+/// How the light shader evaluates distance, angle and falloff. Insert it before adding
+/// [`Light2dPlugin`](crate::Light2dPlugin) to override the default.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Light2dShading {
+    /// `Lookup32` when the adapter can filter 32-bit float textures, `Lookup16` otherwise.
+    #[default]
+    Auto,
+    /// `Rgba32Float` / `R32Float` lookup textures.
+    Lookup32,
+    /// `Rgba16Float` / `R16Float` lookup textures, filterable on every adapter including WebGL2.
+    Lookup16,
+    /// Distance and angle are computed in the shader, the falloff is read from a 16-bit lookup.
+    Analytic,
+}
+
+impl Light2dShading {
+    fn resolve(self, render_device: &RenderDevice, render_adapter: &RenderAdapter) -> Self {
+        match self {
+            Light2dShading::Auto => {
+                let float32_filterable = render_device
+                    .features()
+                    .contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                    && [TextureFormat::R32Float, TextureFormat::Rgba32Float]
+                        .into_iter()
+                        .all(|format| {
+                            render_adapter
+                                .get_texture_format_features(format)
+                                .flags
+                                .contains(TextureFormatFeatureFlags::FILTERABLE)
+                        });
+                if float32_filterable {
+                    Light2dShading::Lookup32
+                } else {
+                    Light2dShading::Lookup16
+                }
+            }
+            shading => shading,
+        }
+    }
+
+    fn falloff_lookup_format(self) -> TextureFormat {
+        match self {
+            Light2dShading::Lookup32 => TextureFormat::R32Float,
+            _ => TextureFormat::R16Float,
+        }
+    }
+
+    fn point_light_lookup_format(self) -> TextureFormat {
+        match self {
+            Light2dShading::Lookup32 => TextureFormat::Rgba32Float,
+            _ => TextureFormat::Rgba16Float,
+        }
+    }
 }
 
 #[derive(Resource)]
 pub struct Light2dPipeline {
+    pub shading: Light2dShading,
     pub view_layout: BindGroupLayout,
+    pub sdf_view_layout: BindGroupLayout,
     pub light_layout: BindGroupLayout,
     pub falloff_lookup_layout: BindGroupLayout,
     pub falloff_lookup_gpu_image: GpuImage,
     pub point_light_lookup_layout: BindGroupLayout,
     pub point_light_lookup_gpu_image: GpuImage,
+    /// Bound as the cookie of lights without a [`Light2dCookie`].
+    pub white_cookie_gpu_image: GpuImage,
+    /// Group 3 of [`Light2dVolumetric`] light shafts, the point light lookup and cookie followed
+    /// by the light's [`Light2dVolumetricMask`].
+    pub volumetric_lookup_layout: BindGroupLayout,
+    pub volumetric_mask_sampler: Sampler,
 }
 
 impl FromWorld for Light2dPipeline {
     fn from_world(world: &mut World) -> Self {
         let mut system_state: SystemState<(
             Res<RenderDevice>,
+            Res<RenderAdapter>,
             Res<DefaultImageSampler>,
             Res<RenderQueue>,
+            Option<Res<Light2dShading>>,
         )> = SystemState::new(world);
-        let (render_device, default_sampler, render_queue) = system_state.get_mut(world);
+        let (render_device, render_adapter, default_sampler, render_queue, shading) =
+            system_state.get_mut(world);
+        let shading = shading
+            .map(|shading| *shading)
+            .unwrap_or_default()
+            .resolve(&render_device, &render_adapter);
 
-        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &[BindGroupLayoutEntry {
+        // Shadow edges share the view group, WebGL2 only has four bind groups.
+        let view_entries = [
+            BindGroupLayoutEntry {
                 binding: 0,
                 visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
@@ -86,9 +182,50 @@ impl FromWorld for Light2dPipeline {
                     min_binding_size: Some(ViewUniform::min_size()),
                 },
                 count: None,
-            }],
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+        ];
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &view_entries,
             label: Some("light_view_layout"),
         });
+        // `Light2dShadowMode::Sdf` views add their distance field and its settings.
+        let sdf_view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                view_entries[0],
+                view_entries[1],
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(Light2dSdfUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_sdf_view_layout"),
+        });
         let light_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
@@ -126,66 +263,102 @@ impl FromWorld for Light2dPipeline {
                 label: Some("light2d_falloff_lookup_texture_layout"),
             });
 
+        // Texture and sampler pairs of the lookup, the cookie and, for light shafts, the mask.
+        let lookup_entries = [0, 1, 2].map(|index| {
+            [
+                BindGroupLayoutEntry {
+                    binding: 2 * index,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2 * index + 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]
+        });
+        let lookup_entries = lookup_entries.concat();
         let point_light_lookup_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Texture {
-                            multisampled: false,
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+                entries: &lookup_entries[..4],
                 label: Some("light2d_point_light_lookup_texture_layout"),
             });
+        let volumetric_lookup_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &lookup_entries,
+                label: Some("light2d_volumetric_lookup_texture_layout"),
+            });
+        let volumetric_mask_sampler = render_device.create_sampler(&SamplerDescriptor {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            ..default()
+        });
 
         let point_light_lookup_gpu_image = create_gpu_image_from_image(
-            create_point_light_lookup_image(),
+            create_point_light_lookup_image(shading.point_light_lookup_format()),
             &render_device,
             &default_sampler,
             &render_queue,
         );
 
         let falloff_lookup_gpu_image = create_gpu_image_from_image(
-            create_falloff_lookup_image(),
+            create_falloff_lookup_image(shading.falloff_lookup_format()),
+            &render_device,
+            &default_sampler,
+            &render_queue,
+        );
+
+        let white_cookie_gpu_image = create_gpu_image_from_image(
+            Image::default(),
             &render_device,
             &default_sampler,
             &render_queue,
         );
 
         Self {
+            shading,
             view_layout,
+            sdf_view_layout,
             light_layout,
             falloff_lookup_layout,
             falloff_lookup_gpu_image,
             point_light_lookup_layout,
             point_light_lookup_gpu_image,
+            white_cookie_gpu_image,
+            volumetric_lookup_layout,
+            volumetric_mask_sampler,
+        }
+    }
+}
+
+fn push_lookup_value(data: &mut Vec<u8>, value: f32, format: TextureFormat) {
+    match format {
+        TextureFormat::R16Float | TextureFormat::Rgba16Float => {
+            data.extend_from_slice(&f32_to_f16_bits(value).to_le_bytes())
         }
+        _ => data.extend_from_slice(&value.to_bits().to_le_bytes()),
     }
 }
 
-fn create_falloff_lookup_image() -> Image {
+pub fn create_falloff_lookup_image(format: TextureFormat) -> Image {
     const WIDTH: usize = 2048;
     const HEIGHT: usize = 128;
-    let mut data = Vec::with_capacity(WIDTH * HEIGHT * 4);
+    let mut data = Vec::with_capacity(WIDTH * HEIGHT * format.pixel_size());
     for y in 0..HEIGHT {
         for x in 0..WIDTH {
             let alpha: f32 = x as f32 / WIDTH as f32;
             let intensity: f32 = y as f32 / HEIGHT as f32;
             let falloff = alpha.powf(E.powf(1.5 - 3.0 * intensity));
-            for u in falloff.to_bits().to_le_bytes() {
-                data.push(u);
-            }
+            push_lookup_value(&mut data, falloff, format);
         }
     }
     Image::new_fill(
@@ -196,14 +369,31 @@ fn create_falloff_lookup_image() -> Image {
         },
         TextureDimension::D2,
         &data[..],
-        TextureFormat::R32Float,
+        format,
+    )
+}
+
+pub fn create_falloff_curve_image(samples: &[f32], format: TextureFormat) -> Image {
+    let mut data = Vec::with_capacity(samples.len() * format.pixel_size());
+    for falloff in samples {
+        push_lookup_value(&mut data, *falloff, format);
+    }
+    Image::new_fill(
+        Extent3d {
+            width: samples.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &data[..],
+        format,
     )
 }
 
-fn create_point_light_lookup_image() -> Image {
+pub fn create_point_light_lookup_image(format: TextureFormat) -> Image {
     const WIDTH: usize = 256;
     const HEIGHT: usize = 256;
-    let mut data = Vec::with_capacity(WIDTH * HEIGHT * 4 * 4);
+    let mut data = Vec::with_capacity(WIDTH * HEIGHT * format.pixel_size());
     let center = Vec2::new(WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
     for y in 0..HEIGHT {
         for x in 0..WIDTH {
@@ -225,9 +415,7 @@ fn create_point_light_lookup_image() -> Image {
             let alpha = direction.y;
 
             for f in vec![red, green, blue, alpha] {
-                for u in f.to_bits().to_le_bytes() {
-                    data.push(u);
-                }
+                push_lookup_value(&mut data, f, format);
             }
         }
     }
@@ -239,7 +427,7 @@ fn create_point_light_lookup_image() -> Image {
         },
         TextureDimension::D2,
         &data[..],
-        TextureFormat::Rgba32Float,
+        format,
     )
 }
 
@@ -291,97 +479,348 @@ fn create_gpu_image_from_image(
     }
 }
 
+pub struct GpuLight2dFalloff {
+    pub gpu_image: GpuImage,
+    pub bind_group: BindGroup,
+}
+
+impl RenderAsset for Light2dFalloff {
+    type ExtractedAsset = Vec<f32>;
+    type PreparedAsset = GpuLight2dFalloff;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<RenderQueue>,
+        SRes<DefaultImageSampler>,
+        SRes<Light2dPipeline>,
+    );
+
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        self.bake()
+    }
+
+    fn prepare_asset(
+        samples: Self::ExtractedAsset,
+        (render_device, render_queue, default_sampler, light2d_pipeline): &mut SystemParamItem<
+            Self::Param,
+        >,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let gpu_image = create_gpu_image_from_image(
+            create_falloff_curve_image(&samples, light2d_pipeline.shading.falloff_lookup_format()),
+            render_device,
+            default_sampler,
+            render_queue,
+        );
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&gpu_image.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&gpu_image.sampler),
+                },
+            ],
+            label: Some("light_falloff_curve_bind_group"),
+            layout: &light2d_pipeline.falloff_lookup_layout,
+        });
+        Ok(GpuLight2dFalloff {
+            gpu_image,
+            bind_group,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Light2dPipelineKey {
+    pub samples: u32,
+    pub format: TextureFormat,
+    /// Draws a [`Lightmap2d`] instead of a point light.
+    pub lightmap: bool,
+    /// Multiplies the light by the cookie in group 3, see [`Light2dCookie`].
+    pub cookie: bool,
+    /// Draws the light shafts of a [`Light2dVolumetric`] light from its
+    /// [`Light2dVolumetricMask`] in group 3.
+    pub volumetric: bool,
+    /// Counts covering light quads instead of shading them, for [`Light2dDebugView::Overdraw`].
+    pub overdraw: bool,
+    /// Marches the distance field of a [`Light2dShadowMode::Sdf`](super::Light2dShadowMode::Sdf)
+    /// view instead of testing edges.
+    pub sdf: bool,
+}
+
 impl SpecializedRenderPipeline for Light2dPipeline {
-    type Key = u32;
+    type Key = Light2dPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        let formats = vec![
-            VertexFormat::Float32x3, // position
-            VertexFormat::Float32x2, // uv
-        ];
+        let mut shader_defs = Vec::new();
+        if self.shading == Light2dShading::Analytic {
+            shader_defs.push("ANALYTIC_LIGHT".to_string());
+        }
+        if key.lightmap {
+            shader_defs.push("LIGHTMAP".to_string());
+        }
+        if key.cookie {
+            shader_defs.push("LIGHT_COOKIE".to_string());
+        }
+        if key.volumetric {
+            shader_defs.push("LIGHT_VOLUMETRIC".to_string());
+        }
+        let view_layout = if key.sdf {
+            shader_defs.push("SHADOW_SDF".to_string());
+            self.sdf_view_layout.clone()
+        } else {
+            self.view_layout.clone()
+        };
+        let blend = if key.overdraw {
+            shader_defs.push("LIGHT_OVERDRAW".to_string());
+            BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            }
+        } else {
+            BlendState::ALPHA_BLENDING
+        };
 
-        let vertex_layout =
-            VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
+        let lookup_layout = if key.volumetric {
+            self.volumetric_lookup_layout.clone()
+        } else {
+            self.point_light_lookup_layout.clone()
+        };
 
-        RenderPipelineDescriptor {
-            vertex: VertexState {
-                shader: LIGHT_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
-                entry_point: "vertex".into(),
-                buffers: vec![vertex_layout],
-            },
-            fragment: Some(FragmentState {
-                shader: LIGHT_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
-                entry_point: "fragment".into(),
-                targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            layout: Some(vec![
-                self.view_layout.clone(),
+        light_quad_pipeline_descriptor(
+            shader_defs,
+            vec![
+                view_layout,
                 self.light_layout.clone(),
                 self.falloff_lookup_layout.clone(),
-                self.point_light_lookup_layout.clone(),
-            ]),
-            primitive: PrimitiveState {
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 4,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+                lookup_layout,
+            ],
+            ColorTargetState {
+                format: key.format,
+                blend: Some(blend),
+                write_mask: ColorWrites::ALL,
             },
-            label: Some("light_2d_pipeline".into()),
-        }
+            key.samples,
+            "light_2d_pipeline",
+        )
     }
 }
 
-#[derive(Component, Clone, Copy)]
+/// A pipeline drawing light quads with `light.wgsl`, shared by [`Light2dPipeline`] and
+/// [`Shadow2dPipeline`](super::shadow::Shadow2dPipeline).
+pub(crate) fn light_quad_pipeline_descriptor(
+    shader_defs: Vec<String>,
+    layout: Vec<BindGroupLayout>,
+    target: ColorTargetState,
+    samples: u32,
+    label: &'static str,
+) -> RenderPipelineDescriptor {
+    let formats = vec![
+        VertexFormat::Float32x3, // position
+        VertexFormat::Float32x2, // uv
+    ];
+
+    let vertex_layout = VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
+
+    RenderPipelineDescriptor {
+        vertex: VertexState {
+            shader: LIGHT_SHADER_HANDLE.typed::<Shader>(),
+            shader_defs: shader_defs.clone(),
+            entry_point: "vertex".into(),
+            buffers: vec![vertex_layout],
+        },
+        fragment: Some(FragmentState {
+            shader: LIGHT_SHADER_HANDLE.typed::<Shader>(),
+            shader_defs,
+            entry_point: "fragment".into(),
+            targets: vec![Some(target)],
+        }),
+        layout: Some(layout),
+        primitive: PrimitiveState {
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        label: Some(label.into()),
+    }
+}
+
+#[derive(Component, Clone)]
 pub struct ExtractedPointLight2d {
     pub transform: GlobalTransform,
+    pub falloff: Option<Handle<Light2dFalloff>>,
+    /// Atlas image of the light's [`Light2dCookie`].
+    pub cookie: Option<Handle<Image>>,
+    /// Also draws the light's [`Light2dVolumetric`] pass.
+    pub volumetric: bool,
+    /// Drawn into the fog of war views, see [`VisionLight2d`].
+    pub vision: bool,
+}
+
+/// The image of a lightmap drawn in place of the point light lookup.
+#[derive(Component, Clone)]
+pub struct ExtractedLightmap2d {
+    pub image: Handle<Image>,
 }
 
 pub fn extract_lights(
     mut commands: Commands,
     mut previous_len: Local<usize>,
-    light_query: Extract<Query<(Entity, &ComputedVisibility, &PointLight2d, &GlobalTransform)>>,
+    light_query: Extract<
+        Query<(
+            Entity,
+            &ComputedVisibility,
+            &PointLight2d,
+            &GlobalTransform,
+            Option<&Static>,
+            Option<&Light2dCookie>,
+            Option<&Light2dVolumetric>,
+            Option<&VisionLight2d>,
+        )>,
+    >,
+    lightmap_query: Extract<Query<(Entity, &ComputedVisibility, &Lightmap2d)>>,
+    atlases: Extract<Option<Res<Assets<TextureAtlas>>>>,
+    mut shadows: ResMut<ExtractedShadows2d>,
+    mut warned: Local<HashSet<Entity>>,
 ) {
     let mut values = Vec::with_capacity(*previous_len);
-    for (entity, visibility, light, transform) in light_query.iter() {
-        if !visibility.is_visible() {
+    // Only the lights that are still invalid are remembered, so despawned and fixed lights
+    // don't pile up.
+    let mut invalid = HashSet::default();
+    for (entity, visibility, light, transform, is_static, cookie, volumetric, vision) in
+        light_query.iter()
+    {
+        // Warn once per invalid light rather than every frame.
+        if let Err(error) = light.validate() {
+            if !warned.contains(&entity) {
+                warn!("PointLight2d on {entity:?} is invalid and will be clamped: {error}");
+            }
+            invalid.insert(entity);
+        }
+        // The fog of war is revealed by vision lights that no camera sees too.
+        let visible = match vision {
+            Some(_) => visibility.is_visible_in_hierarchy(),
+            None => visibility.is_visible(),
+        };
+        if !visible {
             continue;
         }
+        // Already part of a baked lightmap, which only holds the light within its bounds.
+        if is_static.is_some()
+            && lightmap_query.iter().any(|(_, _, lightmap)| {
+                !lightmap.bake
+                    && lightmap.contains_circle(
+                        transform.translation().truncate(),
+                        light_radius(transform),
+                    )
+            })
+        {
+            continue;
+        }
+        let light = light.sanitized();
+        let cookie = cookie.and_then(|cookie| cookie.sample_params(atlases.as_deref()?));
+        let (cookie_rect, cookie_transform) = cookie
+            .as_ref()
+            .map_or((Vec4::ZERO, Vec4::ZERO), |(_, rect, transform)| {
+                (*rect, *transform)
+            });
+        // Only the casters within the light's radius are tested per pixel.
+        let (shadow_edge_start, shadow_edge_count) = if light.shadow_intensity > 0.0 {
+            shadows.push_light_edges(transform.translation().truncate(), light_radius(transform))
+        } else {
+            (0, 0)
+        };
         values.push((
             entity,
             (
                 Light2dUniform {
-                    light_color: light.color.as_linear_rgba_f32().into(),
+                    light_color: light.linear_color(),
                     light_position: transform.translation(),
                     falloff_intensity: light.falloff_intensity,
                     outer_angle: light.outer_angle,
-                    inner_radius_mult: 1.0 / (1.0 - light.inner_radius),
-                    inner_angle_mult: 1.0 / (light.outer_angle - light.inner_angle),
+                    inner_radius_mult: light.inner_radius_mult(),
+                    inner_angle_mult: light.inner_angle_mult(),
                     is_full_angle: if light.inner_angle == 1.0 { 1.0 } else { 0.0 },
+                    cookie_rect,
+                    cookie_transform,
+                    shadow_edge_start,
+                    shadow_edge_count,
+                    volumetric_density: volumetric.map_or(0.0, |v| v.density.max(0.0)),
+                    volumetric_decay: volumetric.map_or(0.0, |v| v.decay.max(0.0)),
+                    shadow_intensity: light.shadow_intensity,
                 },
                 ExtractedPointLight2d {
                     transform: *transform,
+                    falloff: light.falloff,
+                    cookie: cookie.map(|(image, _, _)| image),
+                    volumetric: volumetric.is_some(),
+                    vision: vision.is_some(),
                 },
             ),
         ));
     }
 
+    *warned = invalid;
     *previous_len = values.len();
     commands.insert_or_spawn_batch(values);
+
+    for (entity, visibility, lightmap) in lightmap_query.iter() {
+        if !visibility.is_visible() || lightmap.bake {
+            continue;
+        }
+        let world_size = lightmap.world_size();
+        commands.get_or_spawn(entity).insert((
+            Light2dUniform {
+                light_color: lightmap.tint.as_linear_rgba_f32().into(),
+                light_position: (lightmap.origin + world_size / 2.0).extend(0.0),
+                falloff_intensity: 0.0,
+                outer_angle: 0.0,
+                inner_radius_mult: 0.0,
+                inner_angle_mult: 0.0,
+                is_full_angle: 0.0,
+                cookie_rect: Vec4::ZERO,
+                cookie_transform: Vec4::ZERO,
+                // Baked with the shadows already.
+                shadow_edge_start: 0,
+                shadow_edge_count: 0,
+                volumetric_density: 0.0,
+                volumetric_decay: 0.0,
+                shadow_intensity: 0.0,
+            },
+            ExtractedPointLight2d {
+                transform: GlobalTransform::from(
+                    Transform::from_translation((lightmap.origin + world_size / 2.0).extend(0.0))
+                        .with_scale(world_size.extend(1.0)),
+                ),
+                falloff: None,
+                cookie: None,
+                volumetric: false,
+                vision: false,
+            },
+            ExtractedLightmap2d {
+                image: lightmap.image.clone(),
+            },
+        ));
+    }
 }
 
 #[derive(Resource)]
@@ -423,7 +862,7 @@ pub fn queue_light_bind_group(
                     },
                 ],
                 label: Some("light_lookup_bind_group"),
-                layout: &light2d_pipeline.point_light_lookup_layout,
+                layout: &light2d_pipeline.falloff_lookup_layout,
             }),
             light_lookup_bind_group: render_device.create_bind_group(&BindGroupDescriptor {
                 entries: &[
@@ -439,6 +878,18 @@ pub fn queue_light_bind_group(
                             &light2d_pipeline.point_light_lookup_gpu_image.sampler,
                         ),
                     },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(
+                            &light2d_pipeline.white_cookie_gpu_image.texture_view,
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::Sampler(
+                            &light2d_pipeline.white_cookie_gpu_image.sampler,
+                        ),
+                    },
                 ],
                 label: Some("light_lookup_bind_group"),
                 layout: &light2d_pipeline.point_light_lookup_layout,
@@ -447,6 +898,129 @@ pub fn queue_light_bind_group(
     }
 }
 
+/// Group 3 bind groups of lightmaps and of lights with a cookie, keyed by their image.
+#[derive(Resource, Default)]
+pub struct Light2dImageBindGroups {
+    lightmaps: HashMap<Handle<Image>, BindGroup>,
+    cookies: HashMap<Handle<Image>, BindGroup>,
+}
+
+impl Light2dImageBindGroups {
+    /// Group 3 with `lookup` in bindings 0 and 1 and `cookie` in bindings 2 and 3.
+    fn create(
+        render_device: &RenderDevice,
+        light_pipeline: &Light2dPipeline,
+        lookup: &GpuImage,
+        cookie: &GpuImage,
+        label: &'static str,
+    ) -> BindGroup {
+        render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&lookup.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&lookup.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&cookie.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&cookie.sampler),
+                },
+            ],
+            label: Some(label),
+            layout: &light_pipeline.point_light_lookup_layout,
+        })
+    }
+}
+
+/// What the casters let through of a [`Light2dVolumetric`] light in a view, drawn with the
+/// [`Shadow2dPipeline`] before the view's lights. Its light shafts radially blur
+/// it towards the light.
+pub struct Light2dVolumetricMask {
+    pub texture: CachedTexture,
+    /// Draws the light into `texture`, with the same vertices as the light itself.
+    pub item: Transparent2d,
+}
+
+/// The [`Light2dVolumetricMask`]s of a view and, by light, the group 3 bind groups reading them.
+#[derive(Component, Default)]
+pub struct Light2dVolumetricMasks {
+    pub masks: Vec<Light2dVolumetricMask>,
+    bind_groups: HashMap<Entity, BindGroup>,
+}
+
+impl Light2dVolumetricMasks {
+    const FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        render_device: &RenderDevice,
+        texture_cache: &mut TextureCache,
+        light_pipeline: &Light2dPipeline,
+        size: UVec2,
+        cookie: &GpuImage,
+        light: Entity,
+        item: Transparent2d,
+    ) {
+        let texture = texture_cache.get(
+            render_device,
+            TextureDescriptor {
+                label: Some("light_volumetric_mask_texture"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        let lookup = &light_pipeline.point_light_lookup_gpu_image;
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&lookup.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&lookup.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&cookie.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&cookie.sampler),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&texture.default_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::Sampler(&light_pipeline.volumetric_mask_sampler),
+                },
+            ],
+            label: Some("light_volumetric_bind_group"),
+            layout: &light_pipeline.volumetric_lookup_layout,
+        });
+        self.bind_groups.insert(light, bind_group);
+        self.masks.push(Light2dVolumetricMask { texture, item });
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 struct Light2dVertex {
@@ -487,6 +1061,7 @@ const QUAD_UVS: [Vec2; 4] = [
 
 #[allow(clippy::too_many_arguments)]
 pub fn queue_lights(
+    mut commands: Commands,
     draw_functions: Res<DrawFunctions<Transparent2d>>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
@@ -495,14 +1070,34 @@ pub fn queue_lights(
 
     light_pipeline: Res<Light2dPipeline>,
     mut pipelines: ResMut<SpecializedRenderPipelines<Light2dPipeline>>,
+    (shadow_pipeline, mut shadow_pipelines): (
+        Res<Shadow2dPipeline>,
+        ResMut<SpecializedRenderPipelines<Shadow2dPipeline>>,
+    ),
     mut pipeline_cache: ResMut<PipelineCache>,
-    light2d: Query<(&Light2dUniform, &ExtractedPointLight2d)>,
-    mut views: Query<
-        (&ExtractedView, &VisibleEntities, Option<&Tonemapping>),
-        With<RenderPhase<Transparent2d>>,
-    >,
-    mut child_query: Query<&mut RenderPhase<Transparent2d>, With<Light2dOverlay>>,
+    light2d: Query<(
+        Entity,
+        &Light2dUniform,
+        &ExtractedPointLight2d,
+        Option<&ExtractedLightmap2d>,
+    )>,
+    views: Query<&VisibleEntities, With<RenderPhase<Transparent2d>>>,
+    gpu_images: Res<RenderAssets<Image>>,
+    mut child_query: Query<(
+        Entity,
+        &mut RenderPhase<Transparent2d>,
+        &Light2dOverlay,
+        &Light2dDebugView,
+        Option<&Light2dSdfTextures>,
+        Option<&Light2dFogOfWarView>,
+    )>,
+    mut image_bind_groups: ResMut<Light2dImageBindGroups>,
+    shadow_edge_texture: Res<Shadow2dEdgeTexture>,
+    mut texture_cache: ResMut<TextureCache>,
 ) {
+    // Baked images are replaced wholesale, so don't keep bind groups to old textures around.
+    image_bind_groups.lightmaps.clear();
+    image_bind_groups.cookies.clear();
     if light2d.is_empty() {
         return;
     }
@@ -510,56 +1105,230 @@ pub fn queue_lights(
         let light_meta = &mut light_meta;
         light_meta.vertices.clear();
         light_meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: view_binding,
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: view_binding,
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&shadow_edge_texture.texture_view),
+                },
+            ],
             label: Some("light_view_bind_group"),
             layout: &light_pipeline.view_layout,
         }));
 
         let draw_sprite_function = draw_functions.read().get_id::<DrawLight>().unwrap();
+        let draw_volumetric_function = draw_functions
+            .read()
+            .get_id::<DrawVolumetricLight>()
+            .unwrap();
         let mut colored_index = 0;
 
-        for (view, visible_entities, tonemapping) in &mut views {
-            let pipeline = pipelines.specialize(&mut pipeline_cache, &light_pipeline, 0);
-
-            for mut transparent_phase in &mut child_query {
-                for visible_entity in &visible_entities.entities {
-                    if let Ok((light_uniform, extracted_light)) = light2d.get(*visible_entity) {
-                        // Apply size and global transform
-                        let positions = QUAD_VERTEX_POSITIONS.map(|quad_pos| {
-                            extracted_light
-                                .transform
-                                .transform_point(quad_pos.extend(0.))
-                                .into()
-                        });
-                        let uvs = QUAD_UVS.map(|quad_uv| quad_uv.into());
+        // Fog of war views draw their vision lights, whether a camera sees them or not.
+        let vision_lights = light2d
+            .iter()
+            .filter(|(_, _, extracted_light, _)| extracted_light.vision)
+            .map(|(entity, ..)| entity)
+            .collect::<Vec<_>>();
+        let view_lights = views
+            .iter()
+            .map(|visible_entities| visible_entities.entities.as_slice())
+            .collect::<Vec<_>>();
 
-                        // These items will be sorted by depth with other phase items
-                        let sort_key = FloatOrd(extracted_light.transform.translation().z);
+        for (view_entity, mut transparent_phase, overlay, debug_view, sdf_textures, fog_of_war) in
+            &mut child_query
+        {
+            let format = match gpu_images.get(&overlay.image) {
+                Some(gpu_image) => gpu_image.texture_format,
+                None => continue,
+            };
+            // Until its distance field is ready, an SDF view falls back to the edges.
+            let sdf = sdf_textures.map_or(false, |textures| textures.bind_groups.is_some());
+            let key = Light2dPipelineKey {
+                samples: overlay.samples,
+                format,
+                lightmap: false,
+                cookie: false,
+                volumetric: false,
+                overdraw: *debug_view == Light2dDebugView::Overdraw,
+                sdf,
+            };
+            let shadow_mask = *debug_view == Light2dDebugView::ShadowMask;
+            let shadow_key = Shadow2dPipelineKey {
+                samples: overlay.samples,
+                format,
+                lightmap: false,
+                cookie: false,
+                sdf,
+            };
+            // The overdraw view counts the light shafts' quads, the shadow mask and fog of war
+            // views have no use for them.
+            let light_shafts = !shadow_mask && fog_of_war.is_none();
+            let mut volumetric_masks = Light2dVolumetricMasks::default();
 
-                        // Store the vertex data and add the item to the render phase
-                        for i in QUAD_INDICES {
-                            light_meta.vertices.push(Light2dVertex {
-                                position: positions[i],
-                                uv: uvs[i],
+            let lights = match fog_of_war {
+                Some(_) => vec![vision_lights.as_slice()],
+                None => view_lights.clone(),
+            };
+            for visible_entity in lights.into_iter().flatten() {
+                if let Ok((_, _, extracted_light, lightmap)) = light2d.get(*visible_entity) {
+                    let mut cookie_gpu_image = &light_pipeline.white_cookie_gpu_image;
+                    let cookie = if let Some(lightmap) = lightmap {
+                        let gpu_image = match gpu_images.get(&lightmap.image) {
+                            Some(gpu_image) => gpu_image,
+                            None => continue,
+                        };
+                        image_bind_groups
+                            .lightmaps
+                            .entry(lightmap.image.clone_weak())
+                            .or_insert_with(|| {
+                                Light2dImageBindGroups::create(
+                                    &render_device,
+                                    &light_pipeline,
+                                    gpu_image,
+                                    &light_pipeline.white_cookie_gpu_image,
+                                    "lightmap_bind_group",
+                                )
+                            });
+                        false
+                    } else if let Some((cookie, gpu_image)) = extracted_light
+                        .cookie
+                        .as_ref()
+                        .and_then(|cookie| Some((cookie, gpu_images.get(cookie)?)))
+                    {
+                        image_bind_groups
+                            .cookies
+                            .entry(cookie.clone_weak())
+                            .or_insert_with(|| {
+                                Light2dImageBindGroups::create(
+                                    &render_device,
+                                    &light_pipeline,
+                                    &light_pipeline.point_light_lookup_gpu_image,
+                                    gpu_image,
+                                    "light_cookie_bind_group",
+                                )
                             });
-                        }
-                        let item_start = colored_index;
-                        colored_index += QUAD_INDICES.len() as u32;
-                        let item_end = colored_index;
+                        cookie_gpu_image = gpu_image;
+                        true
+                    } else {
+                        false
+                    };
+                    let pipeline = if shadow_mask {
+                        shadow_pipelines.specialize(
+                            &mut pipeline_cache,
+                            &shadow_pipeline,
+                            Shadow2dPipelineKey {
+                                lightmap: lightmap.is_some(),
+                                cookie,
+                                ..shadow_key
+                            },
+                        )
+                    } else {
+                        pipelines.specialize(
+                            &mut pipeline_cache,
+                            &light_pipeline,
+                            Light2dPipelineKey {
+                                lightmap: lightmap.is_some(),
+                                cookie,
+                                ..key
+                            },
+                        )
+                    };
+                    // Apply size and global transform
+                    let positions = QUAD_VERTEX_POSITIONS.map(|quad_pos| {
+                        extracted_light
+                            .transform
+                            .transform_point(quad_pos.extend(0.))
+                            .into()
+                    });
+                    let uvs = QUAD_UVS.map(|quad_uv| quad_uv.into());
+
+                    // These items will be sorted by depth with other phase items
+                    let sort_key = FloatOrd(extracted_light.transform.translation().z);
 
+                    // Store the vertex data and add the item to the render phase
+                    for i in QUAD_INDICES {
+                        light_meta.vertices.push(Light2dVertex {
+                            position: positions[i],
+                            uv: uvs[i],
+                        });
+                    }
+                    let item_start = colored_index;
+                    colored_index += QUAD_INDICES.len() as u32;
+                    let item_end = colored_index;
+
+                    transparent_phase.add(Transparent2d {
+                        draw_function: draw_sprite_function,
+                        pipeline: pipeline,
+                        entity: *visible_entity,
+                        sort_key,
+                        batch_range: Some(item_start..item_end),
+                    });
+                    if !extracted_light.volumetric || !light_shafts {
+                        continue;
+                    }
+                    if key.overdraw {
                         transparent_phase.add(Transparent2d {
                             draw_function: draw_sprite_function,
-                            pipeline: pipeline,
+                            pipeline,
                             entity: *visible_entity,
                             sort_key,
                             batch_range: Some(item_start..item_end),
                         });
+                        continue;
                     }
+                    // Lights visible to several cameras are listed once per camera.
+                    if volumetric_masks.bind_groups.contains_key(visible_entity) {
+                        continue;
+                    }
+                    let mask_item = Transparent2d {
+                        draw_function: draw_sprite_function,
+                        pipeline: shadow_pipelines.specialize(
+                            &mut pipeline_cache,
+                            &shadow_pipeline,
+                            Shadow2dPipelineKey {
+                                samples: 1,
+                                format: Light2dVolumetricMasks::FORMAT,
+                                cookie,
+                                ..shadow_key
+                            },
+                        ),
+                        entity: *visible_entity,
+                        sort_key,
+                        batch_range: Some(item_start..item_end),
+                    };
+                    volumetric_masks.push(
+                        &render_device,
+                        &mut texture_cache,
+                        &light_pipeline,
+                        overlay.size,
+                        cookie_gpu_image,
+                        *visible_entity,
+                        mask_item,
+                    );
+                    // Over the light itself, with the same vertices.
+                    transparent_phase.add(Transparent2d {
+                        draw_function: draw_volumetric_function,
+                        pipeline: pipelines.specialize(
+                            &mut pipeline_cache,
+                            &light_pipeline,
+                            Light2dPipelineKey {
+                                cookie,
+                                volumetric: true,
+                                ..key
+                            },
+                        ),
+                        entity: *visible_entity,
+                        sort_key,
+                        batch_range: Some(item_start..item_end),
+                    });
                 }
             }
+            if !volumetric_masks.masks.is_empty() {
+                commands.entity(view_entity).insert(volumetric_masks);
+            }
         }
         light_meta
             .vertices
@@ -575,9 +1344,22 @@ pub type DrawLight = (
     SetLightLookupBindGroup<3>,
     DrawLightBatch,
 );
+/// [`DrawLight`] of [`Light2dVolumetric`] light shafts, reading the light's
+/// [`Light2dVolumetricMask`].
+pub type DrawVolumetricLight = (
+    SetItemPipeline,
+    SetLightViewBindGroup<0>,
+    SetSpriteTextureBindGroup<1>,
+    SetFalloffLookupBindGroup<2>,
+    SetVolumetricLookupBindGroup<3>,
+    DrawLightBatch,
+);
 pub struct SetLightViewBindGroup<const I: usize>;
 impl<const I: usize> EntityRenderCommand for SetLightViewBindGroup<I> {
-    type Param = (SRes<LightMeta>, SQuery<Read<ViewUniformOffset>>);
+    type Param = (
+        SRes<LightMeta>,
+        SQuery<(Read<ViewUniformOffset>, Option<Read<Light2dSdfTextures>>)>,
+    );
 
     fn render<'w>(
         view: Entity,
@@ -585,12 +1367,19 @@ impl<const I: usize> EntityRenderCommand for SetLightViewBindGroup<I> {
         (sprite_meta, view_query): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let view_uniform = view_query.get(view).unwrap();
-        pass.set_bind_group(
-            I,
-            sprite_meta.into_inner().view_bind_group.as_ref().unwrap(),
-            &[view_uniform.offset],
-        );
+        let (view_uniform, sdf_textures) = view_query.get_inner(view).unwrap();
+        match sdf_textures.and_then(|textures| textures.bind_groups.as_ref()) {
+            Some(bind_groups) => pass.set_bind_group(
+                I,
+                &bind_groups.light,
+                &[view_uniform.offset, bind_groups.settings_offset],
+            ),
+            None => pass.set_bind_group(
+                I,
+                sprite_meta.into_inner().view_bind_group.as_ref().unwrap(),
+                &[view_uniform.offset],
+            ),
+        }
         RenderCommandResult::Success
     }
 }
@@ -620,30 +1409,87 @@ impl<const I: usize> EntityRenderCommand for SetSpriteTextureBindGroup<I> {
 
 pub struct SetLightLookupBindGroup<const I: usize>;
 impl<const I: usize> EntityRenderCommand for SetLightLookupBindGroup<I> {
-    type Param = SRes<Light2dBindGroup>;
+    type Param = (
+        SRes<Light2dBindGroup>,
+        SRes<Light2dImageBindGroups>,
+        SQuery<(
+            Read<ExtractedPointLight2d>,
+            Option<Read<ExtractedLightmap2d>>,
+        )>,
+    );
 
     fn render<'w>(
         _view: Entity,
         item: Entity,
-        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        (bind_groups, image_bind_groups, light_query): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        pass.set_bind_group(I, &bind_groups.into_inner().light_lookup_bind_group, &[]);
+        let image_bind_groups = image_bind_groups.into_inner();
+        let image_bind_group =
+            light_query
+                .get(item)
+                .ok()
+                .and_then(|(light, lightmap)| match lightmap {
+                    Some(lightmap) => image_bind_groups.lightmaps.get(&lightmap.image),
+                    None => image_bind_groups.cookies.get(light.cookie.as_ref()?),
+                });
+        match image_bind_group {
+            Some(bind_group) => pass.set_bind_group(I, bind_group, &[]),
+            None => pass.set_bind_group(I, &bind_groups.into_inner().light_lookup_bind_group, &[]),
+        }
         RenderCommandResult::Success
     }
 }
 
+pub struct SetVolumetricLookupBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetVolumetricLookupBindGroup<I> {
+    type Param = SQuery<Read<Light2dVolumetricMasks>>;
+
+    fn render<'w>(
+        view: Entity,
+        item: Entity,
+        view_query: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let bind_group = view_query
+            .get_inner(view)
+            .ok()
+            .and_then(|masks| masks.bind_groups.get(&item));
+        match bind_group {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
+
 pub struct SetFalloffLookupBindGroup<const I: usize>;
 impl<const I: usize> EntityRenderCommand for SetFalloffLookupBindGroup<I> {
-    type Param = SRes<Light2dBindGroup>;
+    type Param = (
+        SRes<Light2dBindGroup>,
+        SRes<RenderAssets<Light2dFalloff>>,
+        SQuery<Read<ExtractedPointLight2d>>,
+    );
 
     fn render<'w>(
         _view: Entity,
         item: Entity,
-        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        (bind_groups, falloffs, light_query): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        pass.set_bind_group(I, &bind_groups.into_inner().falloff_lookup_bind_group, &[]);
+        let falloff = light_query
+            .get(item)
+            .ok()
+            .and_then(|light| light.falloff.as_ref())
+            .and_then(|handle| falloffs.into_inner().get(handle));
+        match falloff {
+            Some(falloff) => pass.set_bind_group(I, &falloff.bind_group, &[]),
+            None => {
+                pass.set_bind_group(I, &bind_groups.into_inner().falloff_lookup_bind_group, &[])
+            }
+        }
         RenderCommandResult::Success
     }
 }
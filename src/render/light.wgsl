@@ -17,16 +17,28 @@ struct Light {
     inner_radius_mult: f32,
     inner_angle_mult: f32,
     is_full_angle: f32,
+    // min uv, max uv of the cookie frame in its atlas
+    cookie_rect: vec4<f32>,
+    // scroll offset, rotation, scale
+    cookie_transform: vec4<f32>,
+    shadow_edge_start: u32,
+    shadow_edge_count: u32,
+    volumetric_density: f32,
+    volumetric_decay: f32,
+    shadow_intensity: f32,
 }
 
 @group(0) @binding(0)
 var<uniform> view: View;
-
 @group(1) @binding(0)
 var<uniform> light: Light;
 
+// Occlusion of the light by the casters, needs `view` and `light`.
+#import light_2d::shadow
+
 struct VertexOutput {
     @location(0) uv: vec2<f32>,
+    @location(1) world_position: vec2<f32>,
     @builtin(position) position: vec4<f32>,
 };
 
@@ -37,6 +49,7 @@ fn vertex(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.uv = vertex_uv;
+    out.world_position = vertex_position.xy;
     out.position = view.view_proj * vec4<f32>(vertex_position, 1.0);
     return out;
 }
@@ -50,11 +63,114 @@ var falloff_lookup_sampler: sampler;
 var light_lookup_texture: texture_2d<f32>;
 @group(3) @binding(1)
 var light_lookup_sampler: sampler;
+@group(3) @binding(2)
+var cookie_texture: texture_2d<f32>;
+@group(3) @binding(3)
+var cookie_sampler: sampler;
+#ifdef LIGHT_VOLUMETRIC
+// What the casters let through of this light, drawn with LIGHT_SHADOW_MASK, see shadow.rs.
+@group(3) @binding(4)
+var volumetric_mask_texture: texture_2d<f32>;
+@group(3) @binding(5)
+var volumetric_mask_sampler: sampler;
+#endif
+
+let PI: f32 = 3.141592653589793;
+// Added per covering light quad in the overdraw debug view, see overlay.wgsl.
+let OVERDRAW_STEP: f32 = 0.0625;
+// Mask samples between a pixel and the light.
+let VOLUMETRIC_SAMPLES: u32 = 32u;
+
+// The brightest channel of `tint` scales the coverage, its color tints the light.
+fn filter_light(color: vec4<f32>, tint: vec3<f32>) -> vec4<f32> {
+    let strength = max(tint.r, max(tint.g, tint.b));
+    if (strength > 0.0) {
+        return vec4<f32>(color.rgb * tint / strength, color.a * strength);
+    }
+    return vec4<f32>(color.rgb, 0.0);
+}
+
+#ifdef LIGHT_COOKIE
+fn sample_cookie(uv: vec2<f32>) -> vec4<f32> {
+    // Rotate around the light, then wrap into the current atlas frame.
+    let transform = light.cookie_transform;
+    let c = cos(transform.z);
+    let s = sin(transform.z);
+    let centered = (uv - vec2<f32>(0.5, 0.5)) * transform.w;
+    // uv y points down, so this turns the cookie counter-clockwise on screen.
+    let rotated = vec2<f32>(c * centered.x - s * centered.y, s * centered.x + c * centered.y);
+    let wrapped = fract(rotated + vec2<f32>(0.5, 0.5) + transform.xy);
+    // Inset by half a texel, so the wrap doesn't blend in the neighbouring atlas frames.
+    let rect = light.cookie_rect;
+    // No implicit derivatives, they jump where the cookie wraps.
+    return textureSampleLevel(cookie_texture, cookie_sampler, mix(rect.xy, rect.zw, wrapped), 0.0);
+}
+#endif
+
+#ifdef LIGHT_VOLUMETRIC
+fn world_to_mask_uv(position: vec2<f32>) -> vec2<f32> {
+    let clip = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    let ndc = clip.xy / clip.w;
+    return vec2<f32>(ndc.x + 1.0, 1.0 - ndc.y) * 0.5;
+}
+
+// Radial blur of the occlusion mask from the pixel towards the light. Samples further from the
+// light weigh less, so shafts fade out with distance.
+fn sample_light_shafts(position: vec2<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let start = world_to_mask_uv(position);
+    let step = (world_to_mask_uv(light.light_position.xy) - start) / f32(VOLUMETRIC_SAMPLES);
+    let distance_from_light = 2.0 * length(uv - vec2<f32>(0.5, 0.5));
+    let decay = max(light.volumetric_decay, 0.001);
+    var total = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < VOLUMETRIC_SAMPLES; i = i + 1u) {
+        let along = f32(i) / f32(VOLUMETRIC_SAMPLES);
+        let weight = exp(-decay * distance_from_light * (1.0 - along));
+        let mask = textureSampleLevel(
+            volumetric_mask_texture,
+            volumetric_mask_sampler,
+            start + step * f32(i),
+            0.0
+        );
+        total += mask.rgb * mask.a * weight;
+        total_weight += weight;
+    }
+    return min(light.volumetric_density * total / total_weight, vec3<f32>(1.0));
+}
+#endif
 
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef LIGHT_OVERDRAW
+    return vec4<f32>(OVERDRAW_STEP);
+#else
+#ifdef LIGHTMAP
+#ifdef LIGHT_SHADOW_MASK
+    // The shadows are baked into the light, there is no mask to show.
+    return vec4<f32>(0.0);
+#else
+    // Group 3 holds the baked lightmap instead of the point light lookup.
+    return textureSample(light_lookup_texture, light_lookup_sampler, in.uv) * light.light_color;
+#endif
+#else
+#ifdef ANALYTIC_LIGHT
+    // Same values the point light lookup texture stores, computed per pixel.
+    let offset = in.uv - vec2<f32>(0.5, 0.5);
+    let offset_length = length(offset);
+    var angle_cos = 1.0;
+    if (offset_length > 0.0) {
+        angle_cos = offset.y / offset_length;
+    }
+    let lookup = vec4<f32>(
+        saturate(1.0 - 2.0 * offset_length),
+        saturate(1.0 - acos(clamp(angle_cos, -1.0, 1.0)) / PI),
+        0.0,
+        0.0,
+    );
+#else
     // r = distance, g = angle, b = x direction, a = y direction
     let lookup = textureSample(light_lookup_texture, light_lookup_sampler, in.uv);
+#endif
 
     let distance = lookup.r;
     let radius_attenuation = saturate(light.inner_radius_mult * distance);
@@ -74,6 +190,11 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     var light_color = light.light_color;
     light_color.a *= attenuation;
 
+#ifdef LIGHT_COOKIE
+    let cookie = sample_cookie(in.uv);
+    light_color = filter_light(light_color, cookie.rgb * cookie.a);
+#endif
+
     // #if USE_ADDITIVE_BLENDING
     // lightColor *= attenuation;
     // #else
@@ -81,7 +202,21 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     // #endif
 
     // APPLY_NORMALS_LIGHTING(input, lightColor);
-    // APPLY_SHADOWS(input, lightColor, _ShadowIntensity);
+
+#ifdef LIGHT_VOLUMETRIC
+    light_color = filter_light(light_color, sample_light_shafts(in.world_position, in.uv));
+#else
+    let shadow = sample_shadow(in.world_position);
+#ifdef LIGHT_SHADOW_MASK
+    // What gets through the casters, wherever the light reaches.
+    light_color = vec4<f32>(shadow.transmission, select(0.0, 1.0, light_color.a > 0.0));
+#else
+    // APPLY_SHADOWS
+    light_color = filter_light(light_color, shadow.transmission);
+#endif
+#endif
 
     return light_color;
+#endif
+#endif
 }
@@ -1,18 +1,23 @@
+pub mod ambient_occlusion;
+pub mod debug_lines;
+pub mod fog_of_war;
+pub mod gi;
+pub mod graph;
 pub mod light;
 pub mod overlay;
+pub mod sdf;
 pub mod shadow;
 
-use std::ops::Deref;
-
 use bevy::{
-    core_pipeline::{clear_color::ClearColorConfig, core_2d::Transparent2d},
+    core_pipeline::core_2d::Transparent2d,
     prelude::*,
     render::{
-        camera::{CameraRenderGraph, ExtractedCamera, RenderTarget},
+        camera::{ExtractedCamera, RenderTarget},
         render_phase::RenderPhase,
         view::{ExtractedView, VisibleEntities},
         Extract,
     },
+    utils::HashSet,
 };
 
 pub use light::*;
@@ -21,6 +26,116 @@ pub use light::*;
 pub struct Light2dOverlay {
     pub image: Handle<Image>,
     pub size: UVec2,
+    /// MSAA sample count of the light texture. Lights are soft-edged, so `1` is usually enough.
+    pub samples: u32,
+}
+
+/// Replaces the lit scene of a camera with one of its intermediate lighting buffers. Insert it
+/// on the camera entity and change it at any time.
+///
+/// Lights aren't normal mapped, so there is no normal buffer to show.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Light2dDebugView {
+    #[default]
+    None,
+    /// The light texture, premultiplied by its alpha, on black.
+    LightTexture,
+    /// How many light quads cover each pixel, from blue (one) to red (sixteen or more).
+    Overdraw,
+    /// What the casters let through of the frontmost light reaching each pixel: white where
+    /// nothing occludes it, black in its shadows and outside every light. Lightmaps, ambient
+    /// occlusion and global illumination are left out.
+    ShadowMask,
+}
+
+/// How [`Shadow2d`](crate::Shadow2d) casters occlude the lights of a camera. Insert it on the
+/// camera entity.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Light2dShadowMode {
+    /// Hard shadows, each pixel is tested against the caster edges near its light.
+    #[default]
+    Edges,
+    /// Casters and [`SpriteOccluder2d`](crate::SpriteOccluder2d)s are drawn into an occlusion
+    /// texture, turned into a distance field by jump flooding, and marched per light. The cost
+    /// doesn't depend on the number of casters, but only what is on screen casts shadows, and
+    /// casters are fully opaque.
+    Sdf {
+        /// How quickly the penumbra widens away from the caster, `0.0` for hard shadows.
+        softness: f32,
+    },
+}
+
+/// Light bounced into a camera's light texture off [`Emissive2d`](crate::Emissive2d) sprites
+/// and lit caster surfaces. Insert it on a camera entity with [`Light2dShadowMode::Sdf`], whose
+/// distance field the rays are traced through.
+///
+/// Requires [`Light2dShadowMode::Sdf`]: with the default [`Light2dShadowMode::Edges`] there is
+/// no distance field, so nothing is drawn and a warning is logged.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Light2dGlobalIllumination {
+    /// Scales all indirect light.
+    pub intensity: f32,
+    /// Fraction of the light next to casters reflected back into the scene. Kept below `1.0`,
+    /// as light keeps bouncing over the following frames.
+    pub bounce: f32,
+    /// Cones traced per pixel, at most [`MAX_GI_RAYS`](gi::MAX_GI_RAYS). Fewer cones are wider,
+    /// blurrier and faster.
+    pub rays: u32,
+    /// How far light travels, in world units, so it keeps its reach when the camera zooms.
+    pub max_distance: f32,
+}
+
+impl Default for Light2dGlobalIllumination {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            bounce: 0.5,
+            rays: 16,
+            max_distance: 256.0,
+        }
+    }
+}
+
+/// Darkens the unlit scene next to [`Shadow2d`](crate::Shadow2d) casters and
+/// [`SpriteOccluder2d`](crate::SpriteOccluder2d)s, most in corners, whatever the lights. Lights
+/// wash it out. Insert it on a camera entity with [`Light2dShadowMode::Sdf`], whose distance
+/// field it reads.
+///
+/// Requires [`Light2dShadowMode::Sdf`]: with the default [`Light2dShadowMode::Edges`] there is
+/// no distance field, so nothing is drawn and a warning is logged.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Light2dAmbientOcclusion {
+    /// How far from an occluder the darkening reaches, in world units, so it keeps its size
+    /// when the camera zooms.
+    pub radius: f32,
+    /// Coverage of the darkening right against an occluder, from `0.0` to `1.0`.
+    pub strength: f32,
+}
+
+impl Default for Light2dAmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            radius: 24.0,
+            strength: 0.5,
+        }
+    }
+}
+
+impl Light2dOverlay {
+    pub fn new(image: Handle<Image>, size: UVec2) -> Self {
+        Self {
+            image,
+            size,
+            samples: 1,
+        }
+    }
+
+    /// Texels of the light texture per world unit in `view`. The distance fields are in texels
+    /// of the light texture, which covers the view.
+    pub fn texels_per_unit(&self, view: &ExtractedView) -> f32 {
+        let view_proj = view.projection * view.transform.compute_matrix().inverse();
+        (view_proj.transform_vector3(Vec3::X).truncate() * self.size.as_vec2() / 2.0).length()
+    }
 }
 
 pub fn extract_cameras(
@@ -30,21 +145,51 @@ pub fn extract_cameras(
             (
                 Entity,
                 &Camera,
-                &CameraRenderGraph,
                 &GlobalTransform,
                 &VisibleEntities,
                 &Children,
+                Option<&Light2dDebugView>,
+                Option<&Light2dShadowMode>,
+                Option<&Light2dGlobalIllumination>,
+                Option<&Light2dAmbientOcclusion>,
             ),
             With<Camera2d>,
         >,
     >,
     child_query: Extract<Query<&Light2dOverlay>>,
+    mut warned: Local<HashSet<Entity>>,
 ) {
-    for (parent, camera, camera_render_graph, transform, visible_entities, children) in query.iter()
+    // Only the cameras still missing their distance field are remembered.
+    let mut missing_sdf = HashSet::default();
+    for (
+        parent,
+        camera,
+        transform,
+        visible_entities,
+        children,
+        debug_view,
+        shadow_mode,
+        global_illumination,
+        ambient_occlusion,
+    ) in query.iter()
     {
         if !camera.is_active {
             continue;
         }
+        let sdf = matches!(shadow_mode, Some(Light2dShadowMode::Sdf { .. }));
+        let needs_sdf = [
+            global_illumination.map(|_| "Light2dGlobalIllumination"),
+            ambient_occlusion.map(|_| "Light2dAmbientOcclusion"),
+        ];
+        for name in needs_sdf.into_iter().flatten().filter(|_| !sdf) {
+            if !warned.contains(&parent) {
+                warn!(
+                    "{name} on {parent:?} needs Light2dShadowMode::Sdf, \
+                    it has no effect without it"
+                );
+            }
+            missing_sdf.insert(parent);
+        }
         if let (Some((viewport_origin, _)), Some(viewport_size), Some(target_size)) = (
             camera.physical_viewport_rect(),
             camera.physical_viewport_size(),
@@ -62,7 +207,7 @@ pub fn extract_cameras(
                             viewport: camera.viewport.clone(),
                             physical_viewport_size: Some(viewport_size),
                             physical_target_size: Some(overlay.size),
-                            render_graph: camera_render_graph.deref().clone(),
+                            render_graph: graph::NAME.into(),
                             priority: camera.priority - 1,
                         },
                         ExtractedView {
@@ -77,11 +222,16 @@ pub fn extract_cameras(
                             ),
                         },
                         RenderPhase::<Transparent2d>::default(),
-                        Camera2d {
-                            clear_color: ClearColorConfig::Custom(Color::rgba(0.0, 0.0, 0.0, 0.0)),
-                        },
                         overlay.clone(),
+                        debug_view.copied().unwrap_or_default(),
+                        shadow_mode.copied().unwrap_or_default(),
                     ));
+                    if let Some(global_illumination) = global_illumination {
+                        commands.entity(child.clone()).insert(*global_illumination);
+                    }
+                    if let Some(ambient_occlusion) = ambient_occlusion {
+                        commands.entity(child.clone()).insert(*ambient_occlusion);
+                    }
                     commands
                         .get_or_spawn(parent)
                         .push_children(&[child.clone()]);
@@ -89,4 +239,5 @@ pub fn extract_cameras(
             }
         }
     }
+    *warned = missing_sdf;
 }
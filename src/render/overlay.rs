@@ -33,7 +33,7 @@ use bevy::{
             BevyDefault, DefaultImageSampler, GpuImage, ImageSampler, TextureFormatPixelInfo,
         },
         view::ViewUniform,
-        view::{ExtractedView, ViewUniformOffset, ViewUniforms, VisibleEntities},
+        view::{ExtractedView, ViewTarget, ViewUniformOffset, ViewUniforms, VisibleEntities},
         Extract,
     },
     utils::{FloatOrd, HashMap},
@@ -42,7 +42,7 @@ use std::f32::consts::E;
 
 use bytemuck::{Pod, Zeroable};
 
-use super::Light2dOverlay;
+use super::{fog_of_war::Light2dFogOfWarView, Light2dDebugView, Light2dOverlay};
 
 pub const OVERLAY_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597138);
@@ -105,8 +105,15 @@ impl FromWorld for Light2dOverlayPipeline {
     }
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Light2dOverlayPipelineKey {
+    pub samples: u32,
+    pub hdr: bool,
+    pub debug_view: Light2dDebugView,
+}
+
 impl SpecializedRenderPipeline for Light2dOverlayPipeline {
-    type Key = u32;
+    type Key = Light2dOverlayPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
         let formats = vec![
@@ -116,19 +123,32 @@ impl SpecializedRenderPipeline for Light2dOverlayPipeline {
         let vertex_layout =
             VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
 
+        let mut shader_defs = Vec::new();
+        match key.debug_view {
+            Light2dDebugView::None => {}
+            Light2dDebugView::LightTexture | Light2dDebugView::ShadowMask => {
+                shader_defs.push("DEBUG_LIGHT_TEXTURE".to_string());
+            }
+            Light2dDebugView::Overdraw => shader_defs.push("DEBUG_OVERDRAW".to_string()),
+        }
+
         RenderPipelineDescriptor {
             vertex: VertexState {
                 shader: OVERLAY_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
+                shader_defs: shader_defs.clone(),
                 entry_point: "vertex".into(),
                 buffers: vec![vertex_layout],
             },
             fragment: Some(FragmentState {
                 shader: OVERLAY_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
+                shader_defs,
                 entry_point: "fragment".into(),
                 targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
+                    format: if key.hdr {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
                     blend: Some(BlendState::ALPHA_BLENDING),
                     write_mask: ColorWrites::ALL,
                 })],
@@ -145,7 +165,7 @@ impl SpecializedRenderPipeline for Light2dOverlayPipeline {
             },
             depth_stencil: None,
             multisample: MultisampleState {
-                count: 4,
+                count: key.samples,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -207,6 +227,7 @@ pub fn queue_light_overlay_bind_group(
     mut pipeline_cache: ResMut<PipelineCache>,
     mut image_bind_groups: ResMut<OverlayImageBindGroups>,
     gpu_images: Res<RenderAssets<Image>>,
+    msaa: Res<Msaa>,
     mut views: Query<(
         &mut RenderPhase<Transparent2d>,
         &mut VisibleEntities,
@@ -214,7 +235,8 @@ pub fn queue_light_overlay_bind_group(
         Option<&Tonemapping>,
         &Children,
     )>,
-    mut child_query: Query<&Light2dOverlay>,
+    // Fog of war views are composited by `queue_fogs_of_war`.
+    mut child_query: Query<(&Light2dOverlay, &Light2dDebugView), Without<Light2dFogOfWarView>>,
 ) {
     if let Some(view_binding) = view_uniforms.uniforms.binding() {
         let overlay_meta = &mut overlay_meta;
@@ -238,8 +260,16 @@ pub fn queue_light_overlay_bind_group(
         let mut index = 0;
         for (mut transparent_phase, mut visible_entities, view, tonemapping, children) in &mut views
         {
-            let pipeline = pipelines.specialize(&mut pipeline_cache, &overlay_pipeline, 0);
-            for overlay in &mut child_query {
+            for (overlay, debug_view) in &mut child_query {
+                let pipeline = pipelines.specialize(
+                    &mut pipeline_cache,
+                    &overlay_pipeline,
+                    Light2dOverlayPipelineKey {
+                        samples: msaa.samples,
+                        hdr: view.hdr,
+                        debug_view: *debug_view,
+                    },
+                );
                 // Set-up a new possible batch
                 let image_handle_id = overlay.image.id();
                 if let Some(gpu_image) = gpu_images.get(&Handle::weak(image_handle_id)) {
@@ -33,8 +33,30 @@ var overlay_texture: texture_2d<f32>;
 @group(1) @binding(1)
 var overlay_sampler: sampler;
 
+// Must match light.wgsl.
+let OVERDRAW_STEP: f32 = 0.0625;
+
+// Blue at 0, green at 0.5 and red at 1.
+fn heatmap(t: f32) -> vec3<f32> {
+    let x = 4.0 * saturate(t);
+    return saturate(vec3<f32>(x - 2.0, 2.0 - abs(x - 2.0), 2.0 - x));
+}
+
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     // return vec4<f32>(1.0,1.0,1.0,1.0);
-    return textureSample(overlay_texture, overlay_sampler, in.uv);
+    let color = textureSample(overlay_texture, overlay_sampler, in.uv);
+#ifdef DEBUG_LIGHT_TEXTURE
+    return vec4<f32>(color.rgb * color.a, 1.0);
+#else
+#ifdef DEBUG_OVERDRAW
+    let count = round(color.r / OVERDRAW_STEP);
+    if (count < 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    return vec4<f32>(heatmap((count - 1.0) / 15.0), 1.0);
+#else
+    return color;
+#endif
+#endif
 }
@@ -0,0 +1,830 @@
+use std::ops::Range;
+
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_asset::RenderAssets,
+        render_phase::TrackedRenderPass,
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+            BlendComponent, BlendFactor, BlendOperation, BlendState, BufferBindingType,
+            BufferUsages, BufferVec, CachedRenderPipelineId, ColorTargetState, ColorWrites,
+            DynamicUniformBuffer, Extent3d, FragmentState, FrontFace, LoadOp, MultisampleState,
+            Operations, PipelineCache, PolygonMode, PrimitiveState, PrimitiveTopology,
+            RenderPassColorAttachment, RenderPassDescriptor, RenderPipelineDescriptor,
+            SamplerBindingType, ShaderStages, ShaderType, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDimension,
+            VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{CachedTexture, GpuImage, TextureCache},
+        view::{ViewUniform, ViewUniformOffset, ViewUniforms},
+        Extract,
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::SpriteOccluder2d;
+
+use super::{
+    light::Light2dPipeline,
+    shadow::{ExtractedShadows2d, Shadow2dEdgeTexture},
+    Light2dOverlay, Light2dShadowMode,
+};
+
+pub const SDF_OCCLUDER_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597198);
+pub const SDF_FLOOD_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597158);
+/// `light_2d::sdf`, the distance lookup and fullscreen triangle of the passes reading the
+/// distance field.
+pub const SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597218);
+
+/// Summed caster windings and sprite coverage, see `sdf_occluder.wgsl`.
+const OCCLUSION_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Nearest occluded texel, in texels. 16-bit floats would be off by a texel past 2048.
+const FLOOD_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+/// Settings of one [`Light2dShadowMode::Sdf`] pass.
+#[derive(Clone, Copy, ShaderType)]
+pub struct Light2dSdfUniform {
+    /// Texels between the samples of a jump flood step.
+    pub jump: i32,
+    pub softness: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct OccluderVertex {
+    pub position: [f32; 2],
+    /// The other end of the edge of an open caster.
+    pub other: [f32; 2],
+    /// Side of the edge to widen to, `0.0` for the triangles of closed casters.
+    pub side: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct SpriteOccluderVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub alpha_threshold: f32,
+}
+
+/// Sums everything drawn, like the windings and coverage of `sdf_occluder.wgsl`.
+pub(super) const ADDITIVE_BLENDING: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+/// A pipeline drawing triangle lists into a single-sampled offscreen texture.
+pub(super) fn offscreen_pipeline_descriptor(
+    label: &'static str,
+    shader: Handle<Shader>,
+    entry_points: (&'static str, &'static str),
+    buffers: Vec<VertexBufferLayout>,
+    layout: Vec<BindGroupLayout>,
+    format: TextureFormat,
+    blend: Option<BlendState>,
+) -> RenderPipelineDescriptor {
+    RenderPipelineDescriptor {
+        vertex: VertexState {
+            shader: shader.clone(),
+            shader_defs: Vec::new(),
+            entry_point: entry_points.0.into(),
+            buffers,
+        },
+        fragment: Some(FragmentState {
+            shader,
+            shader_defs: Vec::new(),
+            entry_point: entry_points.1.into(),
+            targets: vec![Some(ColorTargetState {
+                format,
+                blend,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        layout: Some(layout),
+        primitive: PrimitiveState {
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        label: Some(label.into()),
+    }
+}
+
+#[derive(Resource)]
+pub struct Light2dSdfPipeline {
+    pub view_layout: BindGroupLayout,
+    pub sprite_layout: BindGroupLayout,
+    pub flood_layout: BindGroupLayout,
+    occluder_pipeline: CachedRenderPipelineId,
+    sprite_pipeline: CachedRenderPipelineId,
+    seed_pipeline: CachedRenderPipelineId,
+    flood_pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for Light2dSdfPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>().clone();
+
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(ViewUniform::min_size()),
+                },
+                count: None,
+            }],
+            label: Some("light_sdf_view_layout"),
+        });
+
+        let sprite_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("light_sdf_sprite_layout"),
+        });
+
+        let flood_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(Light2dSdfUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("light_sdf_flood_layout"),
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let occluder_pipeline =
+            pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+                "light_sdf_occluder_pipeline",
+                SDF_OCCLUDER_SHADER_HANDLE.typed(),
+                ("occluder_vertex", "occluder_fragment"),
+                vec![VertexBufferLayout::from_vertex_formats(
+                    VertexStepMode::Vertex,
+                    vec![
+                        VertexFormat::Float32x2, // position
+                        VertexFormat::Float32x2, // other
+                        VertexFormat::Float32,   // side
+                    ],
+                )],
+                vec![view_layout.clone()],
+                OCCLUSION_TEXTURE_FORMAT,
+                Some(ADDITIVE_BLENDING),
+            ));
+        let sprite_pipeline = pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+            "light_sdf_sprite_pipeline",
+            SDF_OCCLUDER_SHADER_HANDLE.typed(),
+            ("sprite_vertex", "sprite_fragment"),
+            vec![VertexBufferLayout::from_vertex_formats(
+                VertexStepMode::Vertex,
+                vec![
+                    VertexFormat::Float32x2, // position
+                    VertexFormat::Float32x2, // uv
+                    VertexFormat::Float32,   // alpha_threshold
+                ],
+            )],
+            vec![view_layout.clone(), sprite_layout.clone()],
+            OCCLUSION_TEXTURE_FORMAT,
+            Some(ADDITIVE_BLENDING),
+        ));
+        let seed_pipeline = pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+            "light_sdf_seed_pipeline",
+            SDF_FLOOD_SHADER_HANDLE.typed(),
+            ("fullscreen_vertex", "seed"),
+            Vec::new(),
+            vec![flood_layout.clone()],
+            FLOOD_TEXTURE_FORMAT,
+            None,
+        ));
+        let flood_pipeline = pipeline_cache.queue_render_pipeline(offscreen_pipeline_descriptor(
+            "light_sdf_flood_pipeline",
+            SDF_FLOOD_SHADER_HANDLE.typed(),
+            ("fullscreen_vertex", "flood"),
+            Vec::new(),
+            vec![flood_layout.clone()],
+            FLOOD_TEXTURE_FORMAT,
+            None,
+        ));
+
+        Self {
+            view_layout,
+            sprite_layout,
+            flood_layout,
+            occluder_pipeline,
+            sprite_pipeline,
+            seed_pipeline,
+            flood_pipeline,
+        }
+    }
+}
+
+/// What it takes to draw a sprite outside of the sprite pipeline.
+#[derive(Clone, Debug)]
+pub struct ExtractedSpriteQuad {
+    pub image: Handle<Image>,
+    pub transform: GlobalTransform,
+    pub custom_size: Option<Vec2>,
+    pub rect: Option<Rect>,
+    pub anchor: Vec2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl ExtractedSpriteQuad {
+    pub fn new(sprite: &Sprite, image: &Handle<Image>, transform: &GlobalTransform) -> Self {
+        Self {
+            image: image.clone_weak(),
+            transform: *transform,
+            custom_size: sprite.custom_size,
+            rect: sprite.rect,
+            anchor: sprite.anchor.as_vec(),
+            flip_x: sprite.flip_x,
+            flip_y: sprite.flip_y,
+        }
+    }
+
+    /// World positions and uvs of the two triangles covering the sprite.
+    pub fn vertices(&self, image_size: Vec2) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        let (uv_min, uv_max) = match self.rect {
+            Some(rect) => (rect.min / image_size, rect.max / image_size),
+            None => (Vec2::ZERO, Vec2::ONE),
+        };
+        let size = self
+            .custom_size
+            .or_else(|| self.rect.map(|rect| rect.size()))
+            .unwrap_or(image_size);
+        QUAD_INDICES.into_iter().map(move |i| {
+            let mut uv = QUAD_UVS[i];
+            if self.flip_x {
+                uv.x = 1.0 - uv.x;
+            }
+            if self.flip_y {
+                uv.y = 1.0 - uv.y;
+            }
+            let position = self
+                .transform
+                .transform_point(((QUAD_VERTEX_POSITIONS[i] - self.anchor) * size).extend(0.0));
+            (position.truncate(), uv_min.lerp(uv_max, uv))
+        })
+    }
+}
+
+/// Extends the last batch of `batches` if it draws the same image.
+pub(super) fn push_sprite_batch(
+    batches: &mut Vec<(Handle<Image>, Range<u32>)>,
+    image: &Handle<Image>,
+    vertices: Range<u32>,
+) {
+    match batches.last_mut() {
+        Some((batch_image, range)) if batch_image == image => range.end = vertices.end,
+        _ => batches.push((image.clone_weak(), vertices)),
+    }
+}
+
+pub(super) fn sprite_bind_group(
+    render_device: &RenderDevice,
+    layout: &BindGroupLayout,
+    gpu_image: &GpuImage,
+) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&gpu_image.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&gpu_image.sampler),
+            },
+        ],
+        label: Some("light_sdf_sprite_bind_group"),
+        layout,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedSpriteOccluder {
+    pub quad: ExtractedSpriteQuad,
+    pub alpha_threshold: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedSpriteOccluders {
+    pub sprites: Vec<ExtractedSpriteOccluder>,
+}
+
+pub fn extract_sprite_occluders(
+    mut occluders: ResMut<ExtractedSpriteOccluders>,
+    sprite_query: Extract<
+        Query<(
+            &Sprite,
+            &Handle<Image>,
+            &GlobalTransform,
+            &ComputedVisibility,
+            &SpriteOccluder2d,
+        )>,
+    >,
+) {
+    occluders.sprites.clear();
+    for (sprite, image, transform, visibility, occluder) in sprite_query.iter() {
+        // Only what is on screen ends up in the distance field anyway.
+        if !visibility.is_visible() {
+            continue;
+        }
+        occluders.sprites.push(ExtractedSpriteOccluder {
+            quad: ExtractedSpriteQuad::new(sprite, image, transform),
+            alpha_threshold: occluder.alpha_threshold,
+        });
+    }
+}
+
+#[derive(Resource)]
+pub struct Light2dSdfMeta {
+    occluder_vertices: BufferVec<OccluderVertex>,
+    sprite_vertices: BufferVec<SpriteOccluderVertex>,
+    /// Runs of `sprite_vertices` sharing an image.
+    sprite_batches: Vec<(Handle<Image>, Range<u32>)>,
+    sprite_bind_groups: HashMap<Handle<Image>, BindGroup>,
+    uniforms: DynamicUniformBuffer<Light2dSdfUniform>,
+    /// The view uniforms alone, for drawing into the offscreen textures.
+    pub view_bind_group: Option<BindGroup>,
+}
+
+impl Default for Light2dSdfMeta {
+    fn default() -> Self {
+        Self {
+            occluder_vertices: BufferVec::new(BufferUsages::VERTEX),
+            sprite_vertices: BufferVec::new(BufferUsages::VERTEX),
+            sprite_batches: Vec::new(),
+            sprite_bind_groups: HashMap::default(),
+            uniforms: DynamicUniformBuffer::default(),
+            view_bind_group: None,
+        }
+    }
+}
+
+const QUAD_INDICES: [usize; 6] = [0, 2, 3, 0, 1, 2];
+
+const QUAD_VERTEX_POSITIONS: [Vec2; 4] = [
+    Vec2::new(-0.5, -0.5),
+    Vec2::new(0.5, -0.5),
+    Vec2::new(0.5, 0.5),
+    Vec2::new(-0.5, 0.5),
+];
+
+const QUAD_UVS: [Vec2; 4] = [
+    Vec2::new(0., 1.),
+    Vec2::new(1., 1.),
+    Vec2::new(1., 0.),
+    Vec2::new(0., 0.),
+];
+
+pub fn prepare_sdf_occluders(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut sdf_meta: ResMut<Light2dSdfMeta>,
+    shadows: Res<ExtractedShadows2d>,
+    occluders: Res<ExtractedSpriteOccluders>,
+    gpu_images: Res<RenderAssets<Image>>,
+) {
+    let sdf_meta = &mut *sdf_meta;
+    sdf_meta.occluder_vertices.clear();
+    for (edges, closed) in shadows.casters() {
+        if closed {
+            // A fan from the first point, the winding of each triangle says whether it adds
+            // to or cuts out of the caster.
+            let origin = edges[0].start.to_array();
+            for edge in &edges[1..] {
+                for position in [origin, edge.start.to_array(), edge.end.to_array()] {
+                    sdf_meta.occluder_vertices.push(OccluderVertex {
+                        position,
+                        other: position,
+                        side: 0.0,
+                    });
+                }
+            }
+        } else {
+            for edge in edges {
+                if edge.start == edge.end {
+                    continue;
+                }
+                let (start, end) = (edge.start.to_array(), edge.end.to_array());
+                // The shader widens each end along its own perpendicular, which flips with
+                // the direction of the edge.
+                let corners = [(start, end, 1.0), (start, end, -1.0), (end, start, 1.0)];
+                let opposite = [(end, start, -1.0), (end, start, 1.0), (start, end, 1.0)];
+                for (position, other, side) in corners.into_iter().chain(opposite) {
+                    sdf_meta.occluder_vertices.push(OccluderVertex {
+                        position,
+                        other,
+                        side,
+                    });
+                }
+            }
+        }
+    }
+    sdf_meta
+        .occluder_vertices
+        .write_buffer(&render_device, &render_queue);
+
+    sdf_meta.sprite_vertices.clear();
+    sdf_meta.sprite_batches.clear();
+    for sprite in &occluders.sprites {
+        let gpu_image = match gpu_images.get(&sprite.quad.image) {
+            Some(gpu_image) => gpu_image,
+            None => continue,
+        };
+        let start = sdf_meta.sprite_vertices.len() as u32;
+        for (position, uv) in sprite.quad.vertices(gpu_image.size) {
+            sdf_meta.sprite_vertices.push(SpriteOccluderVertex {
+                position: position.to_array(),
+                uv: uv.to_array(),
+                alpha_threshold: sprite.alpha_threshold,
+            });
+        }
+        let end = sdf_meta.sprite_vertices.len() as u32;
+        push_sprite_batch(&mut sdf_meta.sprite_batches, &sprite.quad.image, start..end);
+    }
+    sdf_meta
+        .sprite_vertices
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Bind groups of one [`Light2dShadowMode::Sdf`] view.
+pub struct Light2dSdfBindGroups {
+    /// Reads the occlusion texture.
+    seed: BindGroup,
+    /// Read the first and second flood texture.
+    flood: [BindGroup; 2],
+    /// The view group of the light pass, with the finished distance field.
+    pub light: BindGroup,
+    /// Of the [`Light2dSdfUniform`] used by the seed and light passes.
+    pub settings_offset: u32,
+    /// Of the [`Light2dSdfUniform`] of each flood step.
+    jump_offsets: Vec<u32>,
+}
+
+/// Render targets of a [`Light2dShadowMode::Sdf`] view, on its [`Light2dOverlay`] entity.
+#[derive(Component)]
+pub struct Light2dSdfTextures {
+    occlusion: CachedTexture,
+    /// Ping-ponged between the jump flood steps.
+    flood: [CachedTexture; 2],
+    size: UVec2,
+    softness: f32,
+    pub bind_groups: Option<Light2dSdfBindGroups>,
+}
+
+impl Light2dSdfTextures {
+    /// Jump lengths of the flood steps, halving down to one texel.
+    fn jumps(&self) -> impl Iterator<Item = i32> {
+        let mut jump = self.size.max_element().next_power_of_two() / 2;
+        std::iter::from_fn(move || {
+            let current = jump;
+            jump /= 2;
+            (current > 0).then_some(current as i32)
+        })
+    }
+
+    /// The finished distance field.
+    pub(super) fn result(&self) -> &TextureView {
+        &self.flood[self.jumps().count() % 2].default_view
+    }
+}
+
+pub fn prepare_sdf_textures(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    views: Query<(Entity, &Light2dOverlay, &Light2dShadowMode)>,
+) {
+    for (entity, overlay, shadow_mode) in &views {
+        let softness = match shadow_mode {
+            Light2dShadowMode::Sdf { softness } => softness.max(0.0),
+            Light2dShadowMode::Edges => continue,
+        };
+        let mut texture = |label, format| {
+            texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: overlay.size.x,
+                        height: overlay.size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                },
+            )
+        };
+        commands.entity(entity).insert(Light2dSdfTextures {
+            occlusion: texture("light_sdf_occlusion_texture", OCCLUSION_TEXTURE_FORMAT),
+            flood: [
+                texture("light_sdf_flood_texture", FLOOD_TEXTURE_FORMAT),
+                texture("light_sdf_flood_texture", FLOOD_TEXTURE_FORMAT),
+            ],
+            size: overlay.size,
+            softness,
+            bind_groups: None,
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn queue_sdf_bind_groups(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    sdf_pipeline: Res<Light2dSdfPipeline>,
+    light_pipeline: Res<Light2dPipeline>,
+    mut sdf_meta: ResMut<Light2dSdfMeta>,
+    view_uniforms: Res<ViewUniforms>,
+    shadow_edge_texture: Res<Shadow2dEdgeTexture>,
+    gpu_images: Res<RenderAssets<Image>>,
+    mut views: Query<&mut Light2dSdfTextures>,
+) {
+    let sdf_meta = &mut *sdf_meta;
+    // Images are replaced wholesale, so don't keep bind groups to old textures around.
+    sdf_meta.sprite_bind_groups.clear();
+    if views.is_empty() {
+        return;
+    }
+    let view_binding = match view_uniforms.uniforms.binding() {
+        Some(view_binding) => view_binding,
+        None => return,
+    };
+
+    sdf_meta.uniforms.clear();
+    let offsets = views
+        .iter()
+        .map(|textures| {
+            let settings = sdf_meta.uniforms.push(Light2dSdfUniform {
+                jump: 0,
+                softness: textures.softness,
+            });
+            let jumps = textures
+                .jumps()
+                .map(|jump| {
+                    sdf_meta.uniforms.push(Light2dSdfUniform {
+                        jump,
+                        softness: textures.softness,
+                    })
+                })
+                .collect::<Vec<_>>();
+            (settings, jumps)
+        })
+        .collect::<Vec<_>>();
+    sdf_meta
+        .uniforms
+        .write_buffer(&render_device, &render_queue);
+    let uniforms_binding = match sdf_meta.uniforms.binding() {
+        Some(binding) => binding,
+        None => return,
+    };
+
+    sdf_meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding.clone(),
+        }],
+        label: Some("light_sdf_view_bind_group"),
+        layout: &sdf_pipeline.view_layout,
+    }));
+    for (image, _) in &sdf_meta.sprite_batches {
+        if let Some(gpu_image) = gpu_images.get(image) {
+            sdf_meta
+                .sprite_bind_groups
+                .entry(image.clone_weak())
+                .or_insert_with(|| {
+                    sprite_bind_group(&render_device, &sdf_pipeline.sprite_layout, gpu_image)
+                });
+        }
+    }
+
+    for (mut textures, (settings_offset, jump_offsets)) in views.iter_mut().zip(offsets) {
+        let flood_bind_group = |label, texture: &TextureView| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(texture),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: uniforms_binding.clone(),
+                    },
+                ],
+                label: Some(label),
+                layout: &sdf_pipeline.flood_layout,
+            })
+        };
+        let light = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: view_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&shadow_edge_texture.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(textures.result()),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: uniforms_binding.clone(),
+                },
+            ],
+            label: Some("light_sdf_light_view_bind_group"),
+            layout: &light_pipeline.sdf_view_layout,
+        });
+        let bind_groups = Light2dSdfBindGroups {
+            seed: flood_bind_group(
+                "light_sdf_seed_bind_group",
+                &textures.occlusion.default_view,
+            ),
+            flood: [
+                flood_bind_group(
+                    "light_sdf_flood_bind_group",
+                    &textures.flood[0].default_view,
+                ),
+                flood_bind_group(
+                    "light_sdf_flood_bind_group",
+                    &textures.flood[1].default_view,
+                ),
+            ],
+            light,
+            settings_offset,
+            jump_offsets,
+        };
+        textures.bind_groups = Some(bind_groups);
+    }
+}
+
+/// Draws the occluders of a [`Light2dShadowMode::Sdf`] view and jump floods them into the
+/// distance field its lights march through. Runs before the light pass, returns whether the
+/// distance field is ready.
+pub fn run_sdf_passes(
+    world: &World,
+    render_context: &mut RenderContext,
+    view_entity: Entity,
+    textures: &Light2dSdfTextures,
+) -> bool {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let sdf_pipeline = world.resource::<Light2dSdfPipeline>();
+    let sdf_meta = world.resource::<Light2dSdfMeta>();
+    let (bind_groups, view_bind_group, view_uniform) = match (
+        textures.bind_groups.as_ref(),
+        sdf_meta.view_bind_group.as_ref(),
+        world.get::<ViewUniformOffset>(view_entity),
+    ) {
+        (Some(bind_groups), Some(view_bind_group), Some(view_uniform)) => {
+            (bind_groups, view_bind_group, view_uniform)
+        }
+        _ => return false,
+    };
+    let pipelines = [
+        sdf_pipeline.occluder_pipeline,
+        sdf_pipeline.sprite_pipeline,
+        sdf_pipeline.seed_pipeline,
+        sdf_pipeline.flood_pipeline,
+    ]
+    .map(|id| pipeline_cache.get_render_pipeline(id));
+    let [occluder_pipeline, sprite_pipeline, seed_pipeline, flood_pipeline] = match pipelines {
+        [Some(occluder), Some(sprite), Some(seed), Some(flood)] => [occluder, sprite, seed, flood],
+        _ => return false,
+    };
+
+    {
+        let mut pass = begin_pass(
+            render_context,
+            "light_sdf_occluder_pass",
+            &textures.occlusion.default_view,
+        );
+        if let Some(buffer) = sdf_meta.occluder_vertices.buffer() {
+            pass.set_render_pipeline(occluder_pipeline);
+            pass.set_bind_group(0, view_bind_group, &[view_uniform.offset]);
+            pass.set_vertex_buffer(0, buffer.slice(..));
+            pass.draw(0..sdf_meta.occluder_vertices.len() as u32, 0..1);
+        }
+        if let Some(buffer) = sdf_meta.sprite_vertices.buffer() {
+            pass.set_render_pipeline(sprite_pipeline);
+            pass.set_bind_group(0, view_bind_group, &[view_uniform.offset]);
+            pass.set_vertex_buffer(0, buffer.slice(..));
+            for (image, range) in &sdf_meta.sprite_batches {
+                if let Some(bind_group) = sdf_meta.sprite_bind_groups.get(image) {
+                    pass.set_bind_group(1, bind_group, &[]);
+                    pass.draw(range.clone(), 0..1);
+                }
+            }
+        }
+    }
+
+    {
+        let mut pass = begin_pass(
+            render_context,
+            "light_sdf_seed_pass",
+            &textures.flood[0].default_view,
+        );
+        pass.set_render_pipeline(seed_pipeline);
+        pass.set_bind_group(0, &bind_groups.seed, &[bind_groups.settings_offset]);
+        pass.draw(0..3, 0..1);
+    }
+
+    for (step, offset) in bind_groups.jump_offsets.iter().enumerate() {
+        let mut pass = begin_pass(
+            render_context,
+            "light_sdf_flood_pass",
+            &textures.flood[(step + 1) % 2].default_view,
+        );
+        pass.set_render_pipeline(flood_pipeline);
+        pass.set_bind_group(0, &bind_groups.flood[step % 2], &[*offset]);
+        pass.draw(0..3, 0..1);
+    }
+
+    true
+}
+
+/// Starts a pass into `view`, cleared to transparent.
+pub(super) fn begin_pass<'a>(
+    render_context: &'a mut RenderContext,
+    label: &'a str,
+    view: &'a TextureView,
+) -> TrackedRenderPass<'a> {
+    let render_pass = render_context
+        .command_encoder
+        .begin_render_pass(&RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::rgba(0.0, 0.0, 0.0, 0.0).into()),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+    TrackedRenderPass::new(render_pass)
+}
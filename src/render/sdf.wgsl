@@ -0,0 +1,19 @@
+#define_import_path light_2d::sdf
+
+// Shared by the passes reading the distance field. The importing shader declares
+// `sdf_texture`, the nearest occluded texel of each texel of the view, see sdf_flood.wgsl.
+
+@vertex
+fn fullscreen_vertex(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Texels from `texel` to the nearest occluded one.
+fn sdf_distance(texel: vec2<f32>, size: vec2<f32>) -> f32 {
+    let seed = textureLoad(sdf_texture, vec2<i32>(clamp(texel, vec2<f32>(0.0), size - 1.0)), 0);
+    if (seed.a == 0.0) {
+        return size.x + size.y;
+    }
+    return distance(texel, seed.xy + 0.5);
+}
@@ -0,0 +1,54 @@
+struct Sdf {
+    jump: i32,
+    softness: f32,
+};
+
+// The occlusion texture for `seed`, the previous pass for `flood`.
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> sdf: Sdf;
+
+@vertex
+fn fullscreen_vertex(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Occluded texels are their own nearest seed. Seeds are stored as (texel.xy, 0, 1), alpha 0
+// meaning none was found yet.
+@fragment
+fn seed(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let occlusion = textureLoad(input_texture, vec2<i32>(position.xy), 0);
+    if (occlusion.r != 0.0 || occlusion.g > 0.0) {
+        return vec4<f32>(floor(position.xy), 0.0, 1.0);
+    }
+    return vec4<f32>(0.0);
+}
+
+// One jump flood step: the nearest of the seeds found by the texels `jump` away.
+@fragment
+fn flood(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(input_texture));
+    let texel = vec2<i32>(position.xy);
+    var best = vec4<f32>(0.0);
+    var best_distance = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let neighbour = texel + vec2<i32>(x, y) * sdf.jump;
+            if (any(neighbour < vec2<i32>(0)) || any(neighbour >= size)) {
+                continue;
+            }
+            let candidate = textureLoad(input_texture, neighbour, 0);
+            if (candidate.a == 0.0) {
+                continue;
+            }
+            let candidate_distance = distance(candidate.xy, vec2<f32>(texel));
+            if (best.a == 0.0 || candidate_distance < best_distance) {
+                best = candidate;
+                best_distance = candidate_distance;
+            }
+        }
+    }
+    return best;
+}
@@ -0,0 +1,84 @@
+struct View {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    world_position: vec3<f32>,
+    // viewport(x_origin, y_origin, width, height)
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var sprite_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var sprite_sampler: sampler;
+
+struct OccluderOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) line: f32,
+};
+
+@vertex
+fn occluder_vertex(
+    @location(0) position: vec2<f32>,
+    @location(1) other: vec2<f32>,
+    @location(2) side: f32,
+) -> OccluderOutput {
+    var out: OccluderOutput;
+    out.position = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.line = abs(side);
+    if (side != 0.0) {
+        // Open casters have no inside, their edges are widened to two texels instead.
+        let other_position = view.view_proj * vec4<f32>(other, 0.0, 1.0);
+        let texel = 2.0 / view.viewport.zw;
+        let direction = normalize((other_position.xy - out.position.xy) / texel);
+        let offset = vec2<f32>(-direction.y, direction.x) * side * texel * out.position.w;
+        out.position = vec4<f32>(out.position.xy + offset, out.position.zw);
+    }
+    return out;
+}
+
+@fragment
+fn occluder_fragment(
+    in: OccluderOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> @location(0) vec4<f32> {
+    // Closed casters are drawn as triangle fans and summed by winding in r, so concave outlines
+    // and holes come out right. Lines add to g, which only ever grows.
+    if (in.line > 0.0) {
+        return vec4<f32>(0.0, 1.0, 0.0, 0.0);
+    }
+    return vec4<f32>(select(-1.0, 1.0, front_facing), 0.0, 0.0, 0.0);
+}
+
+struct SpriteOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) alpha_threshold: f32,
+};
+
+@vertex
+fn sprite_vertex(
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) alpha_threshold: f32,
+) -> SpriteOutput {
+    var out: SpriteOutput;
+    out.position = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    out.uv = uv;
+    out.alpha_threshold = alpha_threshold;
+    return out;
+}
+
+@fragment
+fn sprite_fragment(in: SpriteOutput) -> @location(0) vec4<f32> {
+    if (textureSample(sprite_texture, sprite_sampler, in.uv).a < in.alpha_threshold) {
+        discard;
+    }
+    return vec4<f32>(0.0, 1.0, 0.0, 0.0);
+}
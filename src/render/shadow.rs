@@ -1,138 +1,563 @@
+use std::{num::NonZeroU32, ops::Range};
+
 use bevy::{
-    ecs::system::SystemState,
     prelude::*,
     reflect::TypeUuid,
     render::{
         render_resource::{
-            BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
-            BlendState, BufferBindingType, ColorTargetState, ColorWrites, CompareFunction,
-            DepthBiasState, DepthStencilState, Extent3d, FragmentState, FrontFace,
-            ImageCopyTexture, ImageDataLayout, MultisampleState, Origin3d, PolygonMode,
-            PrimitiveState, PrimitiveTopology, RenderPipelineDescriptor, SamplerBindingType,
-            ShaderStages, ShaderType, SpecializedRenderPipeline, StencilState, TextureAspect,
-            TextureDimension, TextureFormat, TextureSampleType, TextureViewDescriptor,
-            TextureViewDimension, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+            BindGroupLayout, BlendState, ColorTargetState, ColorWrites, Extent3d, ImageCopyTexture,
+            ImageDataLayout, Origin3d, RenderPipelineDescriptor, SpecializedRenderPipeline,
+            Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages, TextureView, TextureViewDescriptor,
         },
         renderer::{RenderDevice, RenderQueue},
-        texture::{
-            BevyDefault, DefaultImageSampler, GpuImage, ImageSampler, TextureFormatPixelInfo,
-        },
-        view::ViewUniform,
         Extract,
     },
+    utils::{FloatOrd, HashMap},
 };
-use std::f32::consts::E;
 
-use crate::Shadow2d;
+use crate::{distance_to_segment, shadow_edges, MergedShadow2d, Shadow2d};
 
+use super::light::{light_quad_pipeline_descriptor, Light2dPipeline, Light2dShading};
+
+/// `light_2d::shadow`, imported by `light.wgsl` to sample the shadow of a light.
 pub const SHADOW_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2763343953151597129);
 
+/// Draws what the casters let through of a light, wherever the light reaches, instead of the
+/// light itself: the masks [`Light2dVolumetric`](crate::Light2dVolumetric) light shafts blur and
+/// the [`Light2dDebugView::ShadowMask`](super::Light2dDebugView::ShadowMask) view.
+///
+/// Shadows are tested per pixel against the [`Shadow2dEdgeTexture`] rather than extruded into
+/// geometry, so translucent casters can filter the light by color and overlapping casters
+/// multiply rather than cover each other, see `shadow.wgsl`. The light quads reuse the
+/// [`Light2dPipeline`] layouts.
 #[derive(Resource)]
 pub struct Shadow2dPipeline {
-    pub view_layout: BindGroupLayout,
+    shading: Light2dShading,
+    view_layout: BindGroupLayout,
+    sdf_view_layout: BindGroupLayout,
+    light_layout: BindGroupLayout,
+    falloff_lookup_layout: BindGroupLayout,
+    point_light_lookup_layout: BindGroupLayout,
 }
 
 impl FromWorld for Shadow2dPipeline {
     fn from_world(world: &mut World) -> Self {
-        let mut system_state: SystemState<(
-            Res<RenderDevice>,
-            Res<DefaultImageSampler>,
-            Res<RenderQueue>,
-        )> = SystemState::new(world);
-        let (render_device, default_sampler, render_queue) = system_state.get_mut(world);
-
-        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: true,
-                    min_binding_size: Some(ViewUniform::min_size()),
-                },
-                count: None,
-            }],
-            label: Some("light_view_layout"),
-        });
-
-        Self { view_layout }
+        let light_pipeline = world.resource::<Light2dPipeline>();
+        Self {
+            shading: light_pipeline.shading,
+            view_layout: light_pipeline.view_layout.clone(),
+            sdf_view_layout: light_pipeline.sdf_view_layout.clone(),
+            light_layout: light_pipeline.light_layout.clone(),
+            falloff_lookup_layout: light_pipeline.falloff_lookup_layout.clone(),
+            point_light_lookup_layout: light_pipeline.point_light_lookup_layout.clone(),
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Shadow2dPipelineKey {
+    pub samples: u32,
+    pub format: TextureFormat,
+    /// A [`Lightmap2d`](crate::Lightmap2d) has its shadows baked in and draws nothing.
+    pub lightmap: bool,
+    /// The light's cookie limits where the mask is drawn.
+    pub cookie: bool,
+    /// Marches the distance field of a [`Light2dShadowMode::Sdf`](super::Light2dShadowMode::Sdf)
+    /// view instead of testing edges.
+    pub sdf: bool,
+}
+
 impl SpecializedRenderPipeline for Shadow2dPipeline {
-    type Key = u32;
+    type Key = Shadow2dPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        let formats = vec![
-            VertexFormat::Float32x3, // position
-        ];
-
-        let vertex_layout =
-            VertexBufferLayout::from_vertex_formats(VertexStepMode::Vertex, formats);
-
-        RenderPipelineDescriptor {
-            vertex: VertexState {
-                shader: SHADOW_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
-                entry_point: "vertex".into(),
-                buffers: vec![vertex_layout],
-            },
-            fragment: Some(FragmentState {
-                shader: SHADOW_SHADER_HANDLE.typed::<Shader>(),
-                shader_defs: Vec::new(),
-                entry_point: "fragment".into(),
-                targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            layout: Some(vec![self.view_layout.clone()]),
-            primitive: PrimitiveState {
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
+        let mut shader_defs = vec!["LIGHT_SHADOW_MASK".to_string()];
+        if self.shading == Light2dShading::Analytic {
+            shader_defs.push("ANALYTIC_LIGHT".to_string());
+        }
+        if key.lightmap {
+            shader_defs.push("LIGHTMAP".to_string());
+        }
+        if key.cookie {
+            shader_defs.push("LIGHT_COOKIE".to_string());
+        }
+        let view_layout = if key.sdf {
+            shader_defs.push("SHADOW_SDF".to_string());
+            self.sdf_view_layout.clone()
+        } else {
+            self.view_layout.clone()
+        };
+
+        light_quad_pipeline_descriptor(
+            shader_defs,
+            vec![
+                view_layout,
+                self.light_layout.clone(),
+                self.falloff_lookup_layout.clone(),
+                self.point_light_lookup_layout.clone(),
+            ],
+            ColorTargetState {
+                format: key.format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
             },
-            depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth24PlusStencil8,
-                depth_write_enabled: false,
-                depth_compare: CompareFunction::Always,
-                stencil: StencilState {
-                    front: todo!(),
-                    back: todo!(),
-                    read_mask: todo!(),
-                    write_mask: todo!(),
-                },
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState {
-                count: 4,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+            key.samples,
+            "shadow_2d_pipeline",
+        )
+    }
+}
+
+/// Texels per row of the [`Shadow2dEdgeTexture`], two per edge.
+pub const SHADOW_EDGE_TEXTURE_WIDTH: u32 = 1024;
+
+const SHADOW_EDGES_PER_ROW: u32 = SHADOW_EDGE_TEXTURE_WIDTH / 2;
+
+/// Most edges a light tests per pixel, matching `SHADOW_MAX_LIGHT_EDGES` in `shadow.wgsl`.
+/// Crowded lights keep the casters nearest to them.
+pub const SHADOW_MAX_LIGHT_EDGES: u32 = 512;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractedShadow2dEdge {
+    pub start: Vec2,
+    pub end: Vec2,
+    /// [`Shadow2d::transmittance`] of the caster.
+    pub transmission: Vec3,
+    /// Index of the caster, edges of the same caster are next to each other.
+    pub caster: u32,
+}
+
+/// Where the edges of a caster are in [`ExtractedShadows2d::edges`], and their bounds.
+#[derive(Clone, Debug)]
+struct ExtractedShadow2dCaster {
+    edges: Range<usize>,
+    closed: bool,
+    min: Vec2,
+    max: Vec2,
+}
+
+/// Casters spanning more cells than this skip the grid and are tested against every light.
+const MAX_CASTER_CELLS: f32 = 256.0;
+
+/// Uniform grid over the caster bounds, so each light only looks at the casters near it.
+#[derive(Default)]
+struct Shadow2dGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<u32>>,
+    /// Casters too large for the grid.
+    large: Vec<u32>,
+    /// Per caster, the last query that found it, so casters in several cells count once.
+    visited: Vec<u32>,
+    query: u32,
+}
+
+impl Shadow2dGrid {
+    /// First and last cell covering the bounds, and how many cells that is.
+    fn cell_range(&self, min: Vec2, max: Vec2) -> (IVec2, IVec2, f32) {
+        let (min, max) = (
+            (min / self.cell_size).floor(),
+            (max / self.cell_size).floor(),
+        );
+        let size = max - min + 1.0;
+        (min.as_ivec2(), max.as_ivec2(), size.x * size.y)
+    }
+
+    fn build(&mut self, casters: &[ExtractedShadow2dCaster]) {
+        self.cells.clear();
+        self.large.clear();
+        self.visited.clear();
+        self.visited.resize(casters.len(), 0);
+        self.query = 0;
+        if casters.is_empty() {
+            return;
+        }
+        // A few casters per cell on average.
+        let average_size = casters
+            .iter()
+            .map(|caster| (caster.max - caster.min).max_element())
+            .sum::<f32>()
+            / casters.len() as f32;
+        self.cell_size = (4.0 * average_size).max(f32::EPSILON);
+
+        for (index, caster) in casters.iter().enumerate() {
+            let (min, max, count) = self.cell_range(caster.min, caster.max);
+            if count > MAX_CASTER_CELLS {
+                self.large.push(index as u32);
+                continue;
+            }
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    self.cells
+                        .entry(IVec2::new(x, y))
+                        .or_default()
+                        .push(index as u32);
+                }
+            }
+        }
+    }
+
+    /// Casters whose bounds overlap the circle, sorted by index.
+    fn query(
+        &mut self,
+        casters: &[ExtractedShadow2dCaster],
+        center: Vec2,
+        radius: f32,
+    ) -> Vec<u32> {
+        let (min, max, count) = self.cell_range(center - radius, center + radius);
+        let overlaps = |caster: &ExtractedShadow2dCaster| {
+            center
+                .clamp(caster.min, caster.max)
+                .distance_squared(center)
+                <= radius * radius
+        };
+        // Lights much larger than the cells are quicker to test against every caster.
+        if count > casters.len() as f32 {
+            return (0..casters.len() as u32)
+                .filter(|index| overlaps(&casters[*index as usize]))
+                .collect();
+        }
+
+        self.query = self.query.wrapping_add(1);
+        if self.query == 0 {
+            self.visited.fill(0);
+            self.query = 1;
+        }
+        let mut found = Vec::new();
+        let cells = (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .chain(&self.large);
+        for &index in cells {
+            let visited = &mut self.visited[index as usize];
+            if *visited != self.query && overlaps(&casters[index as usize]) {
+                found.push(index);
+            }
+            *visited = self.query;
+        }
+        found.sort_unstable();
+        found
+    }
+}
+
+/// World space edges of every [`Shadow2d`], and the edges near each light in the order they are
+/// stored in the [`Shadow2dEdgeTexture`].
+#[derive(Resource)]
+pub struct ExtractedShadows2d {
+    pub edges: Vec<ExtractedShadow2dEdge>,
+    /// Per light, a contiguous range of the caster edges within its radius, at most
+    /// [`SHADOW_MAX_LIGHT_EDGES`].
+    pub light_edges: Vec<ExtractedShadow2dEdge>,
+    /// The most [`light_edges`](Self::light_edges) the tallest [`Shadow2dEdgeTexture`] the
+    /// device supports holds.
+    max_light_edges: usize,
+    /// Whether casters were left out of this frame's shadows to stay within
+    /// `max_light_edges` or [`SHADOW_MAX_LIGHT_EDGES`].
+    pub overflowed: bool,
+    casters: Vec<ExtractedShadow2dCaster>,
+    grid: Shadow2dGrid,
+}
+
+impl FromWorld for ExtractedShadows2d {
+    fn from_world(world: &mut World) -> Self {
+        let max_rows = world
+            .resource::<RenderDevice>()
+            .limits()
+            .max_texture_dimension_2d;
+        Self::new((max_rows * SHADOW_EDGES_PER_ROW) as usize)
+    }
+}
+
+impl ExtractedShadows2d {
+    fn new(max_light_edges: usize) -> Self {
+        Self {
+            edges: Vec::new(),
+            light_edges: Vec::new(),
+            max_light_edges,
+            overflowed: false,
+            casters: Vec::new(),
+            grid: Shadow2dGrid::default(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.edges.clear();
+        self.light_edges.clear();
+        self.overflowed = false;
+        self.casters.clear();
+    }
+
+    fn push_caster(&mut self, shadow: &Shadow2d, transform: &GlobalTransform) {
+        let transmission = shadow.transmittance();
+        let caster = self.casters.len() as u32;
+        let start = self.edges.len();
+        self.edges
+            .extend(
+                shadow_edges(shadow, transform).map(|(start, end)| ExtractedShadow2dEdge {
+                    start,
+                    end,
+                    transmission,
+                    caster,
+                }),
+            );
+        let edges = &self.edges[start..];
+        if edges.is_empty() {
+            return;
+        }
+        let (min, max) = edges.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), edge| {
+                (
+                    min.min(edge.start).min(edge.end),
+                    max.max(edge.start).max(edge.end),
+                )
             },
-            label: Some("light_2d_pipeline".into()),
+        );
+        self.casters.push(ExtractedShadow2dCaster {
+            edges: start..self.edges.len(),
+            closed: shadow.closed,
+            min,
+            max,
+        });
+    }
+
+    /// The edges of each caster, and whether it is closed.
+    pub fn casters(&self) -> impl Iterator<Item = (&[ExtractedShadow2dEdge], bool)> {
+        self.casters
+            .iter()
+            .map(|caster| (&self.edges[caster.edges.clone()], caster.closed))
+    }
+
+    /// Appends the edges overlapping the circle to [`light_edges`](Self::light_edges), nearest
+    /// caster first, returning where they start and how many there are. Casters past
+    /// [`SHADOW_MAX_LIGHT_EDGES`] or that no longer fit in the [`Shadow2dEdgeTexture`] are left
+    /// out.
+    pub fn push_light_edges(&mut self, center: Vec2, radius: f32) -> (u32, u32) {
+        let start = self.light_edges.len();
+        let mut casters = self.grid.query(&self.casters, center, radius);
+        let distance = |caster: &ExtractedShadow2dCaster| {
+            center
+                .clamp(caster.min, caster.max)
+                .distance_squared(center)
+        };
+        casters.sort_by_key(|caster| FloatOrd(distance(&self.casters[*caster as usize])));
+        for caster in casters {
+            let caster_start = self.light_edges.len();
+            // The rays from the light to the pixels it reaches never leave its circle.
+            let edges = self.casters[caster as usize].edges.clone();
+            self.light_edges.extend(
+                self.edges[edges]
+                    .iter()
+                    .filter(|edge| distance_to_segment(center, edge.start, edge.end) <= radius),
+            );
+            if self.light_edges.len() - start > SHADOW_MAX_LIGHT_EDGES as usize
+                || self.light_edges.len() > self.max_light_edges
+            {
+                self.light_edges.truncate(caster_start);
+                self.overflowed = true;
+            }
         }
+        (start as u32, (self.light_edges.len() - start) as u32)
     }
 }
 
 pub fn extract_shadows(
-    mut commands: Commands,
-    mut previous_len: Local<usize>,
-    light_query: Extract<Query<(Entity, &ComputedVisibility, &Shadow2d, &GlobalTransform)>>,
+    mut shadows: ResMut<ExtractedShadows2d>,
+    shadow_query: Extract<Query<(&Shadow2d, &GlobalTransform), Without<MergedShadow2d>>>,
 ) {
-    let mut values = Vec::with_capacity(*previous_len);
-    for (entity, visibility, shadow, transform) in light_query.iter() {
-        if !visibility.is_visible() {
-            continue;
+    let shadows = &mut *shadows;
+    shadows.clear();
+    // Off-screen casters still shadow on-screen lights, so visibility is not checked.
+    for (shadow, transform) in shadow_query.iter() {
+        shadows.push_caster(shadow, transform);
+    }
+    shadows.grid.build(&shadows.casters);
+}
+
+/// Caster edges read by `shadow.wgsl` with `textureLoad`, two `Rgba32Float` texels per edge:
+/// `(start.x, start.y, end.x, end.y)` and `(transmission.rgb, caster)`. Storing a color rather
+/// than a stencil bit lets translucent casters filter the light.
+/// Data textures rather than storage buffers keep WebGL2 working.
+#[derive(Resource)]
+pub struct Shadow2dEdgeTexture {
+    pub texture: Texture,
+    pub texture_view: TextureView,
+    rows: u32,
+}
+
+impl Shadow2dEdgeTexture {
+    fn new(render_device: &RenderDevice, rows: u32) -> Self {
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: Some("light2d_shadow_edge_texture"),
+            size: Extent3d {
+                width: SHADOW_EDGE_TEXTURE_WIDTH,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+        Self {
+            texture,
+            texture_view,
+            rows,
         }
-        values.push((entity, (*transform, shadow.clone())));
     }
+}
+
+impl FromWorld for Shadow2dEdgeTexture {
+    fn from_world(world: &mut World) -> Self {
+        Self::new(world.resource::<RenderDevice>(), 1)
+    }
+}
 
-    *previous_len = values.len();
-    commands.insert_or_spawn_batch(values);
+pub fn prepare_shadow_edges(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    shadows: Res<ExtractedShadows2d>,
+    mut edge_texture: ResMut<Shadow2dEdgeTexture>,
+    mut warned: Local<bool>,
+) {
+    if shadows.overflowed && !*warned {
+        warn!("Too many shadow caster edges near lights, the furthest casters don't cast shadows");
+        *warned = true;
+    }
+    if shadows.light_edges.is_empty() {
+        return;
+    }
+    let rows = (shadows.light_edges.len() as u32 + SHADOW_EDGES_PER_ROW - 1) / SHADOW_EDGES_PER_ROW;
+    if rows > edge_texture.rows {
+        // Grow by powers of two so a slowly growing level doesn't reallocate every frame, but
+        // never past what the device supports, `push_light_edges` keeps within that.
+        let max_rows = render_device.limits().max_texture_dimension_2d;
+        *edge_texture =
+            Shadow2dEdgeTexture::new(&render_device, rows.next_power_of_two().min(max_rows));
+    }
+
+    let mut data = Vec::with_capacity((rows * SHADOW_EDGE_TEXTURE_WIDTH * 4) as usize);
+    for edge in &shadows.light_edges {
+        data.extend_from_slice(&[edge.start.x, edge.start.y, edge.end.x, edge.end.y]);
+        data.extend_from_slice(&[
+            edge.transmission.x,
+            edge.transmission.y,
+            edge.transmission.z,
+            edge.caster as f32,
+        ]);
+    }
+    data.resize((rows * SHADOW_EDGE_TEXTURE_WIDTH * 4) as usize, 0.0);
+
+    render_queue.write_texture(
+        ImageCopyTexture {
+            texture: &edge_texture.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        bytemuck::cast_slice(&data),
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(SHADOW_EDGE_TEXTURE_WIDTH * 16),
+            rows_per_image: None,
+        },
+        Extent3d {
+            width: SHADOW_EDGE_TEXTURE_WIDTH,
+            height: rows,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(shadows: &mut ExtractedShadows2d, center: Vec2, size: f32) {
+        let shadow = Shadow2d {
+            closed: true,
+            points: vec![
+                Vec2::new(-0.5, -0.5),
+                Vec2::new(0.5, -0.5),
+                Vec2::new(0.5, 0.5),
+                Vec2::new(-0.5, 0.5),
+            ],
+            ..default()
+        };
+        let transform =
+            Transform::from_translation(center.extend(0.0)).with_scale(Vec3::new(size, size, 1.0));
+        shadows.push_caster(&shadow, &transform.into());
+    }
+
+    #[test]
+    fn only_edges_within_the_radius_are_tested() {
+        let mut shadows = ExtractedShadows2d::new(usize::MAX);
+        // Its left edge is 5 from the light, its right edge 25.
+        square(&mut shadows, Vec2::new(15.0, 0.0), 20.0);
+        square(&mut shadows, Vec2::new(100.0, 0.0), 20.0);
+        shadows.grid.build(&shadows.casters);
+
+        let (start, count) = shadows.push_light_edges(Vec2::ZERO, 20.0);
+        assert_eq!((start, count), (0, 3));
+        assert!(shadows
+            .light_edges
+            .iter()
+            .all(|edge| edge.start.x < 25.0 || edge.end.x < 25.0));
+        assert!(!shadows.overflowed);
+    }
+
+    #[test]
+    fn crowded_lights_keep_the_nearest_casters() {
+        let mut shadows = ExtractedShadows2d::new(usize::MAX);
+        let count = SHADOW_MAX_LIGHT_EDGES as usize / 4 + 10;
+        for i in (0..count).rev() {
+            square(&mut shadows, Vec2::new(10.0 + 3.0 * i as f32, 0.0), 2.0);
+        }
+        shadows.grid.build(&shadows.casters);
+
+        let (_, edge_count) = shadows.push_light_edges(Vec2::ZERO, 10_000.0);
+        assert_eq!(edge_count, SHADOW_MAX_LIGHT_EDGES);
+        assert!(shadows.overflowed);
+        let furthest = shadows
+            .light_edges
+            .iter()
+            .map(|edge| edge.start.x.max(edge.end.x))
+            .fold(0.0, f32::max);
+        assert!(furthest < 10.0 + 3.0 * (SHADOW_MAX_LIGHT_EDGES / 4) as f32);
+    }
+
+    /// A dungeon of ~300 lights and 10k caster edges, as in the levels the grid was built for.
+    /// Every light would otherwise test all 10k edges per pixel.
+    #[test]
+    fn dungeon_lights_test_a_fraction_of_the_edges() {
+        let mut shadows = ExtractedShadows2d::new(usize::MAX);
+        for y in 0..50 {
+            for x in 0..50 {
+                square(&mut shadows, Vec2::new(x as f32, y as f32) * 40.0, 16.0);
+            }
+        }
+        shadows.grid.build(&shadows.casters);
+        assert_eq!(shadows.edges.len(), 10_000);
+
+        let mut seed = 1u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let lights = 300;
+        let mut most = 0;
+        for _ in 0..lights {
+            let center = Vec2::new(random(), random()) * 2000.0;
+            let (_, count) = shadows.push_light_edges(center, 100.0);
+            most = most.max(count);
+        }
+
+        let naive = lights * shadows.edges.len();
+        assert!(shadows.light_edges.len() * 50 < naive);
+        assert!(most < SHADOW_MAX_LIGHT_EDGES);
+        assert!(!shadows.overflowed);
+    }
 }
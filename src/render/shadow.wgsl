@@ -1,35 +1,115 @@
-struct View {
-    view_proj: mat4x4<f32>,
-    light_position: vec3<f32>,
-};
+#define_import_path light_2d::shadow
 
-@group(0) @binding(0)
-var<uniform> view: View;
+// Two texels per caster edge, (start, end) and (transmission, caster), see shadow.rs.
+@group(0) @binding(1)
+var shadow_edge_texture: texture_2d<f32>;
 
-struct VertexOutput {
-    @location(0) uv: vec2<f32>,
-    @builtin(position) position: vec4<f32>,
+#ifdef SHADOW_SDF
+struct Sdf {
+    jump: i32,
+    softness: f32,
 };
+// Nearest occluded texel of each texel of the view, see sdf_flood.wgsl.
+@group(0) @binding(2)
+var sdf_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var<uniform> sdf: Sdf;
+
+#import light_2d::sdf
+#endif
+
+let SHADOW_EDGE_TEXTURE_WIDTH: u32 = 1024u;
+// Most edges one light tests per pixel, see SHADOW_MAX_LIGHT_EDGES in shadow.rs.
+let SHADOW_MAX_LIGHT_EDGES: u32 = 512u;
+let SDF_MAX_STEPS: u32 = 64u;
 
-@vertex
-fn vertex(
-    @location(0) vertex_position: vec3<f32>,
-){
-    // Transform the position.
-    // If you are batching the shadow data, you can pre-apply the transform instead.
-    let pos_xy = (view.view_proj * vec4<f32>(vertex_position.xy, 0.0, 1.0)).xy;
-
-    // When a_vertex.z is 0, the vertex is on the near side of the shadow and is output as is.
-    // When a_vertex.z is 1, the vertex is on the far side of the shadow as is projected to inifity.
-    let pos_xyzw = vec4(pos_xy - vertex_position.z * view.light_position, 0.0, 1.0 - vertex_position.z);
-
-    var out: VertexOutput;
-    out.uv = vertex_uv;
-    out.position = pos_xyzw;
+struct Shadow {
+    // Light let through by every caster in between, per channel, scaled by the light's shadow
+    // intensity.
+    transmission: vec3<f32>,
+}
+
+#ifdef SHADOW_SDF
+fn world_to_texel(position: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    let clip = view.view_proj * vec4<f32>(position, 0.0, 1.0);
+    let ndc = clip.xy / clip.w;
+    return vec2<f32>(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * size;
+}
+
+// Sphere traces from the pixel towards the light. The closest the ray passes to an occluder,
+// relative to how far it has come, darkens the penumbra, so shadows are hard where the caster
+// touches the ground and soften away from it.
+fn sample_shadow(position: vec2<f32>) -> Shadow {
+    let size = vec2<f32>(textureDimensions(sdf_texture));
+    let start = world_to_texel(position, size);
+    let ray = world_to_texel(light.light_position.xy, size) - start;
+    let ray_length = length(ray);
+    var visibility = 1.0;
+    if (ray_length > 0.0) {
+        let direction = ray / ray_length;
+        var t = 0.0;
+        for (var i = 0u; i < SDF_MAX_STEPS && t < ray_length; i = i + 1u) {
+            let d = sdf_distance(start + direction * t, size);
+            if (d < 1.0) {
+                visibility = 0.0;
+                break;
+            }
+            visibility = min(visibility, d / max(sdf.softness * t, 0.0001));
+            t = t + max(d, 1.0);
+        }
+    }
+    var out: Shadow;
+    out.transmission = vec3<f32>(1.0 - light.shadow_intensity * (1.0 - visibility));
     return out;
 }
+#else
+fn load_edge_texel(index: u32) -> vec4<f32> {
+    return textureLoad(
+        shadow_edge_texture,
+        vec2<i32>(i32(index % SHADOW_EDGE_TEXTURE_WIDTH), i32(index / SHADOW_EDGE_TEXTURE_WIDTH)),
+        0
+    );
+}
+
+fn sample_shadow(position: vec2<f32>) -> Shadow {
+    let origin = light.light_position.xy;
+    let ray = position - origin;
+    var transmission = vec3<f32>(1.0);
+    // Edges of a caster are stored together, a ray entering and leaving it counts once.
+    var caster = -1.0;
+    var caster_transmission = vec3<f32>(1.0);
+    var caster_crossed = false;
+    let edge_count = min(light.shadow_edge_count, SHADOW_MAX_LIGHT_EDGES);
+    for (var i = 0u; i < edge_count; i = i + 1u) {
+        let index = light.shadow_edge_start + i;
+        let edge = load_edge_texel(2u * index);
+        let data = load_edge_texel(2u * index + 1u);
+        if (data.w != caster) {
+            if (caster_crossed) {
+                transmission *= caster_transmission;
+            }
+            caster = data.w;
+            caster_transmission = data.rgb;
+            caster_crossed = false;
+        }
+        let edge_vector = edge.zw - edge.xy;
+        let denominator = ray.x * edge_vector.y - ray.y * edge_vector.x;
+        if (denominator == 0.0) {
+            continue;
+        }
+        let to_edge = edge.xy - origin;
+        let t = (to_edge.x * edge_vector.y - to_edge.y * edge_vector.x) / denominator;
+        let u = (to_edge.x * ray.y - to_edge.y * ray.x) / denominator;
+        if (t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0) {
+            caster_crossed = true;
+        }
+    }
+    if (caster_crossed) {
+        transmission *= caster_transmission;
+    }
 
-@fragment
-fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(0.0);
-}
\ No newline at end of file
+    var out: Shadow;
+    out.transmission = vec3<f32>(1.0) - light.shadow_intensity * (vec3<f32>(1.0) - transmission);
+    return out;
+}
+#endif
@@ -0,0 +1,64 @@
+/// The bits of the half float closest to `value`, rounded to nearest even. Values too large for
+/// a half float become infinity.
+pub(crate) fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // Infinity or NaN, keeping NaNs quiet.
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal or zero.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let half = 1 << (shift - 1);
+        let rest = mantissa & ((1 << shift) - 1);
+        let mut result = (mantissa >> shift) as u16;
+        if rest > half || (rest == half && result & 1 == 1) {
+            result += 1;
+        }
+        return sign | result;
+    }
+    let rest = mantissa & 0x1fff;
+    let mut result = ((exponent as u32) << 10 | mantissa >> 13) as u16;
+    // A carry out of the mantissa correctly bumps the exponent.
+    if rest > 0x1000 || (rest == 0x1000 && result & 1 == 1) {
+        result += 1;
+    }
+    sign | result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(f32_to_f16_bits(0.0), 0x0000);
+        assert_eq!(f32_to_f16_bits(-0.0), 0x8000);
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+        assert_eq!(f32_to_f16_bits(0.5), 0x3800);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16_bits(1e6), 0x7c00);
+        assert_eq!(f32_to_f16_bits(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16_bits(f32::NAN) & 0x7c00, 0x7c00);
+        // The smallest subnormal, and half of it rounding to even.
+        assert_eq!(f32_to_f16_bits(5.960_464_5e-8), 0x0001);
+        assert_eq!(f32_to_f16_bits(2.980_232_2e-8), 0x0000);
+        // 1 + 2^-11 is halfway between 1 and the next half float.
+        assert_eq!(f32_to_f16_bits(1.0 + 1.0 / 2048.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(1.0 + 3.0 / 2048.0), 0x3c02);
+    }
+}
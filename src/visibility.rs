@@ -0,0 +1,370 @@
+use std::f32::consts::TAU;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{affine_2d, MergedShadow2d, Shadow2d};
+
+/// A world-space edge of a [`Shadow2d`] caster.
+#[derive(Debug, Clone, Copy)]
+pub struct Shadow2dEdge {
+    pub entity: Entity,
+    pub start: Vec2,
+    pub end: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit2d {
+    /// The caster that was hit.
+    pub entity: Entity,
+    pub point: Vec2,
+    /// Unit normal of the hit edge, facing the ray origin.
+    pub normal: Vec2,
+    pub distance: f32,
+}
+
+/// World-space edges of a shadow caster.
+pub fn shadow_edges<'a>(
+    shadow: &'a Shadow2d,
+    transform: &'a GlobalTransform,
+) -> impl Iterator<Item = (Vec2, Vec2)> + 'a {
+    let affine = affine_2d(transform);
+    let count = shadow.points.len();
+    let edge_count = match count {
+        0 | 1 => 0,
+        _ if shadow.closed => count,
+        _ => count - 1,
+    };
+    (0..edge_count).map(move |i| {
+        (
+            affine.transform_point2(shadow.points[i]),
+            affine.transform_point2(shadow.points[(i + 1) % count]),
+        )
+    })
+}
+
+fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+/// Parameters `(t, u)` at which `a0 + t * (a1 - a0)` meets `b0 + u * (b1 - b0)`, or `None`
+/// for parallel lines.
+fn line_intersection(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> Option<(f32, f32)> {
+    let r = a1 - a0;
+    let s = b1 - b0;
+    let denominator = cross(r, s);
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = cross(b0 - a0, s) / denominator;
+    let u = cross(b0 - a0, r) / denominator;
+    Some((t, u))
+}
+
+/// Whether the segments `a0..a1` and `b0..b1` cross.
+pub fn segments_intersect(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> bool {
+    segment_intersection(a0, a1, b0, b1).is_some()
+}
+
+/// The point where the segments `a0..a1` and `b0..b1` cross.
+pub fn segment_intersection(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> Option<Vec2> {
+    let (t, u) = line_intersection(a0, a1, b0, b1)?;
+    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+        Some(a0 + (a1 - a0) * t)
+    } else {
+        None
+    }
+}
+
+/// Nearest edge hit by the segment `start..end`.
+pub fn intersect_edges(edges: &[Shadow2dEdge], start: Vec2, end: Vec2) -> Option<RayHit2d> {
+    let length = start.distance(end);
+    let mut nearest: Option<RayHit2d> = None;
+    for edge in edges {
+        if let Some((t, u)) = line_intersection(start, end, edge.start, edge.end) {
+            if !(0.0..=1.0).contains(&t) || !(0.0..=1.0).contains(&u) {
+                continue;
+            }
+            let distance = t * length;
+            if nearest.map_or(true, |hit| distance < hit.distance) {
+                let tangent = (edge.end - edge.start).normalize_or_zero();
+                let mut normal = tangent.perp();
+                if normal.dot(start - edge.start) < 0.0 {
+                    normal = -normal;
+                }
+                nearest = Some(RayHit2d {
+                    entity: edge.entity,
+                    point: start + (end - start) * t,
+                    normal,
+                    distance,
+                });
+            }
+        }
+    }
+    nearest
+}
+
+/// Nearest edge hit by a ray, up to `max_distance`.
+pub fn raycast_edges(
+    edges: &[Shadow2dEdge],
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+) -> Option<RayHit2d> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec2::ZERO {
+        return None;
+    }
+    intersect_edges(edges, origin, origin + direction * max_distance)
+}
+
+/// The region visible from `origin` within `radius`, as a polygon sorted by angle
+/// counter-clockwise around `origin`.
+///
+/// Rays are cast towards every edge endpoint, slightly to either side of it so they can slip
+/// past corners, towards every point where an edge leaves the circle, and at `segments` even
+/// angles so the unobstructed parts follow the circle.
+pub fn visibility_polygon(
+    edges: &[Shadow2dEdge],
+    origin: Vec2,
+    radius: f32,
+    segments: usize,
+) -> Vec<Vec2> {
+    const EPSILON: f32 = 0.0001;
+
+    let edges = edges
+        .iter()
+        .filter(|edge| distance_to_segment(origin, edge.start, edge.end) < radius)
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mut angles = (0..segments)
+        .map(|i| i as f32 / segments as f32 * TAU)
+        .collect::<Vec<_>>();
+    for edge in &edges {
+        for point in [edge.start, edge.end] {
+            let offset = point - origin;
+            if offset.length_squared() > radius * radius {
+                continue;
+            }
+            let angle = offset.y.atan2(offset.x);
+            angles.extend([angle - EPSILON, angle, angle + EPSILON]);
+        }
+        for point in segment_circle_intersections(edge.start, edge.end, origin, radius) {
+            let offset = point - origin;
+            angles.push(offset.y.atan2(offset.x));
+        }
+    }
+    for angle in &mut angles {
+        *angle = angle.rem_euclid(TAU);
+    }
+    angles.sort_by(|a, b| a.total_cmp(b));
+    angles.dedup();
+
+    angles
+        .into_iter()
+        .map(|angle| {
+            let direction = Vec2::new(angle.cos(), angle.sin());
+            match raycast_edges(&edges, origin, direction, radius) {
+                Some(hit) => hit.point,
+                None => origin + direction * radius,
+            }
+        })
+        .collect()
+}
+
+/// Fraction of the light along `start..end`, per linear channel, that gets through `shadow`:
+/// [`Shadow2d::transmittance`] if the segment crosses it, `1.0` otherwise. Entering and leaving
+/// a caster only counts once.
+pub fn caster_transmission(
+    shadow: &Shadow2d,
+    transform: &GlobalTransform,
+    start: Vec2,
+    end: Vec2,
+) -> Vec3 {
+    if shadow_edges(shadow, transform).any(|(a, b)| segments_intersect(start, end, a, b)) {
+        shadow.transmittance()
+    } else {
+        Vec3::ONE
+    }
+}
+
+/// The points where the segment `start..end` crosses the circle around `center`.
+fn segment_circle_intersections(
+    start: Vec2,
+    end: Vec2,
+    center: Vec2,
+    radius: f32,
+) -> impl Iterator<Item = Vec2> {
+    let segment = end - start;
+    let offset = start - center;
+    let a = segment.length_squared();
+    let b = 2.0 * offset.dot(segment);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    let roots = if a == 0.0 || discriminant < 0.0 {
+        [None, None]
+    } else {
+        let root = discriminant.sqrt();
+        [Some((-b - root) / (2.0 * a)), Some((-b + root) / (2.0 * a))]
+    };
+    roots
+        .into_iter()
+        .flatten()
+        .filter(|t| (0.0..=1.0).contains(t))
+        .map(move |t| start + segment * t)
+}
+
+pub fn distance_to_segment(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let segment = end - start;
+    let length_squared = segment.length_squared();
+    if length_squared == 0.0 {
+        return point.distance(start);
+    }
+    let t = ((point - start).dot(segment) / length_squared).clamp(0.0, 1.0);
+    point.distance(start + segment * t)
+}
+
+/// Line-of-sight queries against every [`Shadow2d`] caster, using the same geometry the
+/// shadows are built from.
+#[derive(SystemParam)]
+pub struct Shadow2dCasters<'w, 's> {
+    casters: Query<
+        'w,
+        's,
+        (Entity, &'static Shadow2d, &'static GlobalTransform),
+        Without<MergedShadow2d>,
+    >,
+}
+
+impl<'w, 's> Shadow2dCasters<'w, 's> {
+    pub fn edges(&self) -> Vec<Shadow2dEdge> {
+        self.casters
+            .iter()
+            .flat_map(|(entity, shadow, transform)| {
+                shadow_edges(shadow, transform).map(move |(start, end)| Shadow2dEdge {
+                    entity,
+                    start,
+                    end,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether any caster blocks the segment `start..end`.
+    pub fn is_occluded(&self, start: Vec2, end: Vec2) -> bool {
+        self.casters.iter().any(|(_, shadow, transform)| {
+            shadow_edges(shadow, transform).any(|(a, b)| segments_intersect(start, end, a, b))
+        })
+    }
+
+    /// Fraction of the light along `start..end`, per linear channel, that gets through every
+    /// caster it crosses.
+    pub fn transmission(&self, start: Vec2, end: Vec2) -> Vec3 {
+        self.casters
+            .iter()
+            .map(|(_, shadow, transform)| caster_transmission(shadow, transform, start, end))
+            .fold(Vec3::ONE, |transmission, caster| transmission * caster)
+    }
+
+    pub fn intersect_segment(&self, start: Vec2, end: Vec2) -> Option<RayHit2d> {
+        intersect_edges(&self.edges(), start, end)
+    }
+
+    pub fn raycast(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<RayHit2d> {
+        raycast_edges(&self.edges(), origin, direction, max_distance)
+    }
+
+    pub fn visibility_polygon(&self, origin: Vec2, radius: f32) -> Vec<Vec2> {
+        visibility_polygon(&self.edges(), origin, radius, 64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(start: Vec2, end: Vec2) -> Shadow2dEdge {
+        Shadow2dEdge {
+            entity: Entity::from_raw(0),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn unobstructed_polygon_follows_the_circle() {
+        let polygon = visibility_polygon(&[], Vec2::new(1.0, 2.0), 10.0, 16);
+        assert_eq!(polygon.len(), 16);
+        for point in polygon {
+            assert!((point.distance(Vec2::new(1.0, 2.0)) - 10.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn polygon_is_sorted_by_angle() {
+        let edges = [edge(Vec2::new(2.0, -1.0), Vec2::new(2.0, 1.0))];
+        let polygon = visibility_polygon(&edges, Vec2::ZERO, 10.0, 16);
+        let angles = polygon
+            .iter()
+            .map(|point| point.y.atan2(point.x).rem_euclid(TAU))
+            .collect::<Vec<_>>();
+        assert!(angles.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn walls_clip_the_polygon() {
+        let edges = [edge(Vec2::new(2.0, -1.0), Vec2::new(2.0, 1.0))];
+        let polygon = visibility_polygon(&edges, Vec2::ZERO, 10.0, 16);
+        // The ray straight at the wall stops on it.
+        assert!(polygon.contains(&Vec2::new(2.0, 0.0)));
+        // Both corners of the wall are vertices, with the rays slipping past them beyond.
+        for corner in [Vec2::new(2.0, -1.0), Vec2::new(2.0, 1.0)] {
+            assert!(polygon.iter().any(|point| point.distance(corner) < 1e-3));
+        }
+        assert!(polygon
+            .iter()
+            .any(|point| (point.length() - 10.0).abs() < 1e-3 && point.x > 2.0 && point.y > 0.0));
+        for point in &polygon {
+            assert!(point.length() <= 10.0 + 1e-3);
+            if point.y.abs() < 0.99 && point.x > 0.0 {
+                assert!(point.x <= 2.0 + 1e-3, "{point:?} is behind the wall");
+            }
+        }
+    }
+
+    #[test]
+    fn edges_leaving_the_circle_add_their_crossings() {
+        // A wall that starts inside the circle and leaves it at (6, 8).
+        let edges = [edge(Vec2::new(3.0, 8.0), Vec2::new(12.0, 8.0))];
+        let polygon = visibility_polygon(&edges, Vec2::ZERO, 10.0, 4);
+        assert!(polygon
+            .iter()
+            .any(|point| point.distance(Vec2::new(6.0, 8.0)) < 1e-3));
+    }
+
+    #[test]
+    fn segment_circle_crossings() {
+        let crossings = segment_circle_intersections(
+            Vec2::new(-20.0, 0.0),
+            Vec2::new(20.0, 0.0),
+            Vec2::ZERO,
+            5.0,
+        )
+        .collect::<Vec<_>>();
+        assert_eq!(crossings, vec![Vec2::new(-5.0, 0.0), Vec2::new(5.0, 0.0)]);
+        assert_eq!(
+            segment_circle_intersections(Vec2::ZERO, Vec2::new(1.0, 0.0), Vec2::ZERO, 5.0).count(),
+            0
+        );
+        assert_eq!(
+            segment_circle_intersections(
+                Vec2::new(-20.0, 9.0),
+                Vec2::new(20.0, 9.0),
+                Vec2::ZERO,
+                5.0
+            )
+            .count(),
+            0
+        );
+    }
+}